@@ -13,6 +13,43 @@ export const CANISTER_IDS = {
 // Local development URL
 const LOCAL_REPLICA_URL = 'http://127.0.0.1:4943';
 
+// Error returned by every canister endpoint; branch on the variant key
+export type CanisterError =
+  | { NotFound: { entity: string } }
+  | { AlreadyExists: { entity: string } }
+  | { Unauthorized: { reason: string } }
+  | { InsufficientTokens: { requested: bigint; available: bigint } }
+  | { InvalidInput: { field: string; reason: string } }
+  | { InvalidState: { reason: string } }
+  | { DeadlinePassed: null }
+  | { AlreadyVoted: null }
+  | { CanisterCallFailed: { method: string; reason: string } }
+  | { PaymentFailed: { reason: string } }
+  | { Internal: { reason: string } };
+
+export type CanisterResult<T> = { Ok: T } | { Err: CanisterError };
+
+// Paging for list queries; pass a page's next_cursor as start_after to continue
+export type SortOrder = { Ascending: null } | { Descending: null };
+
+export interface PageRequest<K> {
+  start_after: [] | [K];
+  limit: [] | [number];
+  order: [] | [SortOrder];
+}
+
+export interface Page<T, K> {
+  items: T[];
+  next_cursor: [] | [K];
+  total: bigint;
+}
+
+export const firstPage = <K>(limit?: number): PageRequest<K> => ({
+  start_after: [],
+  limit: limit === undefined ? [] : [limit],
+  order: [],
+});
+
 // Property Canister Interface
 export interface Property {
   id: bigint;
@@ -168,7 +205,7 @@ class ICPClient {
   }
 
   // Property Canister Methods
-  async getProperties(): Promise<Property[]> {
+  async getProperties(page: PageRequest<bigint> = firstPage()): Promise<Page<Property, bigint>> {
     const identity = await this.getIdentity();
     const agent = new HttpAgent({ host: this.isLocal ? LOCAL_REPLICA_URL : 'https://ic0.app', identity });
     
@@ -181,10 +218,10 @@ class ICPClient {
       canisterId: CANISTER_IDS.property_canister,
     });
 
-    return await actor.get_properties() as Property[];
+    return await actor.get_properties(page) as Page<Property, bigint>;
   }
 
-  async createProperty(req: CreatePropertyRequest): Promise<{ Ok?: Property; Err?: string }> {
+  async createProperty(req: CreatePropertyRequest): Promise<CanisterResult<Property>> {
     const identity = await this.getIdentity();
     const agent = new HttpAgent({ host: this.isLocal ? LOCAL_REPLICA_URL : 'https://ic0.app', identity });
     
@@ -197,11 +234,14 @@ class ICPClient {
       canisterId: CANISTER_IDS.property_canister,
     });
 
-    return await actor.create_property(req);
+    return await actor.create_property(req) as CanisterResult<Property>;
   }
 
   // Investment Canister Methods
-  async getUserInvestments(userId: Principal): Promise<Investment[]> {
+  async getUserInvestments(
+    userId: Principal,
+    page: PageRequest<bigint> = firstPage(),
+  ): Promise<Page<Investment, bigint>> {
     const identity = await this.getIdentity();
     const agent = new HttpAgent({ host: this.isLocal ? LOCAL_REPLICA_URL : 'https://ic0.app', identity });
     
@@ -214,10 +254,10 @@ class ICPClient {
       canisterId: CANISTER_IDS.investment_canister,
     });
 
-    return await actor.get_user_investments(userId) as Investment[];
+    return await actor.get_user_investments(userId, page) as Page<Investment, bigint>;
   }
 
-  async createInvestment(req: CreateInvestmentRequest): Promise<{ Ok?: Investment; Err?: string }> {
+  async createInvestment(req: CreateInvestmentRequest): Promise<CanisterResult<Investment>> {
     const identity = await this.getIdentity();
     const agent = new HttpAgent({ host: this.isLocal ? LOCAL_REPLICA_URL : 'https://ic0.app', identity });
     
@@ -230,11 +270,11 @@ class ICPClient {
       canisterId: CANISTER_IDS.investment_canister,
     });
 
-    return await actor.create_investment(req);
+    return await actor.create_investment(req) as CanisterResult<Investment>;
   }
 
   // Governance Canister Methods
-  async getProposals(): Promise<Proposal[]> {
+  async getProposals(page: PageRequest<bigint> = firstPage()): Promise<Page<Proposal, bigint>> {
     const identity = await this.getIdentity();
     const agent = new HttpAgent({ host: this.isLocal ? LOCAL_REPLICA_URL : 'https://ic0.app', identity });
     
@@ -247,10 +287,10 @@ class ICPClient {
       canisterId: CANISTER_IDS.governance_canister,
     });
 
-    return await actor.get_proposals() as Proposal[];
+    return await actor.get_proposals(page) as Page<Proposal, bigint>;
   }
 
-  async createProposal(req: CreateProposalRequest): Promise<{ Ok?: Proposal; Err?: string }> {
+  async createProposal(req: CreateProposalRequest): Promise<CanisterResult<Proposal>> {
     const identity = await this.getIdentity();
     const agent = new HttpAgent({ host: this.isLocal ? LOCAL_REPLICA_URL : 'https://ic0.app', identity });
     
@@ -263,7 +303,7 @@ class ICPClient {
       canisterId: CANISTER_IDS.governance_canister,
     });
 
-    return await actor.create_proposal(req);
+    return await actor.create_proposal(req) as CanisterResult<Proposal>;
   }
 
   // User Canister Methods
@@ -284,7 +324,7 @@ class ICPClient {
     return result.length > 0 ? result[0] as User : null;
   }
 
-  async createUser(req: CreateUserRequest): Promise<{ Ok?: User; Err?: string }> {
+  async createUser(req: CreateUserRequest): Promise<CanisterResult<User>> {
     const identity = await this.getIdentity();
     const agent = new HttpAgent({ host: this.isLocal ? LOCAL_REPLICA_URL : 'https://ic0.app', identity });
     
@@ -297,35 +337,75 @@ class ICPClient {
       canisterId: CANISTER_IDS.user_canister,
     });
 
-    return await actor.create_user(req);
+    return await actor.create_user(req) as CanisterResult<User>;
   }
 }
 
 // IDL Factory (Interface Definition Language) - Simplified version
 // In a real application, these would be generated from your Rust canisters
+const errorIdl = (IDL: any) => IDL.Variant({
+  'NotFound': IDL.Record({ 'entity': IDL.Text }),
+  'AlreadyExists': IDL.Record({ 'entity': IDL.Text }),
+  'Unauthorized': IDL.Record({ 'reason': IDL.Text }),
+  'InsufficientTokens': IDL.Record({ 'requested': IDL.Nat64, 'available': IDL.Nat64 }),
+  'InvalidInput': IDL.Record({ 'field': IDL.Text, 'reason': IDL.Text }),
+  'InvalidState': IDL.Record({ 'reason': IDL.Text }),
+  'DeadlinePassed': IDL.Null,
+  'AlreadyVoted': IDL.Null,
+  'CanisterCallFailed': IDL.Record({ 'method': IDL.Text, 'reason': IDL.Text }),
+  'PaymentFailed': IDL.Record({ 'reason': IDL.Text }),
+  'Internal': IDL.Record({ 'reason': IDL.Text }),
+});
+
+const resultIdl = (IDL: any, ok: any) => IDL.Variant({ 'Ok': ok, 'Err': errorIdl(IDL) });
+
+const pageRequestIdl = (IDL: any, key: any) => IDL.Record({
+  'start_after': IDL.Opt(key),
+  'limit': IDL.Opt(IDL.Nat32),
+  'order': IDL.Opt(IDL.Variant({ 'Ascending': IDL.Null, 'Descending': IDL.Null })),
+});
+
+const pageIdl = (IDL: any, item: any, key: any) => IDL.Record({
+  'items': IDL.Vec(item),
+  'next_cursor': IDL.Opt(key),
+  'total': IDL.Nat64,
+});
+
 const idlFactory = {
   property: ({ IDL }: any) => {
     return IDL.Service({
-      'get_properties': IDL.Func([], [IDL.Vec(IDL.Record({}))], ['query']),
-      'create_property': IDL.Func([IDL.Record({})], [IDL.Variant({ 'Ok': IDL.Record({}), 'Err': IDL.Text })], []),
+      'get_properties': IDL.Func(
+        [pageRequestIdl(IDL, IDL.Nat64)],
+        [pageIdl(IDL, IDL.Record({}), IDL.Nat64)],
+        ['query'],
+      ),
+      'create_property': IDL.Func([IDL.Record({})], [resultIdl(IDL, IDL.Record({}))], []),
     });
   },
   investment: ({ IDL }: any) => {
     return IDL.Service({
-      'get_user_investments': IDL.Func([IDL.Principal], [IDL.Vec(IDL.Record({}))], ['query']),
-      'create_investment': IDL.Func([IDL.Record({})], [IDL.Variant({ 'Ok': IDL.Record({}), 'Err': IDL.Text })], []),
+      'get_user_investments': IDL.Func(
+        [IDL.Principal, pageRequestIdl(IDL, IDL.Nat64)],
+        [pageIdl(IDL, IDL.Record({}), IDL.Nat64)],
+        ['query'],
+      ),
+      'create_investment': IDL.Func([IDL.Record({})], [resultIdl(IDL, IDL.Record({}))], []),
     });
   },
   governance: ({ IDL }: any) => {
     return IDL.Service({
-      'get_proposals': IDL.Func([], [IDL.Vec(IDL.Record({}))], ['query']),
-      'create_proposal': IDL.Func([IDL.Record({})], [IDL.Variant({ 'Ok': IDL.Record({}), 'Err': IDL.Text })], []),
+      'get_proposals': IDL.Func(
+        [pageRequestIdl(IDL, IDL.Nat64)],
+        [pageIdl(IDL, IDL.Record({}), IDL.Nat64)],
+        ['query'],
+      ),
+      'create_proposal': IDL.Func([IDL.Record({})], [resultIdl(IDL, IDL.Record({}))], []),
     });
   },
   user: ({ IDL }: any) => {
     return IDL.Service({
       'get_current_user': IDL.Func([], [IDL.Opt(IDL.Record({}))], ['query']),
-      'create_user': IDL.Func([IDL.Record({})], [IDL.Variant({ 'Ok': IDL.Record({}), 'Err': IDL.Text })], []),
+      'create_user': IDL.Func([IDL.Record({})], [resultIdl(IDL, IDL.Record({}))], []),
     });
   },
 };
@@ -9,13 +9,40 @@ echo "🚀 Building RealtyChain ICP Canisters..."
 echo "📡 Starting local ICP replica..."
 dfx start --clean --background
 
+# Reserve canister IDs so canisters can be initialised with each other's IDs
+dfx canister create --all
+
 # Build all canisters
 echo "🔨 Building Rust canisters..."
 dfx build
 
 # Deploy all canisters
 echo "🌍 Deploying canisters to local replica..."
-dfx deploy
+PROPERTY_CANISTER_ID=$(dfx canister id property_canister)
+INVESTMENT_CANISTER_ID=$(dfx canister id investment_canister)
+GOVERNANCE_CANISTER_ID=$(dfx canister id governance_canister)
+USER_CANISTER_ID=$(dfx canister id user_canister)
+# LEDGER_CANISTER_ID names the ICRC-2 ledger investments and proposal deposits
+# are paid on; LEDGER_UNITS_PER_CENT converts USD cents into its base units.
+# Without a ledger, investments are refused and proposals take no deposit.
+if [ -n "$LEDGER_CANISTER_ID" ]; then
+  LEDGER_ARG="opt principal \"$LEDGER_CANISTER_ID\""
+  PAYMENT_LEDGER_ARG="opt record { canister = principal \"$LEDGER_CANISTER_ID\"; units_per_cent = ${LEDGER_UNITS_PER_CENT:-10000} : nat64 }"
+else
+  LEDGER_ARG="null"
+  PAYMENT_LEDGER_ARG="null"
+fi
+# Controllers implicitly hold every role; grant others later with grant_role
+dfx deploy property_canister --argument "(record { investment_canister = principal \"$INVESTMENT_CANISTER_ID\"; roles = vec {} })"
+dfx deploy investment_canister --argument "(record { property_canister = principal \"$PROPERTY_CANISTER_ID\"; payment_ledger = $PAYMENT_LEDGER_ARG; user_canister = opt principal \"$USER_CANISTER_ID\"; roles = vec {} })"
+dfx deploy user_canister --argument "(record { roles = vec {} })"
+dfx deploy governance_canister --argument "(record { investment_canister = principal \"$INVESTMENT_CANISTER_ID\"; property_canister = principal \"$PROPERTY_CANISTER_ID\"; ledger_canister = $LEDGER_ARG; roles = vec {} })"
+# New properties get their governance config from the governance canister
+dfx canister call property_canister set_governance_canister "(principal \"$GOVERNANCE_CANISTER_ID\")"
+# Passed proposals act on the property and investment canisters
+dfx canister call property_canister grant_role "(record { principal = principal \"$GOVERNANCE_CANISTER_ID\"; role = variant { PropertyManager } })"
+dfx canister call investment_canister grant_role "(record { principal = principal \"$GOVERNANCE_CANISTER_ID\"; role = variant { Treasury } })"
+dfx deploy frontend
 
 # Initialize canisters with sample data
 echo "📊 Initializing with sample data..."
@@ -49,6 +76,21 @@ dfx canister call property_canister create_property '(
   }
 )'
 
+# Each property gets its own token canister, installed from the token_canister
+# build with the token the property canister describes, and registered with
+# the investment canister so that its transfers can move holdings
+TOKEN_WASM=.dfx/local/canisters/token_canister/token_canister.wasm
+TOKEN_CANISTER_IDS=()
+for PROPERTY_ID in 1 2; do
+  TOKEN=$(dfx canister call property_canister get_property_token "($PROPERTY_ID : nat64)" --output idl \
+    | tr -d '\n' | sed -e 's/^( *opt //' -e 's/, *)$//')
+  TOKEN_CANISTER_ID=$(dfx canister call aaaaa-aa provisional_create_canister_with_cycles '(record { settings = null; amount = null })' \
+    | grep -o 'principal "[^"]*"' | cut -d '"' -f 2)
+  dfx canister install "$TOKEN_CANISTER_ID" --wasm "$TOKEN_WASM" --argument "(record { token = $TOKEN; property_canister = principal \"$PROPERTY_CANISTER_ID\"; investment_canister = principal \"$INVESTMENT_CANISTER_ID\" })"
+  dfx canister call investment_canister set_property_token_canister "($PROPERTY_ID : nat64, principal \"$TOKEN_CANISTER_ID\")"
+  TOKEN_CANISTER_IDS+=("$TOKEN_CANISTER_ID")
+done
+
 echo "✅ ICP deployment completed!"
 echo ""
 echo "🔗 Canister URLs:"
@@ -56,6 +98,9 @@ dfx canister id property_canister && echo "Property Canister: http://127.0.0.1:4
 dfx canister id investment_canister && echo "Investment Canister: http://127.0.0.1:4943/?canisterId=$(dfx canister id investment_canister)"
 dfx canister id governance_canister && echo "Governance Canister: http://127.0.0.1:4943/?canisterId=$(dfx canister id governance_canister)"
 dfx canister id user_canister && echo "User Canister: http://127.0.0.1:4943/?canisterId=$(dfx canister id user_canister)"
+for i in "${!TOKEN_CANISTER_IDS[@]}"; do
+  echo "Token Canister (property $((i + 1))): http://127.0.0.1:4943/?canisterId=${TOKEN_CANISTER_IDS[$i]}"
+done
 
 echo ""
 echo "🎯 Your RealtyChain app now has:"
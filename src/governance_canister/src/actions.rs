@@ -0,0 +1,209 @@
+//! Typed actions a proposal carries out once it passes, and their execution
+//! against the property and investment canisters.
+
+use crate::config::{GovernanceConfig, CONFIG_PROPOSAL_TYPE};
+use crate::{apply_governance_config, investment_canister_id, property_canister_id};
+use candid::{CandidType, Deserialize, Principal};
+use realty_common::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum ProposalAction {
+    /// Pays `amount` (USD cents) to holders in proportion to their tokens.
+    DistributeDividend { amount: u64 },
+    UpdatePropertyMetadata(PropertyMetadataUpdate),
+    DeactivateProperty,
+    /// Records the sale of the property at `price` (USD cents).
+    SellProperty { price: u64 },
+    /// Pays `amount` (USD cents) to `payee` for maintenance work.
+    FundMaintenance { amount: u64, payee: Principal },
+    /// Replaces the property's governance configuration.
+    UpdateGovernanceConfig(GovernanceConfig),
+}
+
+/// Mirrors the property canister's `PropertyMetadataUpdate`.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct PropertyMetadataUpdate {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub expected_roi: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum ExecutionResult {
+    Succeeded,
+    Failed(String),
+}
+
+/// Outcome of the latest attempt to carry out a proposal's action.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct ExecutionRecord {
+    pub attempted_at: u64,
+    pub result: ExecutionResult,
+}
+
+impl ProposalAction {
+    /// Proposal type whose tally rules must approve the action, so that an
+    /// action cannot borrow the lower quorum of another type.
+    pub fn proposal_type(&self) -> &'static str {
+        match self {
+            ProposalAction::DistributeDividend { .. } => "dividend",
+            ProposalAction::UpdatePropertyMetadata(_) => "improvement",
+            ProposalAction::DeactivateProperty | ProposalAction::SellProperty { .. } => "sale",
+            ProposalAction::FundMaintenance { .. } => "maintenance",
+            ProposalAction::UpdateGovernanceConfig(_) => CONFIG_PROPOSAL_TYPE,
+        }
+    }
+
+    pub fn validate(&self, proposal_type: &str) -> Result<()> {
+        if proposal_type != self.proposal_type() {
+            return Err(Error::invalid_input(
+                "action",
+                &format!("requires a {} proposal", self.proposal_type()),
+            ));
+        }
+
+        match self {
+            ProposalAction::DistributeDividend { amount }
+            | ProposalAction::FundMaintenance { amount, .. } => {
+                if *amount == 0 {
+                    return Err(Error::invalid_input("amount", "must be greater than zero"));
+                }
+            }
+            ProposalAction::SellProperty { price } => {
+                if *price == 0 {
+                    return Err(Error::invalid_input("price", "must be greater than zero"));
+                }
+            }
+            ProposalAction::UpdatePropertyMetadata(update) => {
+                let fields = [
+                    ("title", &update.title),
+                    ("description", &update.description),
+                    ("image_url", &update.image_url),
+                    ("expected_roi", &update.expected_roi),
+                ];
+                if fields.iter().all(|(_, value)| value.is_none()) {
+                    return Err(Error::invalid_input("action", "must update at least one field"));
+                }
+                for (field, value) in fields {
+                    if value.as_deref().is_some_and(|value| value.trim().is_empty()) {
+                        return Err(Error::invalid_input(field, "must not be empty"));
+                    }
+                }
+            }
+            ProposalAction::UpdateGovernanceConfig(config) => config.validate()?,
+            ProposalAction::DeactivateProperty => {}
+        }
+        Ok(())
+    }
+
+    /// Carries out the action on `property_id`. The governance canister must
+    /// hold the PropertyManager role on the property canister and the
+    /// Treasury role on the investment canister for the actions it forwards.
+    pub async fn execute(&self, property_id: u64) -> Result<()> {
+        match self {
+            ProposalAction::DistributeDividend { amount } => {
+                call::<u64>(investment_canister_id()?, "distribute_dividend", (property_id, *amount))
+                    .await?;
+            }
+            ProposalAction::UpdatePropertyMetadata(update) => {
+                call::<candid::Reserved>(
+                    property_canister_id()?,
+                    "update_property_metadata",
+                    (property_id, update.clone()),
+                )
+                .await?;
+            }
+            ProposalAction::DeactivateProperty => {
+                call::<candid::Reserved>(property_canister_id()?, "deactivate_property", (property_id,))
+                    .await?;
+            }
+            ProposalAction::SellProperty { price } => {
+                call::<candid::Reserved>(
+                    property_canister_id()?,
+                    "record_property_sale",
+                    (property_id, *price),
+                )
+                .await?;
+            }
+            ProposalAction::FundMaintenance { amount, payee } => {
+                call::<u64>(
+                    investment_canister_id()?,
+                    "fund_maintenance",
+                    (property_id, *payee, *amount),
+                )
+                .await?;
+            }
+            ProposalAction::UpdateGovernanceConfig(config) => {
+                apply_governance_config(property_id, config.clone())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Calls an endpoint that returns `Result<T>`, flattening rejections and
+/// endpoint errors into one `Result`.
+async fn call<T>(canister: Principal, method: &str, args: impl candid::utils::ArgumentEncoder) -> Result<T>
+where
+    T: CandidType + DeserializeOwned,
+{
+    let (result,): (Result<T>,) = ic_cdk::call(canister, method, args)
+        .await
+        .map_err(|err| Error::call_failed(method, err))?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata_update() -> PropertyMetadataUpdate {
+        PropertyMetadataUpdate {
+            title: None,
+            description: None,
+            image_url: None,
+            expected_roi: None,
+        }
+    }
+
+    #[test]
+    fn action_must_match_the_proposal_type() {
+        let dividend = ProposalAction::DistributeDividend { amount: 1_000 };
+        assert!(dividend.validate("dividend").is_ok());
+        assert!(dividend.validate("maintenance").is_err());
+        assert!(ProposalAction::DeactivateProperty.validate("sale").is_ok());
+        assert!(ProposalAction::DeactivateProperty.validate("improvement").is_err());
+    }
+
+    #[test]
+    fn rejects_zero_amounts() {
+        assert!(ProposalAction::DistributeDividend { amount: 0 }.validate("dividend").is_err());
+        let maintenance = ProposalAction::FundMaintenance {
+            amount: 0,
+            payee: Principal::anonymous(),
+        };
+        assert!(maintenance.validate("maintenance").is_err());
+        assert!(ProposalAction::SellProperty { price: 0 }.validate("sale").is_err());
+    }
+
+    #[test]
+    fn metadata_update_must_change_a_field() {
+        let empty = ProposalAction::UpdatePropertyMetadata(metadata_update());
+        assert!(empty.validate("improvement").is_err());
+
+        let blank_title = ProposalAction::UpdatePropertyMetadata(PropertyMetadataUpdate {
+            title: Some("  ".to_string()),
+            ..metadata_update()
+        });
+        assert!(blank_title.validate("improvement").is_err());
+
+        let new_title = ProposalAction::UpdatePropertyMetadata(PropertyMetadataUpdate {
+            title: Some("Harbour View Lofts".to_string()),
+            ..metadata_update()
+        });
+        assert!(new_title.validate("improvement").is_ok());
+    }
+}
@@ -0,0 +1,253 @@
+//! Proposals that choose between several options, and the plurality,
+//! approval and ranked-choice tallies used to pick the winner.
+
+use candid::{CandidType, Deserialize};
+use realty_common::{Error, Result};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+pub const MAX_OPTIONS: usize = 20;
+pub const MAX_OPTION_LENGTH: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum ProposalKind {
+    /// A single for/against question decided by `vote_choice`.
+    YesNo,
+    /// Each ballot selects one option; the option with the most power wins.
+    Plurality,
+    /// Each ballot selects any number of options, each receiving the ballot's
+    /// full power; the option with the most power wins.
+    Approval,
+    /// Each ballot ranks options in order of preference and the winner is
+    /// found by instant runoff.
+    RankedChoice,
+}
+
+/// Tally of one option of a multi-option proposal.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct OptionTally {
+    pub option: u32,
+    /// Power counted for the option. For ranked-choice proposals this is the
+    /// power in the last round the option took part in.
+    pub power: u64,
+    /// Ranked-choice round in which the option was eliminated, counting from 1.
+    pub eliminated_in_round: Option<u32>,
+}
+
+pub fn validate_options(kind: ProposalKind, options: &[String]) -> Result<()> {
+    if kind == ProposalKind::YesNo {
+        if !options.is_empty() {
+            return Err(Error::invalid_input("options", "yes/no proposals take no options"));
+        }
+        return Ok(());
+    }
+
+    if options.len() < 2 || options.len() > MAX_OPTIONS {
+        return Err(Error::invalid_input(
+            "options",
+            &format!("must list between 2 and {} options", MAX_OPTIONS),
+        ));
+    }
+    let mut labels = BTreeSet::new();
+    for option in options {
+        let label = option.trim();
+        if label.is_empty() || label.len() > MAX_OPTION_LENGTH {
+            return Err(Error::invalid_input(
+                "options",
+                &format!("each option must be 1 to {} characters", MAX_OPTION_LENGTH),
+            ));
+        }
+        if !labels.insert(label.to_lowercase()) {
+            return Err(Error::invalid_input("options", "options must be distinct"));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `selection` is a valid ballot for a proposal of `kind` with
+/// `option_count` options.
+pub fn validate_selection(kind: ProposalKind, option_count: usize, selection: &[u32]) -> Result<()> {
+    if kind == ProposalKind::YesNo {
+        if !selection.is_empty() {
+            return Err(Error::invalid_input("selection", "yes/no proposals use vote_choice"));
+        }
+        return Ok(());
+    }
+
+    if selection.is_empty() {
+        return Err(Error::invalid_input("selection", "must select at least one option"));
+    }
+    if kind == ProposalKind::Plurality && selection.len() > 1 {
+        return Err(Error::invalid_input("selection", "must select exactly one option"));
+    }
+    let mut seen = BTreeSet::new();
+    for option in selection {
+        if *option as usize >= option_count {
+            return Err(Error::invalid_input("selection", "unknown option"));
+        }
+        if !seen.insert(*option) {
+            return Err(Error::invalid_input("selection", "options may only be selected once"));
+        }
+    }
+    Ok(())
+}
+
+/// Tallies `ballots` of (power, selection) for a multi-option proposal and
+/// returns the per-option results with the winning option. A tie for first
+/// place has no winner.
+pub fn tally(kind: ProposalKind, option_count: usize, ballots: &[(u64, Vec<u32>)]) -> (Vec<OptionTally>, Option<u32>) {
+    match kind {
+        ProposalKind::YesNo => (Vec::new(), None),
+        ProposalKind::Plurality | ProposalKind::Approval => {
+            let mut power = vec![0u64; option_count];
+            for (ballot_power, selection) in ballots {
+                for option in selection {
+                    power[*option as usize] += ballot_power;
+                }
+            }
+            let winner = unique_leader(power.iter().copied().enumerate());
+            let tallies = power
+                .into_iter()
+                .enumerate()
+                .map(|(option, power)| OptionTally {
+                    option: option as u32,
+                    power,
+                    eliminated_in_round: None,
+                })
+                .collect();
+            (tallies, winner)
+        }
+        ProposalKind::RankedChoice => instant_runoff(option_count, ballots),
+    }
+}
+
+fn unique_leader(power: impl Iterator<Item = (usize, u64)>) -> Option<u32> {
+    let mut leader = None;
+    let mut best = 0;
+    let mut tied = false;
+    for (option, power) in power {
+        if power > best {
+            best = power;
+            leader = Some(option as u32);
+            tied = false;
+        } else if power == best && power > 0 {
+            tied = true;
+        }
+    }
+    if tied {
+        None
+    } else {
+        leader
+    }
+}
+
+/// Counts each ballot for its highest-ranked option still in the running,
+/// eliminating the weakest option each round until one holds a majority of
+/// the power still counted. The weakest option with the highest index is
+/// eliminated first when several are tied.
+fn instant_runoff(option_count: usize, ballots: &[(u64, Vec<u32>)]) -> (Vec<OptionTally>, Option<u32>) {
+    let mut tallies: Vec<OptionTally> = (0..option_count)
+        .map(|option| OptionTally {
+            option: option as u32,
+            power: 0,
+            eliminated_in_round: None,
+        })
+        .collect();
+    let mut round = 0u32;
+
+    loop {
+        round += 1;
+        let mut power = vec![0u64; option_count];
+        for (ballot_power, ranking) in ballots {
+            let choice = ranking
+                .iter()
+                .find(|option| tallies[**option as usize].eliminated_in_round.is_none());
+            if let Some(option) = choice {
+                power[*option as usize] += ballot_power;
+            }
+        }
+
+        let remaining: Vec<usize> = (0..option_count)
+            .filter(|option| tallies[*option].eliminated_in_round.is_none())
+            .collect();
+        for option in &remaining {
+            tallies[*option].power = power[*option];
+        }
+
+        let counted: u64 = remaining.iter().map(|option| power[*option]).sum();
+        if counted == 0 {
+            return (tallies, None);
+        }
+        if let Some(option) = remaining.iter().find(|option| power[**option] * 2 > counted) {
+            return (tallies, Some(*option as u32));
+        }
+
+        let weakest = remaining
+            .iter()
+            .copied()
+            .min_by_key(|option| (power[*option], std::cmp::Reverse(*option)))
+            .expect("at least one option remains");
+        let all_tied = remaining.iter().all(|option| power[*option] == power[weakest]);
+        if all_tied {
+            return (tallies, None);
+        }
+        tallies[weakest].eliminated_in_round = Some(round);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plurality_picks_most_power() {
+        let ballots = vec![(10, vec![0]), (30, vec![1]), (25, vec![2])];
+        let (tallies, winner) = tally(ProposalKind::Plurality, 3, &ballots);
+        assert_eq!(winner, Some(1));
+        assert_eq!(tallies[2].power, 25);
+    }
+
+    #[test]
+    fn approval_counts_every_selected_option() {
+        let ballots = vec![(10, vec![0, 1]), (15, vec![1]), (20, vec![2])];
+        let (tallies, winner) = tally(ProposalKind::Approval, 3, &ballots);
+        assert_eq!(tallies[1].power, 25);
+        assert_eq!(winner, Some(1));
+    }
+
+    #[test]
+    fn ties_have_no_winner() {
+        let ballots = vec![(10, vec![0]), (10, vec![1])];
+        assert_eq!(tally(ProposalKind::Plurality, 2, &ballots).1, None);
+    }
+
+    #[test]
+    fn instant_runoff_transfers_eliminated_preferences() {
+        // Option 2 is eliminated first and its ballots move to option 1,
+        // which overtakes the first-round leader.
+        let ballots = vec![(40, vec![0]), (35, vec![1]), (25, vec![2, 1])];
+        let (tallies, winner) = tally(ProposalKind::RankedChoice, 3, &ballots);
+        assert_eq!(winner, Some(1));
+        assert_eq!(tallies[2].eliminated_in_round, Some(1));
+        assert_eq!(tallies[1].power, 60);
+        assert_eq!(tallies[0].power, 40);
+    }
+
+    #[test]
+    fn instant_runoff_ignores_exhausted_ballots() {
+        let ballots = vec![(40, vec![0]), (30, vec![1]), (20, vec![2])];
+        let (tallies, winner) = tally(ProposalKind::RankedChoice, 3, &ballots);
+        assert_eq!(winner, Some(0));
+        assert_eq!(tallies[2].eliminated_in_round, Some(1));
+        assert_eq!(tallies[1].eliminated_in_round, None);
+    }
+
+    #[test]
+    fn validates_selections() {
+        assert!(validate_selection(ProposalKind::Plurality, 3, &[1]).is_ok());
+        assert!(validate_selection(ProposalKind::Plurality, 3, &[0, 1]).is_err());
+        assert!(validate_selection(ProposalKind::Approval, 3, &[3]).is_err());
+        assert!(validate_selection(ProposalKind::RankedChoice, 3, &[2, 2]).is_err());
+        assert!(validate_selection(ProposalKind::YesNo, 0, &[]).is_ok());
+    }
+}
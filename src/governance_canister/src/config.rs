@@ -0,0 +1,191 @@
+//! Governance settings of a single property: which proposal types may be
+//! raised, the rules each is decided by and the limits on new proposals.
+
+use crate::tally::{TallyRules, VotingMode};
+use candid::{CandidType, Deserialize};
+use realty_common::schema::Versioned;
+use realty_common::{versioned_storable, Error, Result};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Proposal type that changes a property's configuration. Every configuration
+/// must allow it so that a property can never lock itself out of governance.
+pub const CONFIG_PROPOSAL_TYPE: &str = "config";
+
+pub const DEFAULT_MIN_VOTING_DAYS: u64 = 1;
+pub const DEFAULT_MAX_VOTING_DAYS: u64 = 30;
+pub const DEFAULT_MIN_PROPOSER_TOKENS: u64 = 10;
+pub const DEFAULT_PROPOSAL_DEPOSIT: u64 = 10_000_000; // in deposit ledger base units
+
+pub const MAX_VOTING_DAYS_LIMIT: u64 = 365;
+pub const MAX_TIMELOCK_SECONDS: u64 = 90 * 24 * 60 * 60;
+// Upper bounds that keep a passed config from making proposals impossible
+pub const MAX_MIN_PROPOSER_TOKENS: u64 = 10_000;
+pub const MAX_PROPOSAL_DEPOSIT: u64 = 100 * DEFAULT_PROPOSAL_DEPOSIT;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct GovernanceConfig {
+    pub proposal_types: Vec<ProposalTypeConfig>, // Types that may be proposed on the property
+    pub voting_mode: VotingMode,
+    pub min_voting_days: u64,
+    pub max_voting_days: u64,
+    pub min_proposer_tokens: u64,
+    pub proposal_deposit: u64, // 0 disables deposits
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct ProposalTypeConfig {
+    pub proposal_type: String,
+    pub tally_rules: TallyRules,
+    pub timelock_seconds: u64,
+}
+
+impl Versioned for GovernanceConfig {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(GovernanceConfig);
+
+impl GovernanceConfig {
+    /// Configuration allowing `proposal_types` with the default limits.
+    pub fn with_types(proposal_types: Vec<ProposalTypeConfig>) -> GovernanceConfig {
+        GovernanceConfig {
+            proposal_types,
+            voting_mode: VotingMode::Linear,
+            min_voting_days: DEFAULT_MIN_VOTING_DAYS,
+            max_voting_days: DEFAULT_MAX_VOTING_DAYS,
+            min_proposer_tokens: DEFAULT_MIN_PROPOSER_TOKENS,
+            proposal_deposit: DEFAULT_PROPOSAL_DEPOSIT,
+        }
+    }
+
+    pub fn proposal_type(&self, proposal_type: &str) -> Result<&ProposalTypeConfig> {
+        self.proposal_types
+            .iter()
+            .find(|config| config.proposal_type == proposal_type)
+            .ok_or_else(|| Error::invalid_input("proposal_type", "is not allowed on this property"))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let mut seen = BTreeSet::new();
+        for config in &self.proposal_types {
+            if config.proposal_type.trim().is_empty() {
+                return Err(Error::invalid_input("proposal_type", "must not be empty"));
+            }
+            if !seen.insert(config.proposal_type.as_str()) {
+                return Err(Error::invalid_input("proposal_types", "must not repeat a type"));
+            }
+            config.tally_rules.validate()?;
+            if config.timelock_seconds > MAX_TIMELOCK_SECONDS {
+                return Err(Error::invalid_input(
+                    "timelock_seconds",
+                    &format!("must not exceed {}", MAX_TIMELOCK_SECONDS),
+                ));
+            }
+        }
+        if !seen.contains(CONFIG_PROPOSAL_TYPE) {
+            return Err(Error::invalid_input(
+                "proposal_types",
+                &format!("must include the {} type", CONFIG_PROPOSAL_TYPE),
+            ));
+        }
+        self.voting_mode.validate()?;
+        if self.min_voting_days == 0
+            || self.min_voting_days > self.max_voting_days
+            || self.max_voting_days > MAX_VOTING_DAYS_LIMIT
+        {
+            return Err(Error::invalid_input(
+                "voting_days",
+                &format!("must satisfy 1 <= min <= max <= {}", MAX_VOTING_DAYS_LIMIT),
+            ));
+        }
+        if self.min_proposer_tokens > MAX_MIN_PROPOSER_TOKENS {
+            return Err(Error::invalid_input(
+                "min_proposer_tokens",
+                &format!("must not exceed {}", MAX_MIN_PROPOSER_TOKENS),
+            ));
+        }
+        if self.proposal_deposit > MAX_PROPOSAL_DEPOSIT {
+            return Err(Error::invalid_input(
+                "proposal_deposit",
+                &format!("must not exceed {}", MAX_PROPOSAL_DEPOSIT),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_voting_duration(&self, days: u64) -> Result<()> {
+        if !(self.min_voting_days..=self.max_voting_days).contains(&days) {
+            return Err(Error::invalid_input(
+                "voting_duration_days",
+                &format!(
+                    "must be between {} and {} days",
+                    self.min_voting_days, self.max_voting_days
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_config(proposal_type: &str) -> ProposalTypeConfig {
+        ProposalTypeConfig {
+            proposal_type: proposal_type.to_string(),
+            tally_rules: TallyRules::default_for(proposal_type),
+            timelock_seconds: 0,
+        }
+    }
+
+    #[test]
+    fn requires_the_config_type() {
+        let config = GovernanceConfig::with_types(vec![type_config("maintenance")]);
+        assert!(config.validate().is_err());
+
+        let config = GovernanceConfig::with_types(vec![
+            type_config("maintenance"),
+            type_config(CONFIG_PROPOSAL_TYPE),
+        ]);
+        assert!(config.validate().is_ok());
+        assert!(config.proposal_type("maintenance").is_ok());
+        assert!(config.proposal_type("sale").is_err());
+    }
+
+    #[test]
+    fn validates_voting_durations() {
+        let mut config = GovernanceConfig::with_types(vec![type_config(CONFIG_PROPOSAL_TYPE)]);
+        assert!(config.validate_voting_duration(7).is_ok());
+        assert!(config.validate_voting_duration(0).is_err());
+        assert!(config.validate_voting_duration(31).is_err());
+
+        config.min_voting_days = 10;
+        config.max_voting_days = 5;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn bounds_proposer_requirements() {
+        let config = GovernanceConfig::with_types(vec![type_config(CONFIG_PROPOSAL_TYPE)]);
+        let at_limits = GovernanceConfig {
+            min_proposer_tokens: MAX_MIN_PROPOSER_TOKENS,
+            proposal_deposit: MAX_PROPOSAL_DEPOSIT,
+            ..config.clone()
+        };
+        assert!(at_limits.validate().is_ok());
+
+        let too_many_tokens = GovernanceConfig {
+            min_proposer_tokens: MAX_MIN_PROPOSER_TOKENS + 1,
+            ..config.clone()
+        };
+        assert!(too_many_tokens.validate().is_err());
+
+        let too_large_deposit = GovernanceConfig {
+            proposal_deposit: MAX_PROPOSAL_DEPOSIT + 1,
+            ..config
+        };
+        assert!(too_large_deposit.validate().is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Comment threads on proposals and the revisions a proposer leaves behind
+//! when amending a proposal before voting starts.
+
+use crate::actions::ProposalAction;
+use candid::{CandidType, Deserialize, Principal};
+use realty_common::schema::Versioned;
+use realty_common::{versioned_storable, Error, Result};
+use serde::Serialize;
+
+pub const MAX_COMMENT_LENGTH: usize = 5_000;
+pub const MAX_MODERATION_REASON_LENGTH: usize = 500;
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Comment {
+    pub id: u64,
+    pub proposal_id: u64,
+    pub author: Principal,
+    pub body: String, // Withheld from queries while the comment is hidden
+    pub parent_id: Option<u64>, // Comment this one replies to
+    pub created_at: u64,
+    pub hidden: Option<Moderation>,
+}
+
+impl Versioned for Comment {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(Comment);
+
+/// Why and by whom a comment was hidden.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct Moderation {
+    pub moderator: Principal,
+    pub reason: String,
+    pub hidden_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AddCommentRequest {
+    pub proposal_id: u64,
+    pub body: String,
+    pub parent_id: Option<u64>,
+}
+
+/// New content of a proposal. Every field is replaced, so the action is
+/// removed when `action` is `None`.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalAmendment {
+    pub title: String,
+    pub description: String,
+    pub action: Option<ProposalAction>,
+}
+
+/// Content a proposal had before an amendment replaced it. Revision 0 is the
+/// proposal as created.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ProposalRevision {
+    pub proposal_id: u64,
+    pub revision: u32,
+    pub title: String,
+    pub description: String,
+    pub action: Option<ProposalAction>,
+    pub superseded_at: u64,
+}
+
+impl Versioned for ProposalRevision {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(ProposalRevision);
+
+pub fn validate_comment_body(body: &str) -> Result<()> {
+    let body = body.trim();
+    if body.is_empty() || body.chars().count() > MAX_COMMENT_LENGTH {
+        return Err(Error::invalid_input(
+            "body",
+            &format!("must be 1 to {} characters", MAX_COMMENT_LENGTH),
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_moderation_reason(reason: &str) -> Result<()> {
+    let reason = reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_MODERATION_REASON_LENGTH {
+        return Err(Error::invalid_input(
+            "reason",
+            &format!("must be 1 to {} characters", MAX_MODERATION_REASON_LENGTH),
+        ));
+    }
+    Ok(())
+}
+
+impl Comment {
+    /// The comment as shown to readers, with the body of a hidden comment
+    /// withheld so replies keep their place in the thread.
+    pub fn redacted(mut self) -> Comment {
+        if self.hidden.is_some() {
+            self.body.clear();
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_comments_are_redacted() {
+        let comment = Comment {
+            id: 1,
+            proposal_id: 1,
+            author: Principal::anonymous(),
+            body: "spam".to_string(),
+            parent_id: None,
+            created_at: 0,
+            hidden: None,
+        };
+        assert_eq!(comment.clone().redacted().body, "spam");
+
+        let hidden = Comment {
+            hidden: Some(Moderation {
+                moderator: Principal::anonymous(),
+                reason: "off topic".to_string(),
+                hidden_at: 1,
+            }),
+            ..comment
+        };
+        assert!(hidden.redacted().body.is_empty());
+    }
+
+    #[test]
+    fn validates_comment_length() {
+        assert!(validate_comment_body("Agree with the roof repair").is_ok());
+        assert!(validate_comment_body("  ").is_err());
+        assert!(validate_comment_body(&"x".repeat(MAX_COMMENT_LENGTH + 1)).is_err());
+    }
+}
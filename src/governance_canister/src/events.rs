@@ -0,0 +1,44 @@
+//! Append-only log of proposal lifecycle events, which indexers and
+//! notification services follow by sequence number.
+
+use crate::ProposalStatus;
+use candid::{CandidType, Deserialize, Principal};
+use realty_common::schema::Versioned;
+use realty_common::versioned_storable;
+use serde::Serialize;
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GovernanceEvent {
+    pub seq: u64, // Starts at 1 and increases by one per event
+    pub timestamp: u64,
+    pub proposal_id: u64,
+    pub kind: GovernanceEventKind,
+}
+
+impl Versioned for GovernanceEvent {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(GovernanceEvent);
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum GovernanceEventKind {
+    ProposalCreated {
+        property_id: u64,
+        proposer: Principal,
+        proposal_type: String,
+    },
+    /// A vote was cast or replaced; the replaced vote stops counting.
+    VoteCast {
+        vote_id: u64,
+        voter: Principal,
+        vote_power: u64,
+    },
+    /// Voting closed with `status`, or a queued proposal was vetoed.
+    ProposalFinalized {
+        status: ProposalStatus,
+        winning_option: Option<u32>,
+    },
+    ProposalExecuted,
+    ExecutionFailed { reason: String },
+}
@@ -0,0 +1,313 @@
+//! Stored layouts of governance records from earlier record versions, kept so
+//! `Versioned::decode_previous` can read data written before an upgrade.
+
+use crate::actions::{ExecutionRecord, ProposalAction};
+use crate::ballots::ProposalKind;
+use crate::tally::{TallyRules, VotingMode};
+use crate::{Proposal, ProposalStatus, Vote};
+use candid::{CandidType, Deserialize, Principal};
+
+/// Proposal as stored at record versions 0 and 1.
+#[derive(CandidType, Deserialize)]
+pub struct ProposalV1 {
+    pub id: u64,
+    pub property_id: u64,
+    pub title: String,
+    pub description: String,
+    pub proposal_type: String,
+    pub proposer: Principal,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub voting_power_for: u64,
+    pub voting_power_against: u64,
+    pub status: String,
+    pub created_at: u64,
+    pub voting_deadline: u64,
+    pub execution_data: Option<String>,
+}
+
+/// Proposal as stored at record version 2, with a free-form status.
+#[derive(CandidType, Deserialize)]
+pub struct ProposalV2 {
+    pub id: u64,
+    pub property_id: u64,
+    pub title: String,
+    pub description: String,
+    pub proposal_type: String,
+    pub proposer: Principal,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub voting_power_for: u64,
+    pub voting_power_against: u64,
+    pub status: String,
+    pub created_at: u64,
+    pub voting_deadline: u64,
+    pub execution_data: Option<String>,
+    pub eligible_voting_power: u64,
+}
+
+impl From<ProposalV1> for ProposalV2 {
+    fn from(v1: ProposalV1) -> Self {
+        ProposalV2 {
+            id: v1.id,
+            property_id: v1.property_id,
+            title: v1.title,
+            description: v1.description,
+            proposal_type: v1.proposal_type,
+            proposer: v1.proposer,
+            votes_for: v1.votes_for,
+            votes_against: v1.votes_against,
+            voting_power_for: v1.voting_power_for,
+            voting_power_against: v1.voting_power_against,
+            status: v1.status,
+            created_at: v1.created_at,
+            voting_deadline: v1.voting_deadline,
+            execution_data: v1.execution_data,
+            // No snapshot was taken for these proposals
+            eligible_voting_power: 0,
+        }
+    }
+}
+
+/// Proposal as stored at record version 3, with opaque execution data.
+#[derive(CandidType, Deserialize)]
+pub struct ProposalV3 {
+    pub id: u64,
+    pub property_id: u64,
+    pub title: String,
+    pub description: String,
+    pub proposal_type: String,
+    pub proposer: Principal,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub voting_power_for: u64,
+    pub voting_power_against: u64,
+    pub status: ProposalStatus,
+    pub created_at: u64,
+    pub voting_deadline: u64,
+    pub execution_data: Option<String>,
+    pub eligible_voting_power: u64,
+    pub tally_rules: TallyRules,
+}
+
+impl From<ProposalV2> for ProposalV3 {
+    fn from(v2: ProposalV2) -> Self {
+        // update_proposal_status used to accept any string; anything that is
+        // not a known outcome is treated as a rejection.
+        let status = match v2.status.as_str() {
+            "active" => ProposalStatus::Active,
+            "passed" => ProposalStatus::Passed,
+            "executed" => ProposalStatus::Executed,
+            _ => ProposalStatus::Rejected,
+        };
+        ProposalV3 {
+            id: v2.id,
+            property_id: v2.property_id,
+            title: v2.title,
+            description: v2.description,
+            tally_rules: TallyRules::default_for(&v2.proposal_type),
+            proposal_type: v2.proposal_type,
+            proposer: v2.proposer,
+            votes_for: v2.votes_for,
+            votes_against: v2.votes_against,
+            voting_power_for: v2.voting_power_for,
+            voting_power_against: v2.voting_power_against,
+            status,
+            created_at: v2.created_at,
+            voting_deadline: v2.voting_deadline,
+            execution_data: v2.execution_data,
+            eligible_voting_power: v2.eligible_voting_power,
+        }
+    }
+}
+
+/// Proposal as stored at record version 4, before multi-option proposals.
+#[derive(CandidType, Deserialize)]
+pub struct ProposalV4 {
+    pub id: u64,
+    pub property_id: u64,
+    pub title: String,
+    pub description: String,
+    pub proposal_type: String,
+    pub proposer: Principal,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub voting_power_for: u64,
+    pub voting_power_against: u64,
+    pub status: ProposalStatus,
+    pub created_at: u64,
+    pub voting_deadline: u64,
+    pub eligible_voting_power: u64,
+    pub tally_rules: TallyRules,
+    pub action: Option<ProposalAction>,
+    pub execution: Option<ExecutionRecord>,
+    pub executable_at: Option<u64>,
+}
+
+impl From<ProposalV3> for ProposalV4 {
+    fn from(v3: ProposalV3) -> Self {
+        ProposalV4 {
+            id: v3.id,
+            property_id: v3.property_id,
+            title: v3.title,
+            description: v3.description,
+            proposal_type: v3.proposal_type,
+            proposer: v3.proposer,
+            votes_for: v3.votes_for,
+            votes_against: v3.votes_against,
+            voting_power_for: v3.voting_power_for,
+            voting_power_against: v3.voting_power_against,
+            status: v3.status,
+            created_at: v3.created_at,
+            voting_deadline: v3.voting_deadline,
+            eligible_voting_power: v3.eligible_voting_power,
+            tally_rules: v3.tally_rules,
+            // Free-form execution data was never executed and has no typed
+            // equivalent
+            action: None,
+            execution: None,
+            executable_at: None,
+        }
+    }
+}
+
+/// Proposal as stored at record version 5, before voting modes.
+#[derive(CandidType, Deserialize)]
+pub struct ProposalV5 {
+    pub id: u64,
+    pub property_id: u64,
+    pub title: String,
+    pub description: String,
+    pub proposal_type: String,
+    pub proposer: Principal,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub voting_power_for: u64,
+    pub voting_power_against: u64,
+    pub status: ProposalStatus,
+    pub created_at: u64,
+    pub voting_deadline: u64,
+    pub eligible_voting_power: u64,
+    pub tally_rules: TallyRules,
+    pub action: Option<ProposalAction>,
+    pub execution: Option<ExecutionRecord>,
+    pub executable_at: Option<u64>,
+    pub kind: ProposalKind,
+    pub options: Vec<String>,
+    pub winning_option: Option<u32>,
+}
+
+impl From<ProposalV4> for ProposalV5 {
+    fn from(v4: ProposalV4) -> Self {
+        ProposalV5 {
+            id: v4.id,
+            property_id: v4.property_id,
+            title: v4.title,
+            description: v4.description,
+            proposal_type: v4.proposal_type,
+            proposer: v4.proposer,
+            votes_for: v4.votes_for,
+            votes_against: v4.votes_against,
+            voting_power_for: v4.voting_power_for,
+            voting_power_against: v4.voting_power_against,
+            status: v4.status,
+            created_at: v4.created_at,
+            voting_deadline: v4.voting_deadline,
+            eligible_voting_power: v4.eligible_voting_power,
+            tally_rules: v4.tally_rules,
+            action: v4.action,
+            execution: v4.execution,
+            executable_at: v4.executable_at,
+            kind: ProposalKind::YesNo,
+            options: Vec::new(),
+            winning_option: None,
+        }
+    }
+}
+
+impl From<ProposalV5> for Proposal {
+    fn from(v5: ProposalV5) -> Self {
+        Proposal {
+            id: v5.id,
+            property_id: v5.property_id,
+            title: v5.title,
+            description: v5.description,
+            proposal_type: v5.proposal_type,
+            proposer: v5.proposer,
+            votes_for: v5.votes_for,
+            votes_against: v5.votes_against,
+            voting_power_for: v5.voting_power_for,
+            voting_power_against: v5.voting_power_against,
+            status: v5.status,
+            created_at: v5.created_at,
+            voting_deadline: v5.voting_deadline,
+            eligible_voting_power: v5.eligible_voting_power,
+            tally_rules: v5.tally_rules,
+            action: v5.action,
+            execution: v5.execution,
+            executable_at: v5.executable_at,
+            kind: v5.kind,
+            options: v5.options,
+            winning_option: v5.winning_option,
+            // Every proposal before voting modes was token weighted
+            voting_mode: VotingMode::Linear,
+            deposit: None,
+            first_vote_at: None,
+        }
+    }
+}
+
+/// Vote as stored at record versions 0 and 1, before delegation.
+#[derive(CandidType, Deserialize)]
+pub struct VoteV1 {
+    pub id: u64,
+    pub proposal_id: u64,
+    pub voter: Principal,
+    pub vote_power: u64,
+    pub vote_choice: bool,
+    pub timestamp: u64,
+}
+
+/// Vote as stored at record version 2, before multi-option ballots.
+#[derive(CandidType, Deserialize)]
+pub struct VoteV2 {
+    pub id: u64,
+    pub proposal_id: u64,
+    pub voter: Principal,
+    pub vote_power: u64,
+    pub vote_choice: bool,
+    pub timestamp: u64,
+    pub delegated_power: u64,
+    pub retracted_at: Option<u64>,
+}
+
+impl From<VoteV1> for VoteV2 {
+    fn from(v1: VoteV1) -> Self {
+        VoteV2 {
+            id: v1.id,
+            proposal_id: v1.proposal_id,
+            voter: v1.voter,
+            vote_power: v1.vote_power,
+            vote_choice: v1.vote_choice,
+            timestamp: v1.timestamp,
+            delegated_power: 0,
+            retracted_at: None,
+        }
+    }
+}
+
+impl From<VoteV2> for Vote {
+    fn from(v2: VoteV2) -> Self {
+        Vote {
+            id: v2.id,
+            proposal_id: v2.proposal_id,
+            voter: v2.voter,
+            vote_power: v2.vote_power,
+            vote_choice: v2.vote_choice,
+            timestamp: v2.timestamp,
+            delegated_power: v2.delegated_power,
+            retracted_at: v2.retracted_at,
+            selection: Vec::new(),
+        }
+    }
+}
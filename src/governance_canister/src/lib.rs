@@ -1,15 +1,99 @@
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk::api::time;
-use ic_cdk_macros::{init, query, update};
+use ic_cdk_macros::{init, post_upgrade, query, update};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use realty_common::access::{self, Role, RoleAssignment, RoleStore};
+use realty_common::icrc::{self, Account, LedgerTransfer};
+use realty_common::paging::{self, Page, PageRequest};
+use realty_common::schema::{self, Migration, Versioned};
+use realty_common::{versioned_storable, Error, Result};
 use serde::Serialize;
+use actions::{ExecutionRecord, ExecutionResult, ProposalAction};
+use ballots::{OptionTally, ProposalKind};
+use config::{GovernanceConfig, ProposalTypeConfig};
+use discussion::{AddCommentRequest, Comment, Moderation, ProposalAmendment, ProposalRevision};
+use events::{GovernanceEvent, GovernanceEventKind};
 use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tally::{TallyRules, VotingMode};
+
+mod actions;
+mod ballots;
+mod config;
+mod discussion;
+mod events;
+mod legacy;
+mod tally;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
+type SchemaVersionCell = StableCell<u32, Memory>;
 type IdStore = StableBTreeMap<u8, u64, Memory>;
 type ProposalStore = StableBTreeMap<u64, Proposal, Memory>;
 type VoteStore = StableBTreeMap<u64, Vote, Memory>;
+// (proposal_id, voter) -> vote_id
+type BallotIndex = StableBTreeMap<(u64, Principal), u64, Memory>;
+// (voter, vote_id)
+type VoterIndex = StableBTreeMap<(Principal, u64), (), Memory>;
+// (proposal_id, voter, vote_id) for every vote, including retracted ones
+type VoteHistoryIndex = StableBTreeMap<(u64, Principal, u64), (), Memory>;
+// (property_id, proposal_id)
+type PropertyProposalIndex = StableBTreeMap<(u64, u64), (), Memory>;
+type CanisterRefStore = StableBTreeMap<u8, Principal, Memory>;
+// (proposal_id, holder) -> voting power of the tokens held when the proposal was created
+type SnapshotStore = StableBTreeMap<(u64, Principal), u64, Memory>;
+// proposal_type -> rules applied to proposals created with that type
+type TallyRuleStore = StableBTreeMap<String, TallyRules, Memory>;
+// (voting_deadline, proposal_id) for proposals still awaiting finalization
+type DeadlineIndex = StableBTreeMap<(u64, u64), (), Memory>;
+// proposal_type -> seconds a passed proposal waits before it executes
+type TimelockStore = StableBTreeMap<String, u64, Memory>;
+// (executable_at, proposal_id) for queued proposals
+type QueueIndex = StableBTreeMap<(u64, u64), (), Memory>;
+// (proposal_id, holder) -> voting power the holder put behind a veto
+type VetoBallotStore = StableBTreeMap<(u64, Principal), u64, Memory>;
+// (delegator, scope key) -> delegate
+type DelegationStore = StableBTreeMap<(Principal, u64), Principal, Memory>;
+// (delegate, scope key, delegator)
+type DelegateIndex = StableBTreeMap<(Principal, u64, Principal), (), Memory>;
+// (proposal_id, delegator) -> id of the delegate's vote carrying its power
+type DelegatedBallotIndex = StableBTreeMap<(u64, Principal), u64, Memory>;
+// property_id -> voting mode of new proposals on the property
+type VotingModeStore = StableBTreeMap<u64, VotingMode, Memory>;
+// (proposer, property_id) -> time of the proposer's latest proposal on the property
+type ProposerActivityStore = StableBTreeMap<(Principal, u64), u64, Memory>;
+type CommentStore = StableBTreeMap<u64, Comment, Memory>;
+// (proposal_id, comment_id) for listing a proposal's comments
+type ProposalCommentIndex = StableBTreeMap<(u64, u64), (), Memory>;
+// (proposal_id, revision) -> content replaced by an amendment
+type RevisionStore = StableBTreeMap<(u64, u32), ProposalRevision, Memory>;
+type GovernanceConfigStore = StableBTreeMap<u64, GovernanceConfig, Memory>;
+type EventLog = StableBTreeMap<u64, GovernanceEvent, Memory>;
+
+const INVESTMENT_CANISTER_KEY: u8 = 0;
+const PROPERTY_CANISTER_KEY: u8 = 1;
+const LEDGER_CANISTER_KEY: u8 = 2;
+
+/// Bump when a stored record changes shape and add the matching migration.
+const SCHEMA_VERSION: u32 = 11;
+
+/// Timelock each built-in proposal type starts with, in seconds.
+const DEFAULT_TIMELOCKS: &[(&str, u64)] = &[
+    ("maintenance", 2 * 24 * 60 * 60),
+    ("improvement", 2 * 24 * 60 * 60),
+    ("dividend", 24 * 60 * 60),
+    ("sale", 7 * 24 * 60 * 60),
+    ("config", 7 * 24 * 60 * 60),
+];
+
+/// Share of the eligible voting power whose veto cancels a queued proposal.
+const VETO_THRESHOLD_BPS: u32 = 6_667;
+
+// Limits on new proposals that apply to every property
+const PROPOSAL_COOLDOWN_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_TITLE_LENGTH: usize = 200;
+const MAX_DESCRIPTION_LENGTH: usize = 10_000;
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct Proposal {
@@ -17,16 +101,54 @@ pub struct Proposal {
     pub property_id: u64,
     pub title: String,
     pub description: String,
-    pub proposal_type: String, // "maintenance", "improvement", "sale", "dividend"
+    pub proposal_type: String, // "maintenance", "improvement", "sale", "dividend", "config"
     pub proposer: Principal,
+    // Multi-option proposals count every ballot in the `for` tallies
     pub votes_for: u64,
     pub votes_against: u64,
     pub voting_power_for: u64,
     pub voting_power_against: u64,
-    pub status: String, // "active", "passed", "rejected", "executed"
+    pub status: ProposalStatus,
     pub created_at: u64,
     pub voting_deadline: u64,
-    pub execution_data: Option<String>, // JSON data for execution
+    pub eligible_voting_power: u64, // Voting power of all holders at creation
+    pub tally_rules: TallyRules, // Rules of the proposal type at creation
+    pub action: Option<ProposalAction>, // Carried out automatically once passed
+    pub execution: Option<ExecutionRecord>, // Latest attempt to carry out the action
+    pub executable_at: Option<u64>, // End of the timelock once queued
+    pub kind: ProposalKind,
+    pub options: Vec<String>, // Choices of a multi-option proposal
+    pub winning_option: Option<u32>, // Set when a multi-option proposal is finalized
+    pub voting_mode: VotingMode, // Mode of the property when the proposal was created
+    pub deposit: Option<Deposit>, // None when no deposit ledger was configured
+    pub first_vote_at: Option<u64>, // Amendments are refused once set
+}
+
+/// Deposit a proposer pays when creating a proposal. It is refunded once the
+/// proposal closes with quorum and forfeited otherwise.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct Deposit {
+    pub amount: u64,
+    pub status: DepositStatus,
+    pub unconfirmed_refund: Option<LedgerTransfer>, // Refund that may have reached the ledger, resent unchanged
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum DepositStatus {
+    Held,
+    Refunded,
+    Forfeited,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum ProposalStatus {
+    Active,
+    /// Approved and waiting out its timelock, during which it can be vetoed.
+    Queued,
+    Passed,
+    Rejected,
+    Vetoed,
+    Executed,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
@@ -34,11 +156,84 @@ pub struct Vote {
     pub id: u64,
     pub proposal_id: u64,
     pub voter: Principal,
-    pub vote_power: u64, // Based on token ownership
+    pub vote_power: u64, // Based on token ownership, including delegated power
     pub vote_choice: bool, // true = for, false = against
     pub timestamp: u64,
+    pub delegated_power: u64, // Part of vote_power delegated by other holders
+    pub retracted_at: Option<u64>, // Set when the vote is changed or withdrawn
+    pub selection: Vec<u32>, // Options chosen on a multi-option proposal, ranked if ranked-choice
+}
+
+/// Properties a delegation applies to. A delegation for a single property
+/// takes precedence over one for all properties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum DelegationScope {
+    AllProperties,
+    Property(u64),
+}
+
+impl DelegationScope {
+    /// Key used in the delegation stores. Property IDs start at 1, so 0 is
+    /// free to stand for all properties.
+    fn key(self) -> u64 {
+        match self {
+            DelegationScope::AllProperties => 0,
+            DelegationScope::Property(property_id) => property_id,
+        }
+    }
+
+    fn from_key(key: u64) -> Self {
+        match key {
+            0 => DelegationScope::AllProperties,
+            property_id => DelegationScope::Property(property_id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Delegation {
+    pub delegator: Principal,
+    pub delegate: Principal,
+    pub scope: DelegationScope,
+}
+
+impl Versioned for Proposal {
+    const VERSION: u8 = 7;
+
+    fn decode_previous(version: u8, payload: &[u8]) -> Self {
+        let v3: legacy::ProposalV3 = match version {
+            0 | 1 => legacy::ProposalV2::from(
+                schema::decode_payload::<legacy::ProposalV1>(version, payload),
+            )
+            .into(),
+            2 => schema::decode_payload::<legacy::ProposalV2>(version, payload).into(),
+            3 => schema::decode_payload(version, payload),
+            4 => return legacy::ProposalV5::from(
+                schema::decode_payload::<legacy::ProposalV4>(version, payload),
+            )
+            .into(),
+            _ => return schema::decode_payload::<legacy::ProposalV5>(version, payload).into(),
+        };
+        legacy::ProposalV5::from(legacy::ProposalV4::from(v3)).into()
+    }
+}
+
+versioned_storable!(Proposal);
+
+impl Versioned for Vote {
+    const VERSION: u8 = 3;
+
+    fn decode_previous(version: u8, payload: &[u8]) -> Self {
+        let v2: legacy::VoteV2 = match version {
+            0 | 1 => schema::decode_payload::<legacy::VoteV1>(version, payload).into(),
+            _ => schema::decode_payload(version, payload),
+        };
+        v2.into()
+    }
 }
 
+versioned_storable!(Vote);
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct CreateProposalRequest {
     pub property_id: u64,
@@ -46,22 +241,53 @@ pub struct CreateProposalRequest {
     pub description: String,
     pub proposal_type: String,
     pub voting_duration_days: u64,
-    pub execution_data: Option<String>,
+    pub action: Option<ProposalAction>,
+    pub kind: ProposalKind,
+    pub options: Vec<String>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct CastVoteRequest {
     pub proposal_id: u64,
-    pub vote_choice: bool,
-    pub voting_power: u64,
+    pub vote_choice: bool, // Ignored for multi-option proposals
+    pub selection: Vec<u32>, // Empty for yes/no proposals
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct ProposalResult {
     pub proposal: Proposal,
     pub total_voting_power: u64,
-    pub participation_rate: f64,
-    pub approval_rate: f64,
+    pub eligible_voting_power: u64,
+    pub participation_rate: f64, // Voted power over eligible power
+    pub approval_rate: f64, // Power in favour over voted power
+    pub quorum_reached: bool,
+    pub threshold_reached: bool,
+    pub time_remaining: u64, // Nanoseconds until voting closes, 0 once closed
+    pub projected_outcome: ProposalStatus, // Final status once voting has closed
+    pub option_results: Vec<OptionResult>, // Empty for yes/no proposals
+    pub leading_option: Option<u32>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct OptionResult {
+    pub option: u32,
+    pub label: String,
+    pub power: u64,
+    pub eliminated_in_round: Option<u32>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct TokenBalance {
+    holder: Principal,
+    tokens: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub investment_canister: Principal,
+    pub property_canister: Principal,
+    pub ledger_canister: Option<Principal>, // ICRC-2 ledger proposal deposits are paid on
+    pub roles: Vec<RoleAssignment>,
 }
 
 thread_local! {
@@ -86,245 +312,2374 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
         )
     );
+
+    static ROLES: RefCell<RoleStore<Memory>> = RefCell::new(
+        RoleStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+        )
+    );
+
+    static STORED_SCHEMA_VERSION: RefCell<SchemaVersionCell> = RefCell::new(
+        SchemaVersionCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
+            0,
+        ).expect("failed to initialize schema version")
+    );
+
+    static BALLOT_INDEX: RefCell<BallotIndex> = RefCell::new(
+        BallotIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+        )
+    );
+
+    static VOTER_INDEX: RefCell<VoterIndex> = RefCell::new(
+        VoterIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+        )
+    );
+
+    static PROPERTY_PROPOSAL_INDEX: RefCell<PropertyProposalIndex> = RefCell::new(
+        PropertyProposalIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+        )
+    );
+
+    static CANISTER_REFS: RefCell<CanisterRefStore> = RefCell::new(
+        CanisterRefStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+        )
+    );
+
+    static SNAPSHOT_STORAGE: RefCell<SnapshotStore> = RefCell::new(
+        SnapshotStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+        )
+    );
+
+    static TALLY_RULES: RefCell<TallyRuleStore> = RefCell::new(
+        TallyRuleStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+        )
+    );
+
+    static DEADLINE_INDEX: RefCell<DeadlineIndex> = RefCell::new(
+        DeadlineIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+        )
+    );
+
+    static TIMELOCKS: RefCell<TimelockStore> = RefCell::new(
+        TimelockStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+        )
+    );
+
+    static QUEUE_INDEX: RefCell<QueueIndex> = RefCell::new(
+        QueueIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+        )
+    );
+
+    static VETO_BALLOTS: RefCell<VetoBallotStore> = RefCell::new(
+        VetoBallotStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+        )
+    );
+
+    static DELEGATIONS: RefCell<DelegationStore> = RefCell::new(
+        DelegationStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+        )
+    );
+
+    static DELEGATE_INDEX: RefCell<DelegateIndex> = RefCell::new(
+        DelegateIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+        )
+    );
+
+    static DELEGATED_BALLOTS: RefCell<DelegatedBallotIndex> = RefCell::new(
+        DelegatedBallotIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+        )
+    );
+
+    // Superseded by GOVERNANCE_CONFIGS; only read when migrating to schema v10
+    static VOTING_MODES: RefCell<VotingModeStore> = RefCell::new(
+        VotingModeStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+        )
+    );
+
+    static PROPOSER_ACTIVITY: RefCell<ProposerActivityStore> = RefCell::new(
+        ProposerActivityStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+        )
+    );
+
+    static COMMENT_STORAGE: RefCell<CommentStore> = RefCell::new(
+        CommentStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+        )
+    );
+
+    static PROPOSAL_COMMENT_INDEX: RefCell<ProposalCommentIndex> = RefCell::new(
+        ProposalCommentIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+        )
+    );
+
+    static REVISIONS: RefCell<RevisionStore> = RefCell::new(
+        RevisionStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+        )
+    );
+
+    static GOVERNANCE_CONFIGS: RefCell<GovernanceConfigStore> = RefCell::new(
+        GovernanceConfigStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+        )
+    );
+
+    static EVENT_LOG: RefCell<EventLog> = RefCell::new(
+        EventLog::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+        )
+    );
+
+    static VOTE_HISTORY_INDEX: RefCell<VoteHistoryIndex> = RefCell::new(
+        VoteHistoryIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+        )
+    );
+
+    // Proposals whose action is being carried out; an execution spans awaits
+    static EXECUTING: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
 }
 
 #[init]
-fn init() {
+fn init(args: InitArgs) {
+    set_schema_version(SCHEMA_VERSION);
+
     // Initialize ID counters
     ID_COUNTER.with(|counter| {
         counter.borrow_mut().insert(0, 0); // proposal counter
         counter.borrow_mut().insert(1, 0); // vote counter
+        counter.borrow_mut().insert(2, 0); // comment counter
     });
-}
 
-#[update]
-fn create_proposal(req: CreateProposalRequest) -> Result<Proposal, String> {
-    let caller = ic_cdk::caller();
-    
-    // Generate new proposal ID
-    let proposal_id = ID_COUNTER.with(|counter| {
-        let mut counter = counter.borrow_mut();
-        let current_id = counter.get(&0).unwrap_or(0);
-        let new_id = current_id + 1;
-        counter.insert(0, new_id);
-        new_id
+    CANISTER_REFS.with(|refs| {
+        let mut refs = refs.borrow_mut();
+        refs.insert(INVESTMENT_CANISTER_KEY, args.investment_canister);
+        refs.insert(PROPERTY_CANISTER_KEY, args.property_canister);
+        if let Some(ledger_canister) = args.ledger_canister {
+            refs.insert(LEDGER_CANISTER_KEY, ledger_canister);
+        }
     });
 
-    let current_time = time();
-    let voting_deadline = current_time + (req.voting_duration_days * 24 * 60 * 60 * 1_000_000_000);
+    ROLES.with(|roles| access::assign_roles(&mut roles.borrow_mut(), args.roles));
 
-    let proposal = Proposal {
-        id: proposal_id,
-        property_id: req.property_id,
-        title: req.title,
-        description: req.description,
-        proposal_type: req.proposal_type,
-        proposer: caller,
-        votes_for: 0,
-        votes_against: 0,
-        voting_power_for: 0,
-        voting_power_against: 0,
-        status: "active".to_string(),
-        created_at: current_time,
-        voting_deadline,
-        execution_data: req.execution_data,
-    };
+    seed_tally_rules();
+    seed_timelocks();
+}
 
-    PROPOSAL_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(proposal_id, proposal.clone());
-    });
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "re-encode proposals and votes in the versioned record format",
+        run: reencode_records,
+    },
+    Migration {
+        version: 2,
+        description: "build ballot, voter and property proposal indexes",
+        run: rebuild_indexes,
+    },
+    Migration {
+        version: 3,
+        description: "re-encode proposals with eligible voting power",
+        run: reencode_records,
+    },
+    Migration {
+        version: 4,
+        description: "type proposal statuses, seed tally rules and index voting deadlines",
+        run: migrate_to_finalization,
+    },
+    Migration {
+        version: 5,
+        description: "re-encode proposals with typed actions",
+        run: reencode_records,
+    },
+    Migration {
+        version: 6,
+        description: "seed proposal timelocks",
+        run: seed_timelocks,
+    },
+    Migration {
+        version: 7,
+        description: "re-encode votes with delegated power",
+        run: reencode_records,
+    },
+    Migration {
+        version: 8,
+        description: "re-encode proposals and votes with multi-option ballots",
+        run: reencode_records,
+    },
+    Migration {
+        version: 9,
+        description: "re-encode proposals with their voting mode",
+        run: reencode_records,
+    },
+    Migration {
+        version: 10,
+        description: "create governance configs for properties with proposals or voting modes",
+        run: migrate_to_governance_configs,
+    },
+    Migration {
+        version: 11,
+        description: "index vote history by proposal and voter",
+        run: index_vote_history,
+    },
+];
 
-    Ok(proposal)
+#[post_upgrade]
+fn post_upgrade() {
+    let stored = STORED_SCHEMA_VERSION.with(|v| *v.borrow().get());
+    let migrated = schema::run_migrations(stored, MIGRATIONS);
+    assert_eq!(migrated, SCHEMA_VERSION, "missing migration to schema v{}", SCHEMA_VERSION);
+    set_schema_version(migrated);
+
+    // Timers do not survive an upgrade
+    schedule_pending_finalizations();
+    schedule_queued_releases();
 }
 
-#[update]
-fn cast_vote(req: CastVoteRequest) -> Result<Vote, String> {
-    let caller = ic_cdk::caller();
-    
-    // Check if proposal exists and is active
-    let proposal = PROPOSAL_STORAGE.with(|storage| {
-        storage.borrow().get(&req.proposal_id)
+fn set_schema_version(version: u32) {
+    STORED_SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(version)
+            .expect("failed to store schema version");
     });
+}
 
-    let proposal = match proposal {
-        Some(p) => p,
-        None => return Err("Proposal not found".to_string()),
-    };
-
-    if proposal.status != "active" {
-        return Err("Proposal is not active".to_string());
-    }
+/// Rewrites every record so that blobs stored before versioning existed are
+/// persisted in the current format.
+fn reencode_records() {
+    PROPOSAL_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let entries: Vec<_> = storage.iter().collect();
+        for (key, record) in entries {
+            storage.insert(key, record);
+        }
+    });
+    VOTE_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let entries: Vec<_> = storage.iter().collect();
+        for (key, record) in entries {
+            storage.insert(key, record);
+        }
+    });
+}
 
-    if time() > proposal.voting_deadline {
-        return Err("Voting deadline has passed".to_string());
-    }
+fn rebuild_indexes() {
+    PROPOSAL_STORAGE.with(|storage| {
+        for (_, proposal) in storage.borrow().iter() {
+            index_proposal(&proposal);
+            if proposal.status == ProposalStatus::Active {
+                index_deadline(&proposal);
+            }
+        }
+    });
+    VOTE_STORAGE.with(|storage| {
+        for (_, vote) in storage.borrow().iter() {
+            index_vote(&vote);
+        }
+    });
+}
 
-    // Check if user already voted
-    let existing_vote = VOTE_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .find(|(_, vote)| {
-                vote.proposal_id == req.proposal_id && vote.voter == caller
-            })
-            .map(|(_, vote)| vote)
+/// Only the history index is built: retracted votes must stay out of the
+/// ballot index.
+fn index_vote_history() {
+    VOTE_STORAGE.with(|storage| {
+        for (_, vote) in storage.borrow().iter() {
+            VOTE_HISTORY_INDEX.with(|index| {
+                index.borrow_mut().insert((vote.proposal_id, vote.voter, vote.id), ());
+            });
+        }
     });
+}
 
-    if existing_vote.is_some() {
-        return Err("User has already voted on this proposal".to_string());
-    }
+fn migrate_to_finalization() {
+    seed_tally_rules();
+    reencode_records();
+    rebuild_indexes();
+}
 
-    // Generate new vote ID
-    let vote_id = ID_COUNTER.with(|counter| {
-        let mut counter = counter.borrow_mut();
-        let current_id = counter.get(&1).unwrap_or(0);
-        let new_id = current_id + 1;
-        counter.insert(1, new_id);
-        new_id
+fn seed_tally_rules() {
+    TALLY_RULES.with(|rules| {
+        let mut rules = rules.borrow_mut();
+        for (proposal_type, default) in tally::DEFAULT_RULES {
+            if !rules.contains_key(&proposal_type.to_string()) {
+                rules.insert(proposal_type.to_string(), *default);
+            }
+        }
     });
+}
 
-    // Create vote record
-    let vote = Vote {
-        id: vote_id,
-        proposal_id: req.proposal_id,
-        voter: caller,
-        vote_power: req.voting_power,
-        vote_choice: req.vote_choice,
-        timestamp: time(),
-    };
+/// Gives every property governance has been used on a stored config, so
+/// that later changes to the defaults only affect new properties.
+fn migrate_to_governance_configs() {
+    seed_tally_rules();
+    seed_timelocks();
 
-    // Store vote
-    VOTE_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(vote_id, vote.clone());
+    let modes: Vec<(u64, VotingMode)> = VOTING_MODES.with(|modes| modes.borrow().iter().collect());
+    let mut property_ids: BTreeSet<u64> = modes.iter().map(|(property_id, _)| *property_id).collect();
+    PROPERTY_PROPOSAL_INDEX.with(|index| {
+        property_ids.extend(index.borrow().iter().map(|((property_id, _), _)| property_id));
+    });
+    // The admin defaults were what these properties used until now
+    for property_id in property_ids {
+        register_governance_config(property_id);
+    }
+    for (property_id, voting_mode) in modes {
+        let mut config = governance_config_for(property_id);
+        config.voting_mode = voting_mode;
+        GOVERNANCE_CONFIGS.with(|configs| configs.borrow_mut().insert(property_id, config));
+    }
+    VOTING_MODES.with(|modes| {
+        let mut modes = modes.borrow_mut();
+        let keys: Vec<u64> = modes.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            modes.remove(&key);
+        }
     });
+}
 
-    // Update proposal vote counts
-    PROPOSAL_STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
-        if let Some(mut proposal) = storage.get(&req.proposal_id) {
-            if req.vote_choice {
-                proposal.votes_for += 1;
-                proposal.voting_power_for += req.voting_power;
-            } else {
-                proposal.votes_against += 1;
-                proposal.voting_power_against += req.voting_power;
+fn seed_timelocks() {
+    TIMELOCKS.with(|timelocks| {
+        let mut timelocks = timelocks.borrow_mut();
+        for (proposal_type, seconds) in DEFAULT_TIMELOCKS {
+            if !timelocks.contains_key(&proposal_type.to_string()) {
+                timelocks.insert(proposal_type.to_string(), *seconds);
             }
-            storage.insert(req.proposal_id, proposal);
         }
     });
-
-    Ok(vote)
 }
 
 #[query]
-fn get_proposals() -> Vec<Proposal> {
-    PROPOSAL_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .map(|(_, proposal)| proposal)
-            .collect()
-    })
+fn get_schema_version() -> u32 {
+    STORED_SCHEMA_VERSION.with(|v| *v.borrow().get())
 }
 
-#[query]
-fn get_active_proposals() -> Vec<Proposal> {
-    let current_time = time();
-    PROPOSAL_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, proposal)| {
-                proposal.status == "active" && proposal.voting_deadline > current_time
-            })
-            .map(|(_, proposal)| proposal)
-            .collect()
-    })
+fn require_role(role: Role) -> Result<()> {
+    ROLES.with(|roles| access::require_role(&roles.borrow(), role))
 }
 
-#[query]
-fn get_proposal(proposal_id: u64) -> Option<Proposal> {
-    PROPOSAL_STORAGE.with(|storage| {
-        storage.borrow().get(&proposal_id)
-    })
+#[update]
+fn grant_role(assignment: RoleAssignment) -> Result<()> {
+    ROLES.with(|roles| access::grant_role(&mut roles.borrow_mut(), assignment))
 }
 
-#[query]
-fn get_property_proposals(property_id: u64) -> Vec<Proposal> {
-    PROPOSAL_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, proposal)| proposal.property_id == property_id)
-            .map(|(_, proposal)| proposal)
-            .collect()
-    })
+#[update]
+fn revoke_role(assignment: RoleAssignment) -> Result<()> {
+    ROLES.with(|roles| access::revoke_role(&mut roles.borrow_mut(), assignment))
 }
 
 #[query]
-fn get_proposal_votes(proposal_id: u64) -> Vec<Vote> {
-    VOTE_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, vote)| vote.proposal_id == proposal_id)
-            .map(|(_, vote)| vote)
-            .collect()
+fn get_roles(principal: Principal) -> Vec<Role> {
+    ROLES.with(|roles| access::get_roles(&roles.borrow(), &principal))
+}
+
+fn investment_canister_id() -> Result<Principal> {
+    CANISTER_REFS.with(|refs| {
+        refs.borrow()
+            .get(&INVESTMENT_CANISTER_KEY)
+            .ok_or_else(|| Error::internal("investment canister is not configured"))
     })
 }
 
-#[query]
-fn get_user_votes(user_id: Principal) -> Vec<Vote> {
-    VOTE_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, vote)| vote.voter == user_id)
-            .map(|(_, vote)| vote)
-            .collect()
+fn property_canister_id() -> Result<Principal> {
+    CANISTER_REFS.with(|refs| {
+        refs.borrow()
+            .get(&PROPERTY_CANISTER_KEY)
+            .ok_or_else(|| Error::internal("property canister is not configured"))
     })
 }
 
+/// Sets the property canister proposal actions are carried out against, for
+/// canisters installed before it was part of `InitArgs`.
 #[update]
-fn update_proposal_status(proposal_id: u64, new_status: String) -> Result<Proposal, String> {
-    PROPOSAL_STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
-        if let Some(mut proposal) = storage.get(&proposal_id) {
-            proposal.status = new_status;
-            storage.insert(proposal_id, proposal.clone());
-            Ok(proposal)
-        } else {
-            Err("Proposal not found".to_string())
-        }
-    })
+fn set_property_canister(property_canister: Principal) -> Result<()> {
+    require_role(Role::Controller)?;
+    CANISTER_REFS.with(|refs| {
+        refs.borrow_mut().insert(PROPERTY_CANISTER_KEY, property_canister);
+    });
+    Ok(())
 }
 
-#[query]
-fn get_proposal_result(proposal_id: u64) -> Option<ProposalResult> {
-    let proposal = get_proposal(proposal_id)?;
-    
-    let total_voting_power = proposal.voting_power_for + proposal.voting_power_against;
-    let total_votes = proposal.votes_for + proposal.votes_against;
-    
-    let participation_rate = if total_votes > 0 {
-        total_votes as f64 / 100.0 // Simplified calculation
-    } else {
+fn ledger_canister_id() -> Option<Principal> {
+    CANISTER_REFS.with(|refs| refs.borrow().get(&LEDGER_CANISTER_KEY))
+}
+
+/// Sets the ICRC-2 ledger proposal deposits are paid on. Proposals created
+/// while no ledger is configured take no deposit.
+#[update]
+fn set_ledger_canister(ledger_canister: Principal) -> Result<()> {
+    require_role(Role::Controller)?;
+    CANISTER_REFS.with(|refs| {
+        refs.borrow_mut().insert(LEDGER_CANISTER_KEY, ledger_canister);
+    });
+    Ok(())
+}
+
+async fn ensure_property_exists(property_id: u64) -> Result<()> {
+    let (property,): (Option<candid::Reserved>,) =
+        ic_cdk::call(property_canister_id()?, "get_property", (property_id,))
+            .await
+            .map_err(|err| Error::call_failed("get_property", err))?;
+    property.map(|_| ()).ok_or_else(|| Error::not_found("property"))
+}
+
+/// Number of tokens of `property_id` held by `user`, read from the investment
+/// ledger. This is the user's voting power on the property.
+async fn holder_tokens(user: Principal, property_id: u64) -> Result<u64> {
+    let (tokens,): (u64,) = ic_cdk::call(
+        investment_canister_id()?,
+        "get_user_tokens_for_property",
+        (user, property_id),
+    )
+    .await
+    .map_err(|err| Error::call_failed("get_user_tokens_for_property", err))?;
+    Ok(tokens)
+}
+
+/// Balances of every holder of `property_id`, read page by page from the
+/// investment ledger.
+async fn holder_snapshot(property_id: u64) -> Result<Vec<TokenBalance>> {
+    let investment_canister = investment_canister_id()?;
+    let mut balances = Vec::new();
+    let mut start_after = None;
+
+    loop {
+        let page = PageRequest {
+            start_after,
+            limit: Some(paging::MAX_PAGE_SIZE),
+            order: None,
+        };
+        let (page,): (Page<TokenBalance, Principal>,) =
+            ic_cdk::call(investment_canister, "get_property_holders", (property_id, page))
+                .await
+                .map_err(|err| Error::call_failed("get_property_holders", err))?;
+
+        balances.extend(page.items);
+        match page.next_cursor {
+            Some(cursor) => start_after = Some(cursor),
+            None => return Ok(balances),
+        }
+    }
+}
+
+/// Tokens of `property_id` currently held across all investors.
+async fn property_token_supply(property_id: u64) -> Result<u64> {
+    let (tokens,): (u64,) = ic_cdk::call(
+        investment_canister_id()?,
+        "get_total_tokens_by_property",
+        (property_id,),
+    )
+    .await
+    .map_err(|err| Error::call_failed("get_total_tokens_by_property", err))?;
+    Ok(tokens)
+}
+
+/// Voting power `voter` held in the snapshot taken for `proposal_id`.
+fn snapshot_power(proposal_id: u64, voter: Principal) -> u64 {
+    SNAPSHOT_STORAGE.with(|snapshots| {
+        snapshots.borrow().get(&(proposal_id, voter)).unwrap_or(0)
+    })
+}
+
+fn validate_proposal_text(title: &str, description: &str) -> Result<()> {
+    let title = title.trim();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(Error::invalid_input(
+            "title",
+            &format!("must be 1 to {} characters", MAX_TITLE_LENGTH),
+        ));
+    }
+    let description = description.trim();
+    if description.is_empty() || description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        return Err(Error::invalid_input(
+            "description",
+            &format!("must be 1 to {} characters", MAX_DESCRIPTION_LENGTH),
+        ));
+    }
+    Ok(())
+}
+
+/// Creates a proposal and snapshots every holder's balance of the property,
+/// which fixes each holder's voting power under the property's voting mode
+/// for the life of the proposal. The property's governance config decides
+/// the allowed types and durations, the proposer's minimum holding and the
+/// deposit, which is only taken when a deposit ledger is configured.
+/// Proposers may propose once per `PROPOSAL_COOLDOWN_NANOS` on a property.
+#[update]
+async fn create_proposal(req: CreateProposalRequest) -> Result<Proposal> {
+    let caller = ic_cdk::caller();
+    validate_proposal_text(&req.title, &req.description)?;
+    let config = governance_config_for(req.property_id);
+    config.validate_voting_duration(req.voting_duration_days)?;
+    config.proposal_type(&req.proposal_type)?;
+    if let Some(action) = &req.action {
+        if req.kind != ProposalKind::YesNo {
+            return Err(Error::invalid_input("action", "only yes/no proposals can carry an action"));
+        }
+        action.validate(&req.proposal_type)?;
+    }
+    ballots::validate_options(req.kind, &req.options)?;
+
+    // Claim the proposer's slot before awaiting so concurrent calls cannot
+    // slip past the rate limit; it is given back if the proposal fails.
+    let activity_key = (caller, req.property_id);
+    let now = time();
+    let last_proposal = PROPOSER_ACTIVITY.with(|activity| activity.borrow().get(&activity_key));
+    if last_proposal.is_some_and(|last| now < last + PROPOSAL_COOLDOWN_NANOS) {
+        return Err(Error::invalid_state("proposer must wait before proposing on this property again"));
+    }
+    PROPOSER_ACTIVITY.with(|activity| activity.borrow_mut().insert(activity_key, now));
+
+    let result = open_proposal_for_vote(caller, req, config).await;
+    if result.is_err() {
+        PROPOSER_ACTIVITY.with(|activity| {
+            let mut activity = activity.borrow_mut();
+            match last_proposal {
+                Some(last) => activity.insert(activity_key, last),
+                None => activity.remove(&activity_key),
+            };
+        });
+    }
+    result
+}
+
+async fn open_proposal_for_vote(
+    caller: Principal,
+    req: CreateProposalRequest,
+    config: GovernanceConfig,
+) -> Result<Proposal> {
+    ensure_property_exists(req.property_id).await?;
+    ensure_governance_config(req.property_id);
+
+    let snapshot = holder_snapshot(req.property_id).await?;
+    let proposer_tokens = snapshot
+        .iter()
+        .find(|balance| balance.holder == caller)
+        .map_or(0, |balance| balance.tokens);
+    if proposer_tokens < config.min_proposer_tokens.max(1) {
+        return Err(Error::unauthorized(&format!(
+            "proposers must hold at least {} of the property's tokens",
+            config.min_proposer_tokens.max(1)
+        )));
+    }
+
+    // Generate new proposal ID; taken before the deposit so the transfer
+    // carries it as its memo
+    let proposal_id = ID_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        let current_id = counter.get(&0).unwrap_or(0);
+        let new_id = current_id + 1;
+        counter.insert(0, new_id);
+        new_id
+    });
+
+    let deposit = match ledger_canister_id() {
+        Some(ledger) if config.proposal_deposit > 0 => {
+            icrc::transfer_from(
+                ledger,
+                Account::of(caller),
+                Account::of(ic_cdk::id()),
+                config.proposal_deposit,
+                icrc::memo("deposit", proposal_id),
+                time(),
+            )
+            .await?;
+            Some(Deposit {
+                amount: config.proposal_deposit,
+                status: DepositStatus::Held,
+                unconfirmed_refund: None,
+            })
+        }
+        _ => None,
+    };
+
+    let tally_rules = config.proposal_type(&req.proposal_type)?.tally_rules;
+    let voting_mode = config.voting_mode;
+    let snapshot: Vec<(Principal, u64)> = snapshot
+        .into_iter()
+        .map(|balance| (balance.holder, voting_mode.power(balance.tokens)))
+        .collect();
+    let eligible_voting_power = snapshot.iter().map(|(_, power)| power).sum();
+
+    let current_time = time();
+    let voting_deadline = current_time + (req.voting_duration_days * 24 * 60 * 60 * 1_000_000_000);
+
+    let proposal = Proposal {
+        id: proposal_id,
+        property_id: req.property_id,
+        title: req.title.trim().to_string(),
+        description: req.description.trim().to_string(),
+        proposal_type: req.proposal_type,
+        proposer: caller,
+        votes_for: 0,
+        votes_against: 0,
+        voting_power_for: 0,
+        voting_power_against: 0,
+        status: ProposalStatus::Active,
+        created_at: current_time,
+        voting_deadline,
+        eligible_voting_power,
+        tally_rules,
+        action: req.action,
+        execution: None,
+        executable_at: None,
+        kind: req.kind,
+        options: req.options.iter().map(|option| option.trim().to_string()).collect(),
+        winning_option: None,
+        voting_mode,
+        deposit,
+        first_vote_at: None,
+    };
+
+    PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(proposal_id, proposal.clone());
+    });
+    index_proposal(&proposal);
+    index_deadline(&proposal);
+    schedule_finalization(proposal_id, voting_deadline);
+    record_event(proposal_id, GovernanceEventKind::ProposalCreated {
+        property_id: proposal.property_id,
+        proposer: caller,
+        proposal_type: proposal.proposal_type.clone(),
+    });
+
+    SNAPSHOT_STORAGE.with(|snapshots| {
+        let mut snapshots = snapshots.borrow_mut();
+        for (holder, power) in snapshot {
+            snapshots.insert((proposal_id, holder), power);
+        }
+    });
+
+    Ok(proposal)
+}
+
+/// Configuration stored for properties registered from now on, built from
+/// the defaults admins maintain with `set_tally_rules` and `set_timelock`.
+fn default_governance_config() -> GovernanceConfig {
+    let proposal_types = TALLY_RULES.with(|rules| {
+        rules
+            .borrow()
+            .iter()
+            .map(|(proposal_type, tally_rules)| {
+                let timelock_seconds = TIMELOCKS.with(|timelocks| {
+                    timelocks.borrow().get(&proposal_type).unwrap_or(0)
+                });
+                ProposalTypeConfig {
+                    proposal_type,
+                    tally_rules,
+                    timelock_seconds,
+                }
+            })
+            .collect()
+    });
+    GovernanceConfig::with_types(proposal_types)
+}
+
+/// Configuration of properties that were never registered, built from the
+/// built-in rules and timelocks only, so that changes to the admin defaults
+/// never reach a property without a config of its own.
+fn baseline_governance_config() -> GovernanceConfig {
+    let proposal_types = tally::DEFAULT_RULES
+        .iter()
+        .map(|(proposal_type, tally_rules)| ProposalTypeConfig {
+            proposal_type: proposal_type.to_string(),
+            tally_rules: *tally_rules,
+            timelock_seconds: DEFAULT_TIMELOCKS
+                .iter()
+                .find(|(timelock_type, _)| timelock_type == proposal_type)
+                .map_or(0, |(_, seconds)| *seconds),
+        })
+        .collect();
+    GovernanceConfig::with_types(proposal_types)
+}
+
+/// Active configuration of `property_id`. Properties that were never
+/// registered use the baseline until governance is first used on them.
+fn governance_config_for(property_id: u64) -> GovernanceConfig {
+    GOVERNANCE_CONFIGS
+        .with(|configs| configs.borrow().get(&property_id))
+        .unwrap_or_else(baseline_governance_config)
+}
+
+/// Stores the configuration `property_id` is using, so that it can only
+/// change through a config proposal from now on.
+fn ensure_governance_config(property_id: u64) -> GovernanceConfig {
+    store_governance_config_if_missing(property_id, governance_config_for(property_id))
+}
+
+/// Stores the current admin defaults for a newly registered property.
+fn register_governance_config(property_id: u64) -> GovernanceConfig {
+    store_governance_config_if_missing(property_id, default_governance_config())
+}
+
+fn store_governance_config_if_missing(property_id: u64, config: GovernanceConfig) -> GovernanceConfig {
+    GOVERNANCE_CONFIGS.with(|configs| {
+        let mut configs = configs.borrow_mut();
+        match configs.get(&property_id) {
+            Some(stored) => stored,
+            None => {
+                configs.insert(property_id, config.clone());
+                config
+            }
+        }
+    })
+}
+
+/// Replaces the configuration of `property_id`. Only executed by a passed
+/// config change proposal; proposals already open keep their rules.
+fn apply_governance_config(property_id: u64, config: GovernanceConfig) -> Result<()> {
+    config.validate()?;
+    GOVERNANCE_CONFIGS.with(|configs| {
+        configs.borrow_mut().insert(property_id, config);
+    });
+    Ok(())
+}
+
+/// Stores the default configuration for a newly created property. Called by
+/// the property canister when the property is created.
+#[update]
+fn init_governance_config(property_id: u64) -> Result<GovernanceConfig> {
+    if ic_cdk::caller() != property_canister_id()? {
+        require_role(Role::Admin)?;
+    }
+    Ok(register_governance_config(property_id))
+}
+
+#[query]
+fn get_governance_config(property_id: u64) -> GovernanceConfig {
+    governance_config_for(property_id)
+}
+
+fn index_proposal(proposal: &Proposal) {
+    PROPERTY_PROPOSAL_INDEX.with(|index| {
+        index.borrow_mut().insert((proposal.property_id, proposal.id), ());
+    });
+}
+
+fn index_deadline(proposal: &Proposal) {
+    DEADLINE_INDEX.with(|index| {
+        index.borrow_mut().insert((proposal.voting_deadline, proposal.id), ());
+    });
+}
+
+/// Arms a timer that finalizes `proposal_id` once `voting_deadline` has passed.
+fn schedule_finalization(proposal_id: u64, voting_deadline: u64) {
+    let delay = voting_deadline.saturating_sub(time()) + 1;
+    ic_cdk_timers::set_timer(Duration::from_nanos(delay), move || {
+        if let Err(err) = finalize(proposal_id) {
+            ic_cdk::println!("failed to finalize proposal {}: {}", proposal_id, err);
+        }
+    });
+}
+
+fn schedule_pending_finalizations() {
+    let pending: Vec<(u64, u64)> = DEADLINE_INDEX.with(|index| {
+        index.borrow().iter().map(|(key, _)| key).collect()
+    });
+    for (voting_deadline, proposal_id) in pending {
+        schedule_finalization(proposal_id, voting_deadline);
+    }
+}
+
+/// Closes voting on an active proposal whose deadline has passed. Approved
+/// proposals are queued for their type's timelock; the rest are rejected.
+fn finalize(proposal_id: u64) -> Result<Proposal> {
+    let mut proposal = get_proposal(proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+
+    if proposal.status != ProposalStatus::Active {
+        return Err(Error::invalid_state("proposal is not active"));
+    }
+    if time() <= proposal.voting_deadline {
+        return Err(Error::invalid_state("voting is still open"));
+    }
+
+    let (outcome, winning_option) = decide(&proposal);
+    proposal.winning_option = winning_option;
+
+    let cast = proposal.voting_power_for + proposal.voting_power_against;
+    let quorum_reached = proposal.tally_rules.quorum_reached(cast, proposal.eligible_voting_power);
+    let refund_due = match &mut proposal.deposit {
+        Some(deposit) if deposit.status == DepositStatus::Held => {
+            if !quorum_reached {
+                deposit.status = DepositStatus::Forfeited;
+            }
+            quorum_reached
+        }
+        _ => false,
+    };
+
+    if outcome == ProposalStatus::Passed {
+        // Types removed from the config since the proposal was made have no delay
+        let timelock = governance_config_for(proposal.property_id)
+            .proposal_type(&proposal.proposal_type)
+            .map_or(0, |config| config.timelock_seconds);
+        let executable_at = timelock_end(time(), timelock);
+        proposal.status = ProposalStatus::Queued;
+        proposal.executable_at = Some(executable_at);
+        QUEUE_INDEX.with(|index| {
+            index.borrow_mut().insert((executable_at, proposal_id), ());
+        });
+        schedule_release(proposal_id, executable_at);
+    } else {
+        proposal.status = ProposalStatus::Rejected;
+    }
+
+    PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(proposal_id, proposal.clone());
+    });
+    DEADLINE_INDEX.with(|index| {
+        index.borrow_mut().remove(&(proposal.voting_deadline, proposal_id));
+    });
+    record_event(proposal_id, GovernanceEventKind::ProposalFinalized {
+        status: proposal.status,
+        winning_option: proposal.winning_option,
+    });
+
+    if refund_due {
+        ic_cdk::spawn(async move {
+            if let Err(err) = refund_deposit(proposal_id).await {
+                ic_cdk::println!("failed to refund deposit of proposal {}: {}", proposal_id, err);
+            }
+        });
+    }
+
+    Ok(proposal)
+}
+
+/// Returns the deposit of a finalized proposal that reached quorum to its
+/// proposer, less the ledger fee for the transfer. A refund whose outcome is
+/// unknown is resent unchanged by the next attempt, so the ledger can
+/// recognise it as a duplicate.
+async fn refund_deposit(proposal_id: u64) -> Result<Proposal> {
+    held_deposit(proposal_id)?;
+    let ledger = ledger_canister_id().ok_or_else(|| Error::internal("deposit ledger is not configured"))?;
+    let fee = icrc::fee(ledger).await?;
+
+    // Read again after the await
+    let (mut proposal, deposit) = held_deposit(proposal_id)?;
+    let transfer = deposit.unconfirmed_refund.unwrap_or(LedgerTransfer {
+        amount: deposit.amount.saturating_sub(fee),
+        created_at_time: time(),
+    });
+    // Mark the deposit refunded before awaiting so it cannot be paid twice
+    set_deposit(&mut proposal, DepositStatus::Refunded, Some(transfer.clone()));
+    let refunded = icrc::transfer(
+        ledger,
+        None,
+        Account::of(proposal.proposer),
+        transfer.amount,
+        icrc::memo("refund", proposal_id),
+        transfer.created_at_time,
+    )
+    .await;
+    let mut proposal = get_proposal(proposal_id).unwrap_or(proposal);
+    match refunded {
+        Ok(_) => {
+            set_deposit(&mut proposal, DepositStatus::Refunded, None);
+            Ok(proposal)
+        }
+        Err(err) => {
+            let unconfirmed = icrc::outcome_unknown(&err).then_some(transfer);
+            set_deposit(&mut proposal, DepositStatus::Held, unconfirmed);
+            Err(err)
+        }
+    }
+}
+
+/// A finalized proposal whose deposit is still held, with the deposit.
+fn held_deposit(proposal_id: u64) -> Result<(Proposal, Deposit)> {
+    let proposal = get_proposal(proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+    if proposal.status == ProposalStatus::Active {
+        return Err(Error::invalid_state("proposal is still open for voting"));
+    }
+    match proposal.deposit.clone() {
+        Some(deposit) if deposit.status == DepositStatus::Held => Ok((proposal, deposit)),
+        _ => Err(Error::invalid_state("proposal has no deposit to refund")),
+    }
+}
+
+fn set_deposit(proposal: &mut Proposal, status: DepositStatus, unconfirmed_refund: Option<LedgerTransfer>) {
+    if let Some(deposit) = &mut proposal.deposit {
+        deposit.status = status;
+        deposit.unconfirmed_refund = unconfirmed_refund;
+    }
+    PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(proposal.id, proposal.clone());
+    });
+}
+
+/// Outcome of `proposal` if voting closed with its current ballots, and the
+/// winning option of a multi-option proposal. Multi-option proposals pass
+/// when they reach quorum and one option wins outright.
+fn decide(proposal: &Proposal) -> (ProposalStatus, Option<u32>) {
+    if proposal.kind == ProposalKind::YesNo {
+        return (proposal.tally_rules.outcome(proposal), None);
+    }
+
+    let (_, winner) = option_tallies(proposal);
+    let cast = proposal.voting_power_for + proposal.voting_power_against;
+    if winner.is_some() && proposal.tally_rules.quorum_reached(cast, proposal.eligible_voting_power) {
+        (ProposalStatus::Passed, winner)
+    } else {
+        (ProposalStatus::Rejected, winner)
+    }
+}
+
+fn option_tallies(proposal: &Proposal) -> (Vec<OptionTally>, Option<u32>) {
+    let ballots: Vec<(u64, Vec<u32>)> = load_votes(current_vote_ids(proposal.id))
+        .into_iter()
+        .map(|(_, vote)| (vote.vote_power, vote.selection))
+        .collect();
+    ballots::tally(proposal.kind, proposal.options.len(), &ballots)
+}
+
+/// IDs of the votes currently standing on `proposal_id`, in ascending order.
+fn current_vote_ids(proposal_id: u64) -> Vec<u64> {
+    // The empty management canister principal sorts before every other principal
+    let mut ids: Vec<u64> = BALLOT_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((proposal_id, Principal::management_canister())..)
+            .take_while(|((id, _), _)| *id == proposal_id)
+            .map(|(_, vote_id)| vote_id)
+            .collect()
+    });
+    ids.sort_unstable();
+    ids
+}
+
+/// End of a timelock of `timelock_seconds` started at `now`.
+fn timelock_end(now: u64, timelock_seconds: u64) -> u64 {
+    now.saturating_add(timelock_seconds.saturating_mul(1_000_000_000))
+}
+
+/// Arms a timer that releases `proposal_id` once its timelock has ended.
+fn schedule_release(proposal_id: u64, executable_at: u64) {
+    let delay = executable_at.saturating_sub(time());
+    ic_cdk_timers::set_timer(Duration::from_nanos(delay), move || {
+        if let Err(err) = release(proposal_id) {
+            ic_cdk::println!("failed to release proposal {}: {}", proposal_id, err);
+        }
+    });
+}
+
+fn schedule_queued_releases() {
+    let queued: Vec<(u64, u64)> = QUEUE_INDEX.with(|index| {
+        index.borrow().iter().map(|(key, _)| key).collect()
+    });
+    for (executable_at, proposal_id) in queued {
+        schedule_release(proposal_id, executable_at);
+    }
+}
+
+/// Moves a queued proposal whose timelock has ended to `Passed` and starts
+/// executing its action, if it has one.
+fn release(proposal_id: u64) -> Result<Proposal> {
+    let mut proposal = get_proposal(proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+    ensure_releasable(&proposal, time())?;
+    let executable_at = proposal.executable_at.unwrap_or(0);
+
+    proposal.status = ProposalStatus::Passed;
+    PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(proposal_id, proposal.clone());
+    });
+    QUEUE_INDEX.with(|index| {
+        index.borrow_mut().remove(&(executable_at, proposal_id));
+    });
+
+    if proposal.action.is_some() {
+        ic_cdk::spawn(async move {
+            if let Err(err) = execute(proposal_id).await {
+                ic_cdk::println!("failed to execute proposal {}: {}", proposal_id, err);
+            }
+        });
+    }
+
+    Ok(proposal)
+}
+
+fn ensure_releasable(proposal: &Proposal, now: u64) -> Result<()> {
+    if proposal.status != ProposalStatus::Queued {
+        return Err(Error::invalid_state("proposal is not queued"));
+    }
+    if now < proposal.executable_at.unwrap_or(0) {
+        return Err(Error::invalid_state("timelock has not ended"));
+    }
+    Ok(())
+}
+
+/// Loads a proposal that can still be vetoed.
+fn vetoable_proposal(proposal_id: u64) -> Result<Proposal> {
+    let proposal = get_proposal(proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+    ensure_vetoable(&proposal, time())?;
+    Ok(proposal)
+}
+
+/// A queued proposal can be vetoed until its timelock ends.
+fn ensure_vetoable(proposal: &Proposal, now: u64) -> Result<()> {
+    if proposal.status != ProposalStatus::Queued {
+        return Err(Error::invalid_state("only queued proposals can be vetoed"));
+    }
+    if now >= proposal.executable_at.unwrap_or(0) {
+        return Err(Error::DeadlinePassed);
+    }
+    Ok(())
+}
+
+/// Whether holders behind a veto with `veto_power` reach the veto threshold
+/// of the `eligible` voting power.
+fn veto_threshold_reached(veto_power: u64, eligible: u64) -> bool {
+    veto_power as u128 * tally::BPS_SCALE as u128 >= eligible as u128 * VETO_THRESHOLD_BPS as u128
+}
+
+fn veto(mut proposal: Proposal) -> Proposal {
+    if let Some(executable_at) = proposal.executable_at {
+        QUEUE_INDEX.with(|index| {
+            index.borrow_mut().remove(&(executable_at, proposal.id));
+        });
+    }
+    proposal.status = ProposalStatus::Vetoed;
+    PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(proposal.id, proposal.clone());
+    });
+    record_event(proposal.id, GovernanceEventKind::ProposalFinalized {
+        status: ProposalStatus::Vetoed,
+        winning_option: None,
+    });
+    proposal
+}
+
+/// Voting power holders have put behind vetoing `proposal_id`.
+fn veto_power(proposal_id: u64) -> u64 {
+    VETO_BALLOTS.with(|ballots| {
+        ballots
+            .borrow()
+            .range((proposal_id, Principal::management_canister())..)
+            .take_while(|((id, _), _)| *id == proposal_id)
+            .map(|(_, power)| power)
+            .sum()
+    })
+}
+
+/// Carries out the action of a passed proposal and records the attempt. A
+/// successful execution moves the proposal to `Executed`.
+async fn execute(proposal_id: u64) -> Result<Proposal> {
+    let proposal = get_proposal(proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+    if proposal.status != ProposalStatus::Passed {
+        return Err(Error::invalid_state("only passed proposals can be executed"));
+    }
+    let action = proposal
+        .action
+        .ok_or_else(|| Error::invalid_state("proposal has no action to execute"))?;
+
+    let claimed = EXECUTING.with(|executing| executing.borrow_mut().insert(proposal_id));
+    if !claimed {
+        return Err(Error::invalid_state("proposal is already being executed"));
+    }
+    let result = action.execute(proposal.property_id).await;
+    EXECUTING.with(|executing| executing.borrow_mut().remove(&proposal_id));
+    record_event(proposal_id, match &result {
+        Ok(()) => GovernanceEventKind::ProposalExecuted,
+        Err(err) => GovernanceEventKind::ExecutionFailed { reason: err.to_string() },
+    });
+
+    PROPOSAL_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let mut proposal = storage.get(&proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+        record_execution(&mut proposal, &result, time());
+        storage.insert(proposal_id, proposal.clone());
+        Ok(proposal)
+    })
+}
+
+/// Records an execution attempt made at `now`. A failed attempt leaves the
+/// proposal `Passed` so that it can be retried.
+fn record_execution(proposal: &mut Proposal, result: &Result<()>, now: u64) {
+    proposal.execution = Some(ExecutionRecord {
+        attempted_at: now,
+        result: match result {
+            Ok(()) => ExecutionResult::Succeeded,
+            Err(err) => ExecutionResult::Failed(err.to_string()),
+        },
+    });
+    if result.is_ok() {
+        proposal.status = ProposalStatus::Executed;
+    }
+}
+
+fn index_vote(vote: &Vote) {
+    BALLOT_INDEX.with(|index| {
+        index.borrow_mut().insert((vote.proposal_id, vote.voter), vote.id);
+    });
+    VOTER_INDEX.with(|index| {
+        index.borrow_mut().insert((vote.voter, vote.id), ());
+    });
+    VOTE_HISTORY_INDEX.with(|index| {
+        index.borrow_mut().insert((vote.proposal_id, vote.voter, vote.id), ());
+    });
+}
+
+fn load_votes(ids: Vec<u64>) -> Vec<(u64, Vote)> {
+    VOTE_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter()
+            .filter_map(|id| storage.get(&id).map(|vote| (id, vote)))
+            .collect()
+    })
+}
+
+/// Loads a proposal that is still open for voting.
+fn open_proposal(proposal_id: u64) -> Result<Proposal> {
+    let proposal = PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow().get(&proposal_id)
+    });
+
+    let proposal = match proposal {
+        Some(p) => p,
+        None => return Err(Error::not_found("proposal")),
+    };
+
+    if proposal.status != ProposalStatus::Active {
+        return Err(Error::invalid_state("proposal is not active"));
+    }
+
+    if time() > proposal.voting_deadline {
+        return Err(Error::DeadlinePassed);
+    }
+
+    Ok(proposal)
+}
+
+/// Retracts `voter`'s current vote on `proposal_id`, taking its power out of
+/// the tallies. The vote record is kept, marked as retracted, so the voter's
+/// history stays auditable. Delegated power the vote carried is released and
+/// can be carried by the voter's next vote.
+fn retract_vote(proposal_id: u64, voter: Principal, now: u64) -> Option<Vote> {
+    let vote_id = BALLOT_INDEX.with(|index| index.borrow_mut().remove(&(proposal_id, voter)))?;
+    let mut vote = VOTE_STORAGE.with(|storage| storage.borrow().get(&vote_id))?;
+
+    vote.retracted_at = Some(now);
+    VOTE_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(vote_id, vote.clone());
+    });
+
+    DELEGATED_BALLOTS.with(|index| {
+        let mut index = index.borrow_mut();
+        let carried: Vec<_> = index
+            .range((proposal_id, Principal::management_canister())..)
+            .take_while(|((id, _), _)| *id == proposal_id)
+            .filter(|(_, carrying_vote)| *carrying_vote == vote_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in carried {
+            index.remove(&key);
+        }
+    });
+
+    PROPOSAL_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if let Some(mut proposal) = storage.get(&proposal_id) {
+            if vote.vote_choice {
+                proposal.votes_for -= 1;
+                proposal.voting_power_for -= vote.vote_power;
+            } else {
+                proposal.votes_against -= 1;
+                proposal.voting_power_against -= vote.vote_power;
+            }
+            storage.insert(proposal_id, proposal);
+        }
+    });
+
+    Some(vote)
+}
+
+/// The principal `delegator` has delegated its vote on `property_id` to.
+fn effective_delegate(delegator: Principal, property_id: u64) -> Option<Principal> {
+    DELEGATIONS.with(|delegations| {
+        let delegations = delegations.borrow();
+        delegations
+            .get(&(delegator, property_id))
+            .or_else(|| delegations.get(&(delegator, DelegationScope::AllProperties.key())))
+    })
+}
+
+/// Holders whose snapshot power on `proposal` `delegate` currently votes
+/// with, and that power. Delegation is not transitive: only holders who
+/// delegated to `delegate` directly count, and holders who voted themselves
+/// or whose power another vote already carries are left out.
+fn delegated_power_for(proposal: &Proposal, delegate: Principal) -> Vec<(Principal, u64)> {
+    if proposal.eligible_voting_power == 0 {
+        // Without a snapshot there is no fixed power to delegate
+        return Vec::new();
+    }
+
+    let scopes = [proposal.property_id, DelegationScope::AllProperties.key()];
+    let delegators: Vec<Principal> = DELEGATE_INDEX.with(|index| {
+        let index = index.borrow();
+        scopes
+            .iter()
+            .flat_map(|scope| {
+                index
+                    .range((delegate, *scope, Principal::management_canister())..)
+                    .take_while(|((d, s, _), _)| *d == delegate && s == scope)
+                    .map(|((_, _, delegator), _)| delegator)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    });
+
+    delegators
+        .into_iter()
+        .filter(|delegator| effective_delegate(*delegator, proposal.property_id) == Some(delegate))
+        .filter(|delegator| {
+            let key = (proposal.id, *delegator);
+            !BALLOT_INDEX.with(|index| index.borrow().contains_key(&key))
+                && !DELEGATED_BALLOTS.with(|index| index.borrow().contains_key(&key))
+        })
+        .map(|delegator| (delegator, snapshot_power(proposal.id, delegator)))
+        .filter(|(_, power)| *power > 0)
+        .collect()
+}
+
+/// Takes `voter`'s power back out of a delegate's vote that carried it, so
+/// the voter's direct vote replaces the delegate's choice.
+fn withdraw_delegated_power(proposal_id: u64, voter: Principal, power: u64) {
+    let Some(vote_id) = DELEGATED_BALLOTS.with(|index| index.borrow_mut().remove(&(proposal_id, voter)))
+    else {
+        return;
+    };
+    let Some(mut vote) = VOTE_STORAGE.with(|storage| storage.borrow().get(&vote_id)) else {
+        return;
+    };
+
+    vote.vote_power -= power;
+    vote.delegated_power -= power;
+    VOTE_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(vote_id, vote.clone());
+    });
+
+    PROPOSAL_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if let Some(mut proposal) = storage.get(&proposal_id) {
+            if vote.vote_choice {
+                proposal.voting_power_for -= power;
+            } else {
+                proposal.voting_power_against -= power;
+            }
+            storage.insert(proposal_id, proposal);
+        }
+    });
+}
+
+/// Casts the caller's vote, replacing any vote the caller already cast on the
+/// proposal while voting is open.
+#[update]
+async fn cast_vote(req: CastVoteRequest) -> Result<Vote> {
+    let caller = ic_cdk::caller();
+
+    let proposal = open_proposal(req.proposal_id)?;
+    ballots::validate_selection(proposal.kind, proposal.options.len(), &req.selection)?;
+    // Multi-option ballots are counted in the `for` tallies
+    let vote_choice = proposal.kind != ProposalKind::YesNo || req.vote_choice;
+
+    let own_power = if proposal.eligible_voting_power > 0 {
+        snapshot_power(proposal.id, caller)
+    } else {
+        // Proposals created before snapshots existed use live balances
+        let live_power = proposal.voting_mode.power(holder_tokens(caller, proposal.property_id).await?);
+        // Other messages may have run while awaiting the ledger
+        open_proposal(req.proposal_id)?;
+        live_power
+    };
+    let has_voted = BALLOT_INDEX.with(|index| index.borrow().contains_key(&(req.proposal_id, caller)));
+    if own_power == 0 && !has_voted && delegated_power_for(&proposal, caller).is_empty() {
+        return Err(Error::unauthorized("only holders of the property's tokens or their delegates can vote"));
+    }
+
+    Ok(record_vote(&proposal, caller, own_power, vote_choice, req.selection, time()))
+}
+
+/// Records `voter`'s vote with `own_power` plus the power delegated to it,
+/// replacing the voter's previous vote on the proposal, and updates the
+/// tallies.
+fn record_vote(
+    proposal: &Proposal,
+    voter: Principal,
+    own_power: u64,
+    vote_choice: bool,
+    selection: Vec<u32>,
+    now: u64,
+) -> Vote {
+    let proposal_id = proposal.id;
+    // Power the replaced vote carried for delegators is counted afresh below
+    retract_vote(proposal_id, voter, now);
+    let delegated = delegated_power_for(proposal, voter);
+    let delegated_power: u64 = delegated.iter().map(|(_, power)| power).sum();
+    let voting_power = own_power + delegated_power;
+
+    // A direct vote overrides the delegate's vote for this holder
+    withdraw_delegated_power(proposal_id, voter, own_power);
+
+    // Generate new vote ID
+    let vote_id = ID_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        let current_id = counter.get(&1).unwrap_or(0);
+        let new_id = current_id + 1;
+        counter.insert(1, new_id);
+        new_id
+    });
+
+    // Create vote record
+    let vote = Vote {
+        id: vote_id,
+        proposal_id,
+        voter,
+        vote_power: voting_power,
+        vote_choice,
+        timestamp: now,
+        delegated_power,
+        retracted_at: None,
+        selection,
+    };
+
+    // Store vote
+    VOTE_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(vote_id, vote.clone());
+    });
+    index_vote(&vote);
+    record_event_at(proposal_id, now, GovernanceEventKind::VoteCast {
+        vote_id,
+        voter,
+        vote_power: voting_power,
+    });
+    DELEGATED_BALLOTS.with(|index| {
+        let mut index = index.borrow_mut();
+        for (delegator, _) in delegated {
+            index.insert((proposal_id, delegator), vote_id);
+        }
+    });
+
+    // Update proposal vote counts
+    PROPOSAL_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if let Some(mut proposal) = storage.get(&proposal_id) {
+            proposal.first_vote_at.get_or_insert(vote.timestamp);
+            if vote_choice {
+                proposal.votes_for += 1;
+                proposal.voting_power_for += voting_power;
+            } else {
+                proposal.votes_against += 1;
+                proposal.voting_power_against += voting_power;
+            }
+            storage.insert(proposal_id, proposal);
+        }
+    });
+
+    vote
+}
+
+fn record_event(proposal_id: u64, kind: GovernanceEventKind) {
+    record_event_at(proposal_id, time(), kind);
+}
+
+fn record_event_at(proposal_id: u64, timestamp: u64, kind: GovernanceEventKind) {
+    EVENT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let seq = log.last_key_value().map_or(0, |(seq, _)| seq) + 1;
+        log.insert(seq, GovernanceEvent {
+            seq,
+            timestamp,
+            proposal_id,
+            kind,
+        });
+    });
+}
+
+/// Events recorded after `seq`, oldest first. Pass the `seq` of the last
+/// event received to continue, or 0 to read from the start of the log.
+#[query]
+fn get_events_since(seq: u64, limit: Option<u32>) -> Vec<GovernanceEvent> {
+    let limit = limit.unwrap_or(paging::DEFAULT_PAGE_SIZE).clamp(1, paging::MAX_PAGE_SIZE);
+    EVENT_LOG.with(|log| {
+        log.borrow()
+            .range(seq.saturating_add(1)..)
+            .take(limit as usize)
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+/// Sequence number of the latest event, or 0 while the log is empty.
+#[query]
+fn get_latest_event_seq() -> u64 {
+    EVENT_LOG.with(|log| log.borrow().last_key_value().map_or(0, |(seq, _)| seq))
+}
+
+#[query]
+fn get_proposals(page: PageRequest<u64>) -> Page<Proposal, u64> {
+    PROPOSAL_STORAGE.with(|storage| {
+        paging::paginate(&storage.borrow(), &page)
+    })
+}
+
+#[query]
+fn get_active_proposals(page: PageRequest<u64>) -> Page<Proposal, u64> {
+    let current_time = time();
+    PROPOSAL_STORAGE.with(|storage| {
+        paging::paginate_filtered(&storage.borrow(), &page, |proposal| {
+            proposal.status == ProposalStatus::Active && proposal.voting_deadline > current_time
+        })
+    })
+}
+
+#[query]
+fn get_proposal(proposal_id: u64) -> Option<Proposal> {
+    PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow().get(&proposal_id)
+    })
+}
+
+#[query]
+fn get_property_proposals(property_id: u64, page: PageRequest<u64>) -> Page<Proposal, u64> {
+    let ids: Vec<u64> = PROPERTY_PROPOSAL_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let entries = PROPOSAL_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter()
+            .filter_map(|id| storage.get(&id).map(|proposal| (id, proposal)))
+            .collect()
+    });
+    paging::paginate_sorted(entries, &page)
+}
+
+#[query]
+fn get_proposal_votes(proposal_id: u64, page: PageRequest<u64>) -> Page<Vote, u64> {
+    paging::paginate_sorted(load_votes(current_vote_ids(proposal_id)), &page)
+}
+
+#[query]
+fn get_user_votes(user_id: Principal, page: PageRequest<u64>) -> Page<Vote, u64> {
+    let ids: Vec<u64> = VOTER_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((user_id, 0)..=(user_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    paging::paginate_sorted(load_votes(ids), &page)
+}
+
+/// Replaces the title, description and action of a proposal nobody has voted
+/// on yet. The replaced content is kept as a revision.
+#[update]
+fn amend_proposal(proposal_id: u64, amendment: ProposalAmendment) -> Result<Proposal> {
+    let mut proposal = open_proposal(proposal_id)?;
+    if proposal.proposer != ic_cdk::caller() {
+        return Err(Error::unauthorized("only the proposer can amend a proposal"));
+    }
+    // Proposals from before first_vote_at was recorded may only have tallies
+    if proposal.first_vote_at.is_some() || proposal.votes_for + proposal.votes_against > 0 {
+        return Err(Error::invalid_state("proposals cannot be amended once voting has started"));
+    }
+    validate_proposal_text(&amendment.title, &amendment.description)?;
+    if let Some(action) = &amendment.action {
+        if proposal.kind != ProposalKind::YesNo {
+            return Err(Error::invalid_input("action", "only yes/no proposals can carry an action"));
+        }
+        action.validate(&proposal.proposal_type)?;
+    }
+
+    let revision = REVISIONS.with(|revisions| {
+        revisions
+            .borrow()
+            .range((proposal_id, 0)..=(proposal_id, u32::MAX))
+            .count() as u32
+    });
+    let superseded = ProposalRevision {
+        proposal_id,
+        revision,
+        title: proposal.title,
+        description: proposal.description,
+        action: proposal.action,
+        superseded_at: time(),
+    };
+    REVISIONS.with(|revisions| {
+        revisions.borrow_mut().insert((proposal_id, revision), superseded);
+    });
+
+    proposal.title = amendment.title.trim().to_string();
+    proposal.description = amendment.description.trim().to_string();
+    proposal.action = amendment.action;
+    PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(proposal_id, proposal.clone());
+    });
+    Ok(proposal)
+}
+
+/// Earlier versions of a proposal, oldest first. The cursor is the revision
+/// number.
+#[query]
+fn get_proposal_revisions(proposal_id: u64, page: PageRequest<u32>) -> Page<ProposalRevision, u32> {
+    let entries = REVISIONS.with(|revisions| {
+        revisions
+            .borrow()
+            .range((proposal_id, 0)..=(proposal_id, u32::MAX))
+            .map(|((_, number), revision)| (number, revision))
+            .collect()
+    });
+    paging::paginate_sorted(entries, &page)
+}
+
+/// Adds a comment, or a reply when `parent_id` is set, to a proposal that is
+/// still being voted on or waiting out its timelock. Only the proposer and
+/// holders of the property's tokens can comment.
+#[update]
+async fn add_comment(req: AddCommentRequest) -> Result<Comment> {
+    let caller = ic_cdk::caller();
+    discussion::validate_comment_body(&req.body)?;
+    let proposal = get_proposal(req.proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+    if let Some(parent_id) = req.parent_id {
+        let parent = COMMENT_STORAGE.with(|storage| storage.borrow().get(&parent_id));
+        if parent.is_none_or(|parent| parent.proposal_id != req.proposal_id) {
+            return Err(Error::not_found("parent comment"));
+        }
+    }
+
+    let in_snapshot = SNAPSHOT_STORAGE.with(|snapshots| {
+        snapshots.borrow().contains_key(&(req.proposal_id, caller))
+    });
+    let may_comment = proposal.proposer == caller
+        || in_snapshot
+        || holder_tokens(caller, proposal.property_id).await? > 0;
+    if !may_comment {
+        return Err(Error::unauthorized("only holders of the property's tokens can comment"));
+    }
+
+    // Checked after the await since the proposal may have closed meanwhile
+    let proposal = get_proposal(req.proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+    if !matches!(proposal.status, ProposalStatus::Active | ProposalStatus::Queued) {
+        return Err(Error::invalid_state("discussion of this proposal is closed"));
+    }
+
+    let comment_id = ID_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        let current_id = counter.get(&2).unwrap_or(0);
+        let new_id = current_id + 1;
+        counter.insert(2, new_id);
+        new_id
+    });
+    let comment = Comment {
+        id: comment_id,
+        proposal_id: req.proposal_id,
+        author: caller,
+        body: req.body.trim().to_string(),
+        parent_id: req.parent_id,
+        created_at: time(),
+        hidden: None,
+    };
+    COMMENT_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(comment_id, comment.clone());
+    });
+    PROPOSAL_COMMENT_INDEX.with(|index| {
+        index.borrow_mut().insert((req.proposal_id, comment_id), ());
+    });
+    Ok(comment)
+}
+
+/// Hides a comment from readers. Replies stay visible and the comment's body
+/// is kept so the decision can be reversed.
+#[update]
+fn hide_comment(comment_id: u64, reason: String) -> Result<Comment> {
+    require_role(Role::Admin)?;
+    discussion::validate_moderation_reason(&reason)?;
+    let moderation = Moderation {
+        moderator: ic_cdk::caller(),
+        reason: reason.trim().to_string(),
+        hidden_at: time(),
+    };
+    set_comment_moderation(comment_id, Some(moderation))
+}
+
+#[update]
+fn unhide_comment(comment_id: u64) -> Result<Comment> {
+    require_role(Role::Admin)?;
+    set_comment_moderation(comment_id, None)
+}
+
+fn set_comment_moderation(comment_id: u64, hidden: Option<Moderation>) -> Result<Comment> {
+    COMMENT_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let mut comment = storage.get(&comment_id).ok_or_else(|| Error::not_found("comment"))?;
+        comment.hidden = hidden;
+        storage.insert(comment_id, comment.clone());
+        Ok(comment)
+    })
+}
+
+/// Comments on a proposal in the order they were made. Threads are rebuilt
+/// from each comment's `parent_id`.
+#[query]
+fn get_proposal_comments(proposal_id: u64, page: PageRequest<u64>) -> Page<Comment, u64> {
+    let ids: Vec<u64> = PROPOSAL_COMMENT_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((proposal_id, 0)..=(proposal_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let entries = COMMENT_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter()
+            .filter_map(|id| storage.get(&id).map(|comment| (id, comment.redacted())))
+            .collect()
+    });
+    paging::paginate_sorted(entries, &page)
+}
+
+/// Finalizes a proposal whose voting deadline has passed. Timers normally do
+/// this at the deadline; anyone may call it to settle a proposal sooner.
+#[update]
+fn finalize_proposal(proposal_id: u64) -> Result<Proposal> {
+    finalize(proposal_id)
+}
+
+/// Retries the refund of a deposit that could not be returned when the
+/// proposal was finalized. The refund always goes to the proposer.
+#[update]
+async fn claim_deposit_refund(proposal_id: u64) -> Result<Proposal> {
+    let proposal = get_proposal(proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+    let cast = proposal.voting_power_for + proposal.voting_power_against;
+    if !proposal.tally_rules.quorum_reached(cast, proposal.eligible_voting_power) {
+        return Err(Error::invalid_state("deposits of proposals that missed quorum are forfeited"));
+    }
+    refund_deposit(proposal_id).await
+}
+
+/// Starts executing a queued proposal whose timelock has ended, for when its
+/// timer has not fired yet.
+#[update]
+fn release_proposal(proposal_id: u64) -> Result<Proposal> {
+    release(proposal_id)
+}
+
+/// Cancels a queued proposal before it executes.
+#[update]
+fn veto_proposal(proposal_id: u64) -> Result<Proposal> {
+    require_role(Role::Guardian)?;
+    let proposal = vetoable_proposal(proposal_id)?;
+    Ok(veto(proposal))
+}
+
+/// Adds the caller's snapshot voting power to the holder veto of a queued
+/// proposal. The proposal is vetoed once the holders behind the veto reach
+/// a supermajority of the eligible voting power.
+#[update]
+fn cast_veto_vote(proposal_id: u64) -> Result<Proposal> {
+    let caller = ic_cdk::caller();
+    let proposal = vetoable_proposal(proposal_id)?;
+
+    if proposal.eligible_voting_power == 0 {
+        return Err(Error::invalid_state("proposal has no snapshot for holders to veto with"));
+    }
+    let power = snapshot_power(proposal_id, caller);
+    if power == 0 {
+        return Err(Error::unauthorized("only holders of the property's tokens can veto"));
+    }
+    let already_vetoed = VETO_BALLOTS.with(|ballots| {
+        ballots.borrow().contains_key(&(proposal_id, caller))
+    });
+    if already_vetoed {
+        return Err(Error::AlreadyVoted);
+    }
+
+    VETO_BALLOTS.with(|ballots| {
+        ballots.borrow_mut().insert((proposal_id, caller), power);
+    });
+
+    if veto_threshold_reached(veto_power(proposal_id), proposal.eligible_voting_power) {
+        Ok(veto(proposal))
+    } else {
+        Ok(proposal)
+    }
+}
+
+#[query]
+fn get_veto_power(proposal_id: u64) -> u64 {
+    veto_power(proposal_id)
+}
+
+/// Sets the default timelock of a proposal type for properties registered
+/// afterwards. Properties with a config keep their timelocks.
+#[update]
+fn set_timelock(proposal_type: String, seconds: u64) -> Result<()> {
+    require_role(Role::Admin)?;
+    if proposal_type.trim().is_empty() {
+        return Err(Error::invalid_input("proposal_type", "must not be empty"));
+    }
+    if seconds > config::MAX_TIMELOCK_SECONDS {
+        return Err(Error::invalid_input(
+            "seconds",
+            &format!("must not exceed {}", config::MAX_TIMELOCK_SECONDS),
+        ));
+    }
+
+    TIMELOCKS.with(|timelocks| {
+        timelocks.borrow_mut().insert(proposal_type, seconds);
+    });
+    Ok(())
+}
+
+#[query]
+fn get_timelocks() -> Vec<(String, u64)> {
+    TIMELOCKS.with(|timelocks| timelocks.borrow().iter().collect())
+}
+
+/// Retries the action of a passed proposal whose execution failed.
+#[update]
+async fn execute_proposal(proposal_id: u64) -> Result<Proposal> {
+    execute(proposal_id).await
+}
+
+/// Records that a passed proposal without an action has been carried out
+/// off-chain. Outcomes themselves are only set by finalization.
+#[update]
+fn update_proposal_status(proposal_id: u64, new_status: ProposalStatus) -> Result<Proposal> {
+    require_role(Role::Admin)?;
+
+    PROPOSAL_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let mut proposal = storage.get(&proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+        if proposal.action.is_some() {
+            return Err(Error::invalid_state("proposals with an action are executed by the canister"));
+        }
+        match (proposal.status, new_status) {
+            (ProposalStatus::Passed, ProposalStatus::Executed) => {
+                proposal.status = new_status;
+                storage.insert(proposal_id, proposal.clone());
+                record_event(proposal_id, GovernanceEventKind::ProposalExecuted);
+                Ok(proposal)
+            }
+            _ => Err(Error::invalid_state(&format!(
+                "cannot move proposal from {:?} to {:?}",
+                proposal.status, new_status
+            ))),
+        }
+    })
+}
+
+/// Sets the default rules of a proposal type for properties registered
+/// afterwards. Properties with a config keep their rules.
+#[update]
+fn set_tally_rules(proposal_type: String, rules: TallyRules) -> Result<()> {
+    require_role(Role::Admin)?;
+    if proposal_type.trim().is_empty() {
+        return Err(Error::invalid_input("proposal_type", "must not be empty"));
+    }
+    rules.validate()?;
+
+    TALLY_RULES.with(|store| {
+        store.borrow_mut().insert(proposal_type, rules);
+    });
+    Ok(())
+}
+
+#[query]
+fn get_tally_rules() -> Vec<(String, TallyRules)> {
+    TALLY_RULES.with(|rules| rules.borrow().iter().collect())
+}
+
+/// Withdraws the caller's vote on a proposal that is still open for voting.
+#[update]
+fn withdraw_vote(proposal_id: u64) -> Result<Vote> {
+    let caller = ic_cdk::caller();
+    open_proposal(proposal_id)?;
+    retract_vote(proposal_id, caller, time()).ok_or_else(|| Error::not_found("vote"))
+}
+
+/// Every vote `voter` has cast on `proposal_id`, including changed and
+/// withdrawn ones, oldest first.
+#[query]
+fn get_vote_history(proposal_id: u64, voter: Principal, page: PageRequest<u64>) -> Page<Vote, u64> {
+    let ids: Vec<u64> = VOTE_HISTORY_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((proposal_id, voter, 0)..=(proposal_id, voter, u64::MAX))
+            .map(|((_, _, id), _)| id)
+            .collect()
+    });
+    paging::paginate_sorted(load_votes(ids), &page)
+}
+
+/// Power a vote by `voter` on `proposal_id` would carry now: the voter's own
+/// snapshot power plus any power delegated to it that no vote carries yet.
+#[query]
+fn get_voting_power(proposal_id: u64, voter: Principal) -> u64 {
+    let own_power = snapshot_power(proposal_id, voter);
+    let delegated_power: u64 = get_proposal(proposal_id)
+        .map(|proposal| delegated_power_for(&proposal, voter))
+        .unwrap_or_default()
+        .iter()
+        .map(|(_, power)| power)
+        .sum();
+    own_power + delegated_power
+}
+
+/// Delegates the caller's vote for `scope` to `delegate`, replacing any
+/// earlier delegation for the same scope. Votes already cast are unaffected.
+#[update]
+fn delegate_votes(scope: DelegationScope, delegate: Principal) -> Result<()> {
+    let caller = ic_cdk::caller();
+    if delegate == caller {
+        return Err(Error::invalid_input("delegate", "cannot delegate to yourself"));
+    }
+    if delegate == Principal::anonymous() {
+        return Err(Error::invalid_input("delegate", "cannot delegate to the anonymous principal"));
+    }
+    set_delegation(caller, scope, delegate);
+    Ok(())
+}
+
+fn set_delegation(delegator: Principal, scope: DelegationScope, delegate: Principal) {
+    let key = scope.key();
+    let previous = DELEGATIONS.with(|delegations| delegations.borrow_mut().insert((delegator, key), delegate));
+    DELEGATE_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous {
+            index.remove(&(previous, key, delegator));
+        }
+        index.insert((delegate, key, delegator), ());
+    });
+}
+
+/// Revokes the caller's delegation for `scope`. Power a delegate has already
+/// voted with stays on that vote unless the caller votes directly.
+#[update]
+fn revoke_delegation(scope: DelegationScope) -> Result<()> {
+    let caller = ic_cdk::caller();
+    let key = scope.key();
+
+    let delegate = DELEGATIONS
+        .with(|delegations| delegations.borrow_mut().remove(&(caller, key)))
+        .ok_or_else(|| Error::not_found("delegation"))?;
+    DELEGATE_INDEX.with(|index| {
+        index.borrow_mut().remove(&(delegate, key, caller));
+    });
+    Ok(())
+}
+
+/// Delegations made by `delegator`, ordered by scope. The cursor is the
+/// scope's property ID, or 0 for all properties.
+#[query]
+fn get_delegations(delegator: Principal, page: PageRequest<u64>) -> Page<Delegation, u64> {
+    let entries = DELEGATIONS.with(|delegations| {
+        delegations
+            .borrow()
+            .range((delegator, 0)..=(delegator, u64::MAX))
+            .map(|((_, key), delegate)| {
+                let delegation = Delegation {
+                    delegator,
+                    delegate,
+                    scope: DelegationScope::from_key(key),
+                };
+                (key, delegation)
+            })
+            .collect()
+    });
+    paging::paginate_sorted(entries, &page)
+}
+
+/// Delegations made to `delegate`, ordered by scope and then delegator. The
+/// cursor pairs the scope's property ID, or 0 for all properties, with the
+/// delegator.
+#[query]
+fn get_delegators(delegate: Principal, page: PageRequest<(u64, Principal)>) -> Page<Delegation, (u64, Principal)> {
+    let entries = DELEGATE_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((delegate, 0, Principal::management_canister())..)
+            .take_while(|((d, _, _), _)| *d == delegate)
+            .map(|((_, key, delegator), _)| {
+                let delegation = Delegation {
+                    delegator,
+                    delegate,
+                    scope: DelegationScope::from_key(key),
+                };
+                ((key, delegator), delegation)
+            })
+            .collect()
+    });
+    paging::paginate_sorted(entries, &page)
+}
+
+/// Tally report for a proposal. Participation is measured against the
+/// snapshot taken at creation, or against the current token supply for
+/// proposals created before snapshots existed; quorum and the projected
+/// outcome follow the rules finalization applies.
+#[query(composite = true)]
+async fn get_proposal_result(proposal_id: u64) -> Result<ProposalResult> {
+    let proposal = get_proposal(proposal_id).ok_or_else(|| Error::not_found("proposal"))?;
+
+    let eligible_voting_power = if proposal.eligible_voting_power > 0 {
+        proposal.eligible_voting_power
+    } else {
+        property_token_supply(proposal.property_id).await?
+    };
+    let total_voting_power = proposal.voting_power_for + proposal.voting_power_against;
+
+    let participation_rate = if eligible_voting_power > 0 {
+        total_voting_power as f64 / eligible_voting_power as f64
+    } else {
         0.0
     };
-    
+
+    let (tallies, leading_option) = option_tallies(&proposal);
+    let option_results: Vec<OptionResult> = tallies
+        .into_iter()
+        .map(|tally| OptionResult {
+            label: proposal.options[tally.option as usize].clone(),
+            option: tally.option,
+            power: tally.power,
+            eliminated_in_round: tally.eliminated_in_round,
+        })
+        .collect();
+
+    // For multi-option proposals approval is the leading option's share
+    let approved_power = match leading_option {
+        Some(option) => option_results[option as usize].power,
+        None if proposal.kind == ProposalKind::YesNo => proposal.voting_power_for,
+        None => 0,
+    };
     let approval_rate = if total_voting_power > 0 {
-        proposal.voting_power_for as f64 / total_voting_power as f64
+        approved_power as f64 / total_voting_power as f64
     } else {
         0.0
     };
 
-    Some(ProposalResult {
+    let rules = proposal.tally_rules;
+    let quorum_reached =
+        rules.quorum_reached(total_voting_power, proposal.eligible_voting_power);
+    let threshold_reached = match proposal.kind {
+        ProposalKind::YesNo => {
+            rules.threshold_reached(proposal.voting_power_for, proposal.voting_power_against)
+        }
+        _ => leading_option.is_some(),
+    };
+    let projected_outcome = match proposal.status {
+        ProposalStatus::Active => decide(&proposal).0,
+        status => status,
+    };
+    let time_remaining = match proposal.status {
+        ProposalStatus::Active => proposal.voting_deadline.saturating_sub(time()),
+        _ => 0,
+    };
+
+    Ok(ProposalResult {
         proposal,
         total_voting_power,
+        eligible_voting_power,
         participation_rate,
         approval_rate,
+        quorum_reached,
+        threshold_reached,
+        time_remaining,
+        projected_outcome,
+        option_results,
+        leading_option,
     })
 }
 
 // Export candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::Storable;
+
+    /// Records as written by the canister before they were versioned.
+    #[derive(CandidType)]
+    struct LegacyProposal {
+        id: u64,
+        property_id: u64,
+        title: String,
+        description: String,
+        proposal_type: String,
+        proposer: Principal,
+        votes_for: u64,
+        votes_against: u64,
+        voting_power_for: u64,
+        voting_power_against: u64,
+        status: String,
+        created_at: u64,
+        voting_deadline: u64,
+        execution_data: Option<String>,
+    }
+
+    #[derive(CandidType)]
+    struct LegacyVote {
+        id: u64,
+        proposal_id: u64,
+        voter: Principal,
+        vote_power: u64,
+        vote_choice: bool,
+        timestamp: u64,
+    }
+
+    #[test]
+    fn loads_unversioned_proposal() {
+        let bytes = candid::encode_one(&LegacyProposal {
+            id: 3,
+            property_id: 1,
+            title: "Replace roof".to_string(),
+            description: "Roof is leaking".to_string(),
+            proposal_type: "maintenance".to_string(),
+            proposer: Principal::anonymous(),
+            votes_for: 2,
+            votes_against: 1,
+            voting_power_for: 300,
+            voting_power_against: 100,
+            status: "active".to_string(),
+            created_at: 5,
+            voting_deadline: 50,
+            execution_data: None,
+        })
+        .unwrap();
+
+        let proposal = Proposal::from_bytes(bytes.into());
+        assert_eq!(proposal.id, 3);
+        assert_eq!(proposal.voting_power_for, 300);
+        assert_eq!(proposal.status, ProposalStatus::Active);
+        assert_eq!(proposal.eligible_voting_power, 0);
+        assert_eq!(proposal.tally_rules, TallyRules::default_for("maintenance"));
+        assert_eq!(proposal.to_bytes()[0], Proposal::VERSION);
+    }
+
+    #[test]
+    fn loads_v1_proposal_without_snapshot() {
+        let mut bytes = vec![1];
+        bytes.extend(candid::encode_one(&LegacyProposal {
+            id: 4,
+            property_id: 2,
+            title: "Repaint".to_string(),
+            description: "Exterior paint".to_string(),
+            proposal_type: "improvement".to_string(),
+            proposer: Principal::anonymous(),
+            votes_for: 0,
+            votes_against: 0,
+            voting_power_for: 0,
+            voting_power_against: 0,
+            status: "active".to_string(),
+            created_at: 1,
+            voting_deadline: 2,
+            execution_data: Some("{}".to_string()),
+        })
+        .unwrap());
+
+        let proposal = Proposal::from_bytes(bytes.into());
+        assert_eq!(proposal.id, 4);
+        assert_eq!(proposal.action, None);
+        assert_eq!(proposal.execution, None);
+        assert_eq!(proposal.eligible_voting_power, 0);
+        assert_eq!(proposal.deposit, None);
+    }
+
+    #[test]
+    fn loads_v2_proposal_with_free_form_status() {
+        let mut bytes = vec![2];
+        bytes.extend(candid::encode_one(&legacy::ProposalV2 {
+            id: 5,
+            property_id: 2,
+            title: "Sell the building".to_string(),
+            description: "Offer received".to_string(),
+            proposal_type: "sale".to_string(),
+            proposer: Principal::anonymous(),
+            votes_for: 1,
+            votes_against: 0,
+            voting_power_for: 10,
+            voting_power_against: 0,
+            status: "on hold".to_string(),
+            created_at: 1,
+            voting_deadline: 2,
+            execution_data: None,
+            eligible_voting_power: 40,
+        })
+        .unwrap());
+
+        let proposal = Proposal::from_bytes(bytes.into());
+        assert_eq!(proposal.status, ProposalStatus::Rejected);
+        assert_eq!(proposal.eligible_voting_power, 40);
+        assert_eq!(proposal.tally_rules, TallyRules::default_for("sale"));
+    }
+
+    #[test]
+    fn loads_unversioned_vote() {
+        let bytes = candid::encode_one(&LegacyVote {
+            id: 6,
+            proposal_id: 3,
+            voter: Principal::anonymous(),
+            vote_power: 300,
+            vote_choice: true,
+            timestamp: 7,
+        })
+        .unwrap();
+
+        let vote = Vote::from_bytes(bytes.into());
+        assert_eq!(vote.proposal_id, 3);
+        assert!(vote.vote_choice);
+        assert_eq!(vote.delegated_power, 0);
+        assert_eq!(vote.to_bytes()[0], Vote::VERSION);
+    }
+
+    fn proposal(status: ProposalStatus) -> Proposal {
+        Proposal {
+            id: 1,
+            property_id: 1,
+            title: "Replace roof".to_string(),
+            description: "Roof is leaking".to_string(),
+            proposal_type: "maintenance".to_string(),
+            proposer: Principal::anonymous(),
+            votes_for: 0,
+            votes_against: 0,
+            voting_power_for: 0,
+            voting_power_against: 0,
+            status,
+            created_at: 0,
+            voting_deadline: 10,
+            eligible_voting_power: 100,
+            tally_rules: TallyRules::default_for("maintenance"),
+            action: Some(ProposalAction::FundMaintenance {
+                amount: 500,
+                payee: Principal::anonymous(),
+            }),
+            execution: None,
+            executable_at: None,
+            kind: ProposalKind::YesNo,
+            options: Vec::new(),
+            winning_option: None,
+            voting_mode: VotingMode::Linear,
+            deposit: None,
+            first_vote_at: None,
+        }
+    }
+
+    #[test]
+    fn failed_execution_stays_passed_until_a_retry_succeeds() {
+        let mut passed = proposal(ProposalStatus::Passed);
+
+        record_execution(&mut passed, &Err(Error::internal("ledger unavailable")), 20);
+        assert_eq!(passed.status, ProposalStatus::Passed);
+        assert_eq!(
+            passed.execution,
+            Some(ExecutionRecord {
+                attempted_at: 20,
+                result: ExecutionResult::Failed(Error::internal("ledger unavailable").to_string()),
+            })
+        );
+
+        record_execution(&mut passed, &Ok(()), 30);
+        assert_eq!(passed.status, ProposalStatus::Executed);
+        assert_eq!(
+            passed.execution,
+            Some(ExecutionRecord {
+                attempted_at: 30,
+                result: ExecutionResult::Succeeded,
+            })
+        );
+    }
+
+    #[test]
+    fn holder_veto_needs_the_threshold() {
+        // 6667 bps of 10_000 is exactly 6667
+        assert!(!veto_threshold_reached(6_666, 10_000));
+        assert!(veto_threshold_reached(6_667, 10_000));
+        // Rounding never lets a share below 66.67% through
+        assert!(!veto_threshold_reached(2, 3));
+        assert!(veto_threshold_reached(3, 3));
+    }
+
+    #[test]
+    fn releases_only_after_the_timelock() {
+        let executable_at = timelock_end(1_000, 2);
+        assert_eq!(executable_at, 2_000_001_000);
+        assert_eq!(timelock_end(u64::MAX - 1, u64::MAX), u64::MAX);
+
+        let mut queued = proposal(ProposalStatus::Queued);
+        queued.executable_at = Some(executable_at);
+        assert!(ensure_releasable(&queued, executable_at - 1).is_err());
+        assert!(ensure_vetoable(&queued, executable_at - 1).is_ok());
+        assert!(ensure_releasable(&queued, executable_at).is_ok());
+        assert!(ensure_vetoable(&queued, executable_at).is_err());
+
+        let passed = proposal(ProposalStatus::Passed);
+        assert!(ensure_releasable(&passed, executable_at).is_err());
+        assert!(ensure_vetoable(&passed, 0).is_err());
+    }
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    /// Stores `proposal` with a snapshot giving each holder its power.
+    fn store_with_snapshot(proposal: &Proposal, holders: &[(Principal, u64)]) {
+        PROPOSAL_STORAGE.with(|storage| storage.borrow_mut().insert(proposal.id, proposal.clone()));
+        SNAPSHOT_STORAGE.with(|snapshots| {
+            let mut snapshots = snapshots.borrow_mut();
+            for (holder, power) in holders {
+                snapshots.insert((proposal.id, *holder), *power);
+            }
+        });
+    }
+
+    #[test]
+    fn delegation_is_not_transitive() {
+        let (alice, bob, carol) = (principal(1), principal(2), principal(3));
+        let active = proposal(ProposalStatus::Active);
+        store_with_snapshot(&active, &[(alice, 10), (bob, 20), (carol, 30)]);
+
+        set_delegation(alice, DelegationScope::Property(active.property_id), bob);
+        set_delegation(bob, DelegationScope::AllProperties, carol);
+
+        assert_eq!(delegated_power_for(&active, bob), vec![(alice, 10)]);
+        // Alice's power does not pass through Bob to Carol
+        assert_eq!(delegated_power_for(&active, carol), vec![(bob, 20)]);
+
+        // A property delegation overrides one for all properties
+        set_delegation(alice, DelegationScope::AllProperties, carol);
+        assert_eq!(delegated_power_for(&active, carol), vec![(bob, 20)]);
+    }
+
+    #[test]
+    fn direct_vote_withdraws_power_from_the_delegate() {
+        let (bob, carol) = (principal(2), principal(3));
+        let mut active = proposal(ProposalStatus::Active);
+        active.voting_power_for = 50;
+        active.votes_for = 1;
+        store_with_snapshot(&active, &[(bob, 20), (carol, 30)]);
+        set_delegation(bob, DelegationScope::AllProperties, carol);
+
+        let carried = Vote {
+            id: 7,
+            proposal_id: active.id,
+            voter: carol,
+            vote_power: 50,
+            vote_choice: true,
+            timestamp: 0,
+            delegated_power: 20,
+            retracted_at: None,
+            selection: Vec::new(),
+        };
+        VOTE_STORAGE.with(|storage| storage.borrow_mut().insert(carried.id, carried.clone()));
+        index_vote(&carried);
+        DELEGATED_BALLOTS.with(|index| index.borrow_mut().insert((active.id, bob), carried.id));
+        assert!(delegated_power_for(&active, carol).is_empty());
+
+        withdraw_delegated_power(active.id, bob, 20);
+        let vote = VOTE_STORAGE.with(|storage| storage.borrow().get(&carried.id)).unwrap();
+        assert_eq!((vote.vote_power, vote.delegated_power), (30, 0));
+        assert_eq!(get_proposal(active.id).unwrap().voting_power_for, 30);
+        assert!(DELEGATED_BALLOTS.with(|index| !index.borrow().contains_key(&(active.id, bob))));
+
+        // Withdrawing again changes nothing
+        withdraw_delegated_power(active.id, bob, 20);
+        assert_eq!(get_proposal(active.id).unwrap().voting_power_for, 30);
+    }
+
+    #[test]
+    fn changing_a_vote_moves_its_power_once() {
+        let (bob, carol) = (principal(2), principal(3));
+        let active = proposal(ProposalStatus::Active);
+        store_with_snapshot(&active, &[(bob, 20), (carol, 30)]);
+        set_delegation(bob, DelegationScope::AllProperties, carol);
+
+        let first = record_vote(&active, carol, 30, true, Vec::new(), 1);
+        assert_eq!((first.vote_power, first.delegated_power), (50, 20));
+        let tallied = get_proposal(active.id).unwrap();
+        assert_eq!((tallied.voting_power_for, tallied.voting_power_against), (50, 0));
+
+        // Own and delegated power move together to the new choice
+        let changed = record_vote(&active, carol, 30, false, Vec::new(), 2);
+        assert_eq!(changed.vote_power, 50);
+        let tallied = get_proposal(active.id).unwrap();
+        assert_eq!((tallied.voting_power_for, tallied.voting_power_against), (0, 50));
+        assert_eq!((tallied.votes_for, tallied.votes_against), (0, 1));
+
+        // Casting the same choice again does not count the power twice
+        record_vote(&active, carol, 30, false, Vec::new(), 3);
+        let tallied = get_proposal(active.id).unwrap();
+        assert_eq!((tallied.voting_power_for, tallied.voting_power_against), (0, 50));
+
+        let history = get_vote_history(active.id, carol, PageRequest::default());
+        assert_eq!(history.total, 3);
+        assert_eq!(history.items[0].retracted_at, Some(2));
+        assert_eq!(history.items[2].retracted_at, None);
+    }
+
+    #[test]
+    fn admin_defaults_only_reach_registered_properties() {
+        seed_tally_rules();
+        seed_timelocks();
+        TIMELOCKS.with(|timelocks| timelocks.borrow_mut().insert("maintenance".to_string(), 0));
+
+        // An unregistered property keeps the built-in baseline
+        assert_eq!(governance_config_for(7), baseline_governance_config());
+        let maintenance_timelock = |config: GovernanceConfig| {
+            config.proposal_type("maintenance").unwrap().timelock_seconds
+        };
+        assert_eq!(maintenance_timelock(governance_config_for(7)), 2 * 24 * 60 * 60);
+
+        // A property registered afterwards stores the changed defaults
+        assert_eq!(maintenance_timelock(register_governance_config(8)), 0);
+        TIMELOCKS.with(|timelocks| timelocks.borrow_mut().insert("maintenance".to_string(), 60));
+        assert_eq!(maintenance_timelock(governance_config_for(8)), 0);
+        assert_eq!(maintenance_timelock(register_governance_config(8)), 0);
+    }
+
+    #[test]
+    fn event_log_reads_from_a_cursor() {
+        assert_eq!(get_latest_event_seq(), 0);
+        for timestamp in 1..=(paging::MAX_PAGE_SIZE as u64 + 5) {
+            record_event_at(timestamp, timestamp, GovernanceEventKind::ProposalExecuted);
+        }
+        let latest = get_latest_event_seq();
+        assert_eq!(latest, paging::MAX_PAGE_SIZE as u64 + 5);
+
+        let all = get_events_since(0, Some(u32::MAX));
+        assert_eq!(all.len(), paging::MAX_PAGE_SIZE as usize);
+        assert!(all.windows(2).all(|pair| pair[1].seq == pair[0].seq + 1));
+        assert_eq!(all[0].seq, 1);
+
+        let after = get_events_since(latest - 2, None);
+        assert_eq!(after.iter().map(|event| event.seq).collect::<Vec<_>>(), [latest - 1, latest]);
+        assert_eq!(get_events_since(latest, None).len(), 0);
+        assert_eq!(get_events_since(3, Some(0)).iter().map(|event| event.seq).collect::<Vec<_>>(), [4]);
+    }
+
+    #[test]
+    fn validates_proposal_text() {
+        assert!(validate_proposal_text("Fix the roof", "Replace damaged tiles").is_ok());
+        assert!(validate_proposal_text("   ", "Replace damaged tiles").is_err());
+        assert!(validate_proposal_text("Fix the roof", "").is_err());
+        assert!(validate_proposal_text(&"x".repeat(MAX_TITLE_LENGTH + 1), "Replace damaged tiles").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! Voting power rules and the quorum and approval rules used to decide the
+//! outcome of a proposal once voting closes.
+
+use crate::{Proposal, ProposalStatus};
+use candid::{CandidType, Deserialize};
+use realty_common::schema::Versioned;
+use realty_common::{versioned_storable, Error, Result};
+use serde::Serialize;
+
+/// Ratios are expressed in basis points, so 10_000 is 100%.
+pub const BPS_SCALE: u32 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct TallyRules {
+    /// Share of the eligible voting power that must take part.
+    pub quorum_bps: u32,
+    /// Share of the cast voting power that must be in favour.
+    pub approval_threshold_bps: u32,
+}
+
+impl Versioned for TallyRules {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(TallyRules);
+
+/// Rules each built-in proposal type starts with.
+pub const DEFAULT_RULES: &[(&str, TallyRules)] = &[
+    ("maintenance", TallyRules { quorum_bps: 2_000, approval_threshold_bps: 5_000 }),
+    ("improvement", TallyRules { quorum_bps: 3_000, approval_threshold_bps: 5_000 }),
+    ("dividend", TallyRules { quorum_bps: 2_000, approval_threshold_bps: 5_000 }),
+    ("sale", TallyRules { quorum_bps: 5_000, approval_threshold_bps: 6_667 }),
+    ("config", TallyRules { quorum_bps: 5_000, approval_threshold_bps: 6_667 }),
+];
+
+impl TallyRules {
+    /// Default rules for `proposal_type`, falling back to a simple majority
+    /// with the strictest built-in quorum for types that have none.
+    pub fn default_for(proposal_type: &str) -> TallyRules {
+        DEFAULT_RULES
+            .iter()
+            .find(|(name, _)| *name == proposal_type)
+            .map(|(_, rules)| *rules)
+            .unwrap_or(TallyRules { quorum_bps: 5_000, approval_threshold_bps: 5_000 })
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.quorum_bps > BPS_SCALE {
+            return Err(Error::invalid_input("quorum_bps", "must not exceed 10000"));
+        }
+        if self.approval_threshold_bps == 0 || self.approval_threshold_bps > BPS_SCALE {
+            return Err(Error::invalid_input(
+                "approval_threshold_bps",
+                "must be between 1 and 10000",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether `cast` power out of `eligible` meets the quorum. Proposals
+    /// without a snapshot have no known eligible power and only need a vote.
+    pub fn quorum_reached(&self, cast: u64, eligible: u64) -> bool {
+        if eligible == 0 {
+            return cast > 0;
+        }
+        cast as u128 * BPS_SCALE as u128 >= eligible as u128 * self.quorum_bps as u128
+    }
+
+    /// Whether the power in favour is at least the threshold share of the
+    /// cast power and outweighs the power against.
+    pub fn threshold_reached(&self, power_for: u64, power_against: u64) -> bool {
+        let cast = power_for as u128 + power_against as u128;
+        power_for > power_against
+            && power_for as u128 * BPS_SCALE as u128 >= cast * self.approval_threshold_bps as u128
+    }
+
+    /// Outcome of `proposal` if voting closed with its current tally.
+    pub fn outcome(&self, proposal: &Proposal) -> ProposalStatus {
+        let cast = proposal.voting_power_for + proposal.voting_power_against;
+        if self.quorum_reached(cast, proposal.eligible_voting_power)
+            && self.threshold_reached(proposal.voting_power_for, proposal.voting_power_against)
+        {
+            ProposalStatus::Passed
+        } else {
+            ProposalStatus::Rejected
+        }
+    }
+}
+
+/// How a holder's tokens translate into voting power on a property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum VotingMode {
+    /// One vote per token.
+    Linear,
+    /// The square root of the holder's tokens, rounded down.
+    Quadratic,
+    /// One vote per token up to `cap` votes per holder.
+    Capped { cap: u64 },
+}
+
+impl Versioned for VotingMode {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(VotingMode);
+
+impl VotingMode {
+    pub fn validate(&self) -> Result<()> {
+        if let VotingMode::Capped { cap: 0 } = self {
+            return Err(Error::invalid_input("cap", "must be greater than zero"));
+        }
+        Ok(())
+    }
+
+    /// Voting power of a holder of `tokens`.
+    pub fn power(&self, tokens: u64) -> u64 {
+        match self {
+            VotingMode::Linear => tokens,
+            VotingMode::Quadratic => tokens.isqrt(),
+            VotingMode::Capped { cap } => tokens.min(*cap),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAJORITY: TallyRules = TallyRules { quorum_bps: 2_000, approval_threshold_bps: 5_000 };
+
+    #[test]
+    fn quorum_is_a_share_of_eligible_power() {
+        assert!(!MAJORITY.quorum_reached(199, 1_000));
+        assert!(MAJORITY.quorum_reached(200, 1_000));
+        assert!(!MAJORITY.quorum_reached(0, 0));
+        assert!(MAJORITY.quorum_reached(1, 0));
+    }
+
+    #[test]
+    fn ties_do_not_pass() {
+        assert!(!MAJORITY.threshold_reached(100, 100));
+        assert!(MAJORITY.threshold_reached(101, 100));
+        assert!(!MAJORITY.threshold_reached(0, 0));
+    }
+
+    #[test]
+    fn supermajority_threshold() {
+        let sale = TallyRules::default_for("sale");
+        assert!(!sale.threshold_reached(600, 400));
+        assert!(sale.threshold_reached(667, 333));
+    }
+
+    #[test]
+    fn voting_modes_scale_tokens() {
+        assert_eq!(VotingMode::Linear.power(10_000), 10_000);
+        assert_eq!(VotingMode::Quadratic.power(10_000), 100);
+        assert_eq!(VotingMode::Quadratic.power(99), 9);
+        assert_eq!(VotingMode::Capped { cap: 500 }.power(10_000), 500);
+        assert_eq!(VotingMode::Capped { cap: 500 }.power(20), 20);
+        assert!(VotingMode::Capped { cap: 0 }.validate().is_err());
+    }
+
+    #[test]
+    fn validates_ranges() {
+        assert!(MAJORITY.validate().is_ok());
+        assert!(TallyRules { quorum_bps: 10_001, approval_threshold_bps: 5_000 }.validate().is_err());
+        assert!(TallyRules { quorum_bps: 0, approval_threshold_bps: 0 }.validate().is_err());
+    }
+}
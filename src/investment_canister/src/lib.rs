@@ -1,15 +1,79 @@
 use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult;
 use ic_cdk::api::time;
-use ic_cdk_macros::{init, query, update};
+use ic_cdk_macros::{init, post_upgrade, query, update};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use market::{BookKey, Order, OrderBook, OrderSide, OrderStatus, PlaceOrderRequest, SettlementFailure, Trade, Withdrawal};
+use redemption::{OpenRedemptionWindowRequest, RedemptionRequest, RedemptionStatus, RedemptionWindow};
+use realty_common::access::{self, Role, RoleAssignment, RoleStore};
+use realty_common::icrc::{self, Account, LedgerTransfer};
+use realty_common::paging::{self, Page, PageRequest};
+use realty_common::schema::{self, Migration, Versioned};
+use realty_common::{versioned_storable, Error, Result};
 use serde::Serialize;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+mod market;
+mod redemption;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
+type SchemaVersionCell = StableCell<u32, Memory>;
 type IdStore = StableBTreeMap<u8, u64, Memory>;
 type InvestmentStore = StableBTreeMap<u64, Investment, Memory>;
 type TransactionStore = StableBTreeMap<u64, Transaction, Memory>;
+type CanisterRefStore = StableBTreeMap<u8, Principal, Memory>;
+// (user, property_id, investment_id)
+type HoldingIndex = StableBTreeMap<(Principal, u64, u64), (), Memory>;
+// (property_id, investment_id)
+type PropertyInvestmentIndex = StableBTreeMap<(u64, u64), (), Memory>;
+// (user, transaction_id)
+type UserTransactionIndex = StableBTreeMap<(Principal, u64), (), Memory>;
+type PaymentLedgerStore = StableBTreeMap<u8, PaymentLedger, Memory>;
+// property_id -> seconds after purchase before tokens can be transferred
+type LockupStore = StableBTreeMap<u64, u64, Memory>;
+type OrderStore = StableBTreeMap<u64, Order, Memory>;
+type OrderBookIndex = StableBTreeMap<BookKey, (), Memory>;
+// (owner, order_id)
+type UserOrderIndex = StableBTreeMap<(Principal, u64), (), Memory>;
+type TradeStore = StableBTreeMap<u64, Trade, Memory>;
+// (property_id, trade_id)
+type PropertyTradeIndex = StableBTreeMap<(u64, u64), (), Memory>;
+// user -> ledger units the market owes them, withdrawn on request
+type MarketBalanceStore = StableBTreeMap<Principal, u64, Memory>;
+type RedemptionWindowStore = StableBTreeMap<u64, RedemptionWindow, Memory>;
+type RedemptionRequestStore = StableBTreeMap<u64, RedemptionRequest, Memory>;
+// (window_id, request_id)
+type WindowRequestIndex = StableBTreeMap<(u64, u64), (), Memory>;
+// (investor, request_id)
+type UserRedemptionIndex = StableBTreeMap<(Principal, u64), (), Memory>;
+// (property_id, seq)
+type HoldingChangeLog = StableBTreeMap<(u64, u64), HoldingChange, Memory>;
+// owner -> withdrawal awaiting confirmation from the ledger
+type PendingWithdrawalStore = StableBTreeMap<Principal, Withdrawal, Memory>;
+// property_id -> canister serving the property's ICRC-1 token
+type PropertyTokenStore = StableBTreeMap<u64, Principal, Memory>;
+// (property_id, failure_id)
+type SettlementFailureStore = StableBTreeMap<(u64, u64), SettlementFailure, Memory>;
+
+const PROPERTY_CANISTER_KEY: u8 = 0;
+const USER_CANISTER_KEY: u8 = 1;
+const PAYMENT_LEDGER_KEY: u8 = 0;
+const ORDER_COUNTER_KEY: u8 = 2;
+const TRADE_COUNTER_KEY: u8 = 3;
+const REDEMPTION_WINDOW_COUNTER_KEY: u8 = 4;
+const REDEMPTION_REQUEST_COUNTER_KEY: u8 = 5;
+const WITHDRAWAL_COUNTER_KEY: u8 = 6;
+const SETTLEMENT_FAILURE_COUNTER_KEY: u8 = 7;
+/// Longest lockup a property can set, ten years.
+const MAX_LOCKUP_SECONDS: u64 = 10 * 365 * 24 * 60 * 60;
+/// Property IDs start at 1, so this subaccount ID is free to hold the
+/// payments escrowed by buy orders.
+const MARKET_ESCROW_ID: u64 = 0;
+
+/// Bump when a stored record changes shape and add the matching migration.
+const SCHEMA_VERSION: u32 = 3;
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct Investment {
@@ -28,12 +92,39 @@ pub struct Transaction {
     pub id: u64,
     pub user_id: Principal,
     pub property_id: u64,
-    pub transaction_type: String, // "purchase", "dividend", "sale"
+    pub transaction_type: String, // "purchase", "dividend", "sale", "maintenance", "transfer_out", "transfer_in"
     pub amount: u64, // in USD cents
     pub tokens: u64,
     pub timestamp: u64,
+    pub ledger_block: Option<u64>, // Ledger transfer that paid for a purchase
+}
+
+impl Versioned for Investment {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(Investment);
+
+impl Versioned for Transaction {
+    const VERSION: u8 = 1;
 }
 
+versioned_storable!(Transaction);
+
+/// ICRC-2 ledger investments are paid on. Prices are kept in USD cents, so
+/// `units_per_cent` converts them into the ledger's base units.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct PaymentLedger {
+    pub canister: Principal,
+    pub units_per_cent: u64,
+}
+
+impl Versioned for PaymentLedger {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(PaymentLedger);
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct CreateInvestmentRequest {
     pub property_id: u64,
@@ -41,6 +132,13 @@ pub struct CreateInvestmentRequest {
     pub investment_amount: u64,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferTokensRequest {
+    pub property_id: u64,
+    pub to: Principal,
+    pub tokens: u64,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct PortfolioSummary {
     pub total_value: u64,
@@ -49,6 +147,72 @@ pub struct PortfolioSummary {
     pub total_returns: u64,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TokenBalance {
+    pub holder: Principal,
+    pub tokens: u64,
+}
+
+/// A holder's new token balance in a property, logged whenever one of their
+/// investments in it changes so the property's token canister can mirror
+/// balances.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct HoldingChange {
+    pub seq: u64, // Starts at 1 and increases by one per change of the property
+    pub property_id: u64,
+    pub holder: Principal,
+    pub balance: u64,
+}
+
+impl Versioned for HoldingChange {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(HoldingChange);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub property_canister: Principal,
+    pub payment_ledger: Option<PaymentLedger>,
+    pub user_canister: Option<Principal>, // Source of KYC status for transfers
+    pub roles: Vec<RoleAssignment>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct ReserveTokensRequest {
+    property_id: u64,
+    tokens: u64,
+    investment_amount: u64,
+}
+
+/// The fields of the property canister's `Property` its net asset value is
+/// taken from.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct PropertyValuation {
+    total_value: u64,
+    total_tokens: u64,
+}
+
+/// The fields of the property canister's `Property` a redemption window
+/// is checked against.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct PropertyStatus {
+    is_active: bool,
+}
+
+/// The fields of the user canister's `User` that transfers check.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct UserStatus {
+    kyc_status: String,
+    is_active: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct UpdateTokensRequest {
+    property_id: u64,
+    tokens_purchased: u64,
+}
+
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
         MemoryManager::init(DefaultMemoryImpl::default())
@@ -71,208 +235,2309 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
         )
     );
+
+    static CANISTER_REFS: RefCell<CanisterRefStore> = RefCell::new(
+        CanisterRefStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+        )
+    );
+
+    static ROLES: RefCell<RoleStore<Memory>> = RefCell::new(
+        RoleStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
+        )
+    );
+
+    static STORED_SCHEMA_VERSION: RefCell<SchemaVersionCell> = RefCell::new(
+        SchemaVersionCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))),
+            0,
+        ).expect("failed to initialize schema version")
+    );
+
+    static HOLDING_INDEX: RefCell<HoldingIndex> = RefCell::new(
+        HoldingIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+        )
+    );
+
+    static PROPERTY_INVESTMENT_INDEX: RefCell<PropertyInvestmentIndex> = RefCell::new(
+        PropertyInvestmentIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+        )
+    );
+
+    static USER_TRANSACTION_INDEX: RefCell<UserTransactionIndex> = RefCell::new(
+        UserTransactionIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+        )
+    );
+
+    static PAYMENT_LEDGER: RefCell<PaymentLedgerStore> = RefCell::new(
+        PaymentLedgerStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+        )
+    );
+
+    static LOCKUPS: RefCell<LockupStore> = RefCell::new(
+        LockupStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+        )
+    );
+
+    static ORDERS: RefCell<OrderStore> = RefCell::new(
+        OrderStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+        )
+    );
+
+    static ORDER_BOOK: RefCell<OrderBookIndex> = RefCell::new(
+        OrderBookIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+        )
+    );
+
+    static USER_ORDER_INDEX: RefCell<UserOrderIndex> = RefCell::new(
+        UserOrderIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+        )
+    );
+
+    static TRADES: RefCell<TradeStore> = RefCell::new(
+        TradeStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+        )
+    );
+
+    static PROPERTY_TRADE_INDEX: RefCell<PropertyTradeIndex> = RefCell::new(
+        PropertyTradeIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+        )
+    );
+
+    static MARKET_BALANCES: RefCell<MarketBalanceStore> = RefCell::new(
+        MarketBalanceStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+        )
+    );
+
+    static REDEMPTION_WINDOWS: RefCell<RedemptionWindowStore> = RefCell::new(
+        RedemptionWindowStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+        )
+    );
+
+    static REDEMPTION_REQUESTS: RefCell<RedemptionRequestStore> = RefCell::new(
+        RedemptionRequestStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+        )
+    );
+
+    static WINDOW_REQUEST_INDEX: RefCell<WindowRequestIndex> = RefCell::new(
+        WindowRequestIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+        )
+    );
+
+    static USER_REDEMPTION_INDEX: RefCell<UserRedemptionIndex> = RefCell::new(
+        UserRedemptionIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+        )
+    );
+
+    static HOLDING_CHANGES: RefCell<HoldingChangeLog> = RefCell::new(
+        HoldingChangeLog::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+        )
+    );
+
+    static PROPERTY_TOKENS: RefCell<PropertyTokenStore> = RefCell::new(
+        PropertyTokenStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+        )
+    );
+
+    static PENDING_WITHDRAWALS: RefCell<PendingWithdrawalStore> = RefCell::new(
+        PendingWithdrawalStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+        )
+    );
+
+    static SETTLEMENT_FAILURES: RefCell<SettlementFailureStore> = RefCell::new(
+        SettlementFailureStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+        )
+    );
 }
 
 #[init]
-fn init() {
+fn init(args: InitArgs) {
+    set_schema_version(SCHEMA_VERSION);
+
     // Initialize ID counters
     ID_COUNTER.with(|counter| {
         counter.borrow_mut().insert(0, 0); // investment counter
         counter.borrow_mut().insert(1, 0); // transaction counter
+        counter.borrow_mut().insert(ORDER_COUNTER_KEY, 0);
+        counter.borrow_mut().insert(TRADE_COUNTER_KEY, 0);
+        counter.borrow_mut().insert(REDEMPTION_WINDOW_COUNTER_KEY, 0);
+        counter.borrow_mut().insert(REDEMPTION_REQUEST_COUNTER_KEY, 0);
+        counter.borrow_mut().insert(WITHDRAWAL_COUNTER_KEY, 0);
     });
-}
 
-#[update]
-fn create_investment(req: CreateInvestmentRequest) -> Result<Investment, String> {
-    let caller = ic_cdk::caller();
-    
-    // Generate new investment ID
-    let investment_id = ID_COUNTER.with(|counter| {
-        let mut counter = counter.borrow_mut();
-        let current_id = counter.get(&0).unwrap_or(0);
-        let new_id = current_id + 1;
-        counter.insert(0, new_id);
-        new_id
+    CANISTER_REFS.with(|refs| {
+        let mut refs = refs.borrow_mut();
+        refs.insert(PROPERTY_CANISTER_KEY, args.property_canister);
+        if let Some(user_canister) = args.user_canister {
+            refs.insert(USER_CANISTER_KEY, user_canister);
+        }
     });
 
-    // Create investment record
-    let investment = Investment {
-        id: investment_id,
-        user_id: caller,
-        property_id: req.property_id,
-        tokens_owned: req.tokens_to_purchase,
-        investment_amount: req.investment_amount,
-        current_value: req.investment_amount, // Initially same as investment
-        purchase_date: time(),
-        is_active: true,
-    };
+    if let Some(ledger) = args.payment_ledger {
+        ledger.validate().expect("invalid payment ledger");
+        PAYMENT_LEDGER.with(|store| store.borrow_mut().insert(PAYMENT_LEDGER_KEY, ledger));
+    }
 
-    // Store investment
-    INVESTMENT_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(investment_id, investment.clone());
-    });
+    ROLES.with(|roles| access::assign_roles(&mut roles.borrow_mut(), args.roles));
+}
 
-    // Create transaction record
-    create_transaction_record(
-        caller,
-        req.property_id,
-        "purchase".to_string(),
-        req.investment_amount,
-        req.tokens_to_purchase,
-    );
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "re-encode investments and transactions in the versioned record format",
+        run: reencode_records,
+    },
+    Migration {
+        version: 2,
+        description: "build holding, property and transaction indexes",
+        run: rebuild_indexes,
+    },
+    Migration {
+        version: 3,
+        description: "log current holdings for property token canisters",
+        run: log_current_holdings,
+    },
+];
 
-    Ok(investment)
+#[post_upgrade]
+fn post_upgrade() {
+    let stored = STORED_SCHEMA_VERSION.with(|v| *v.borrow().get());
+    let migrated = schema::run_migrations(stored, MIGRATIONS);
+    assert_eq!(migrated, SCHEMA_VERSION, "missing migration to schema v{}", SCHEMA_VERSION);
+    set_schema_version(migrated);
 }
 
-fn create_transaction_record(
-    user_id: Principal,
-    property_id: u64,
-    transaction_type: String,
-    amount: u64,
-    tokens: u64,
-) -> u64 {
-    let transaction_id = ID_COUNTER.with(|counter| {
-        let mut counter = counter.borrow_mut();
-        let current_id = counter.get(&1).unwrap_or(0);
-        let new_id = current_id + 1;
-        counter.insert(1, new_id);
-        new_id
+fn set_schema_version(version: u32) {
+    STORED_SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(version)
+            .expect("failed to store schema version");
     });
+}
 
-    let transaction = Transaction {
-        id: transaction_id,
-        user_id,
-        property_id,
-        transaction_type,
-        amount,
-        tokens,
-        timestamp: time(),
-    };
-
+/// Rewrites every record so that blobs stored before versioning existed are
+/// persisted in the current format.
+fn reencode_records() {
+    INVESTMENT_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let entries: Vec<_> = storage.iter().collect();
+        for (key, record) in entries {
+            storage.insert(key, record);
+        }
+    });
     TRANSACTION_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(transaction_id, transaction);
+        let mut storage = storage.borrow_mut();
+        let entries: Vec<_> = storage.iter().collect();
+        for (key, record) in entries {
+            storage.insert(key, record);
+        }
     });
-
-    transaction_id
 }
 
-#[query]
-fn get_user_investments(user_id: Principal) -> Vec<Investment> {
+fn rebuild_indexes() {
     INVESTMENT_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, investment)| investment.user_id == user_id && investment.is_active)
-            .map(|(_, investment)| investment)
-            .collect()
-    })
+        for (_, investment) in storage.borrow().iter() {
+            index_investment(&investment);
+        }
+    });
+    TRANSACTION_STORAGE.with(|storage| {
+        for (id, transaction) in storage.borrow().iter() {
+            USER_TRANSACTION_INDEX.with(|index| {
+                index.borrow_mut().insert((transaction.user_id, id), ());
+            });
+        }
+    });
 }
 
-#[query]
-fn get_property_investments(property_id: u64) -> Vec<Investment> {
-    INVESTMENT_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, investment)| investment.property_id == property_id && investment.is_active)
-            .map(|(_, investment)| investment)
-            .collect()
-    })
+fn log_current_holdings() {
+    let mut property_ids: Vec<u64> = PROPERTY_INVESTMENT_INDEX.with(|index| {
+        index.borrow().iter().map(|((property_id, _), _)| property_id).collect()
+    });
+    property_ids.dedup();
+    for property_id in property_ids {
+        for (holder, balance) in holder_balances(property_id) {
+            log_holding_change(property_id, holder, balance);
+        }
+    }
 }
 
 #[query]
-fn get_user_transactions(user_id: Principal) -> Vec<Transaction> {
-    TRANSACTION_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, transaction)| transaction.user_id == user_id)
-            .map(|(_, transaction)| transaction)
-            .collect()
-    })
+fn get_schema_version() -> u32 {
+    STORED_SCHEMA_VERSION.with(|v| *v.borrow().get())
 }
 
-#[query]
-fn get_user_portfolio_summary(user_id: Principal) -> PortfolioSummary {
-    let investments = get_user_investments(user_id);
-    
-    let total_value = investments.iter().map(|inv| inv.current_value).sum();
-    let total_investments = investments.iter().map(|inv| inv.investment_amount).sum();
-    let active_properties = investments.len() as u64;
-    let total_returns = if total_value > total_investments {
-        total_value - total_investments
-    } else {
-        0
-    };
-
-    PortfolioSummary {
-        total_value,
-        total_investments,
-        active_properties,
-        total_returns,
-    }
+fn require_role(role: Role) -> Result<()> {
+    ROLES.with(|roles| access::require_role(&roles.borrow(), role))
 }
 
 #[update]
-fn update_investment_value(investment_id: u64, new_value: u64) -> Result<Investment, String> {
-    INVESTMENT_STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
-        
-        if let Some(mut investment) = storage.get(&investment_id) {
-            investment.current_value = new_value;
-            storage.insert(investment_id, investment.clone());
-            Ok(investment)
-        } else {
-            Err("Investment not found".to_string())
-        }
-    })
+fn grant_role(assignment: RoleAssignment) -> Result<()> {
+    ROLES.with(|roles| access::grant_role(&mut roles.borrow_mut(), assignment))
 }
 
 #[update]
-fn process_dividend(
-    user_id: Principal,
-    property_id: u64,
-    dividend_amount: u64,
-) -> Result<u64, String> {
-    // Create dividend transaction
-    let transaction_id = create_transaction_record(
-        user_id,
-        property_id,
-        "dividend".to_string(),
-        dividend_amount,
-        0, // No tokens involved in dividend
-    );
-
-    Ok(transaction_id)
+fn revoke_role(assignment: RoleAssignment) -> Result<()> {
+    ROLES.with(|roles| access::revoke_role(&mut roles.borrow_mut(), assignment))
 }
 
 #[query]
-fn get_total_tokens_by_property(property_id: u64) -> u64 {
-    INVESTMENT_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, investment)| investment.property_id == property_id && investment.is_active)
-            .map(|(_, investment)| investment.tokens_owned)
-            .sum()
+fn get_roles(principal: Principal) -> Vec<Role> {
+    ROLES.with(|roles| access::get_roles(&roles.borrow(), &principal))
+}
+
+fn property_canister_id() -> Result<Principal> {
+    CANISTER_REFS.with(|refs| {
+        refs.borrow()
+            .get(&PROPERTY_CANISTER_KEY)
+            .ok_or_else(|| Error::invalid_state("property canister is not configured; a controller must call set_property_canister"))
     })
 }
 
+/// Sets the property canister tokens are reserved on, for canisters
+/// installed before it was part of `InitArgs` or moved to a new canister.
+#[update]
+fn set_property_canister(property_canister: Principal) -> Result<()> {
+    require_role(Role::Controller)?;
+    CANISTER_REFS.with(|refs| {
+        refs.borrow_mut().insert(PROPERTY_CANISTER_KEY, property_canister);
+    });
+    Ok(())
+}
+
 #[query]
-fn get_user_tokens_for_property(user_id: Principal, property_id: u64) -> u64 {
-    INVESTMENT_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, investment)| {
-                investment.user_id == user_id 
-                && investment.property_id == property_id 
-                && investment.is_active
-            })
-            .map(|(_, investment)| investment.tokens_owned)
-            .sum()
-    })
+fn get_property_canister() -> Option<Principal> {
+    CANISTER_REFS.with(|refs| refs.borrow().get(&PROPERTY_CANISTER_KEY))
 }
 
-// Export candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+#[update]
+fn set_user_canister(user_canister: Principal) -> Result<()> {
+    require_role(Role::Controller)?;
+    CANISTER_REFS.with(|refs| {
+        refs.borrow_mut().insert(USER_CANISTER_KEY, user_canister);
+    });
+    Ok(())
+}
+
+/// Fails unless `user` is an active user whose KYC has been verified.
+async fn ensure_verified(user: Principal) -> Result<()> {
+    let user_canister = CANISTER_REFS
+        .with(|refs| refs.borrow().get(&USER_CANISTER_KEY))
+        .ok_or_else(|| Error::internal("user canister is not configured"))?;
+    let (status,): (Option<UserStatus>,) = ic_cdk::call(user_canister, "get_user", (user,))
+        .await
+        .map_err(|err| Error::call_failed("get_user", err))?;
+    match status {
+        Some(status) if status.kyc_status == "verified" && status.is_active => Ok(()),
+        _ => Err(Error::unauthorized(&format!("user {} is not KYC verified", user))),
+    }
+}
+
+fn lockup_period(property_id: u64) -> u64 {
+    LOCKUPS.with(|lockups| lockups.borrow().get(&property_id).unwrap_or(0))
+}
+
+/// Lockup of `property_id` in nanoseconds, saturating for periods stored
+/// before they were bounded.
+fn lockup_nanos(property_id: u64) -> u64 {
+    lockup_period(property_id).saturating_mul(1_000_000_000)
+}
+
+/// Sets how long after purchase tokens of `property_id` stay locked before
+/// they can be transferred.
+#[update]
+fn set_lockup_period(property_id: u64, seconds: u64) -> Result<()> {
+    require_role(Role::PropertyManager)?;
+    if seconds > MAX_LOCKUP_SECONDS {
+        return Err(Error::invalid_input(
+            "seconds",
+            &format!("must not exceed {}", MAX_LOCKUP_SECONDS),
+        ));
+    }
+    LOCKUPS.with(|lockups| {
+        lockups.borrow_mut().insert(property_id, seconds);
+    });
+    Ok(())
+}
+
+#[query]
+fn get_lockup_period(property_id: u64) -> u64 {
+    lockup_period(property_id)
+}
+
+impl PaymentLedger {
+    fn validate(&self) -> Result<()> {
+        if self.units_per_cent == 0 {
+            return Err(Error::invalid_input("units_per_cent", "must be greater than zero"));
+        }
+        Ok(())
+    }
+
+    /// Ledger units paid for `amount` USD cents.
+    fn units_for(&self, amount: u64) -> Result<u64> {
+        amount
+            .checked_mul(self.units_per_cent)
+            .ok_or_else(|| Error::invalid_input("investment_amount", "is too large to pay on the ledger"))
+    }
+}
+
+fn payment_ledger() -> Result<PaymentLedger> {
+    PAYMENT_LEDGER.with(|store| {
+        store
+            .borrow()
+            .get(&PAYMENT_LEDGER_KEY)
+            .ok_or_else(|| Error::invalid_state("payment ledger is not configured"))
+    })
+}
+
+#[update]
+fn set_payment_ledger(ledger: PaymentLedger) -> Result<()> {
+    require_role(Role::Controller)?;
+    ledger.validate()?;
+    PAYMENT_LEDGER.with(|store| store.borrow_mut().insert(PAYMENT_LEDGER_KEY, ledger));
+    Ok(())
+}
+
+#[query]
+fn get_payment_ledger() -> Option<PaymentLedger> {
+    PAYMENT_LEDGER.with(|store| store.borrow().get(&PAYMENT_LEDGER_KEY))
+}
+
+/// Account of this canister that holds the payments for `property_id`.
+#[query]
+fn get_property_account(property_id: u64) -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(icrc::subaccount_for(property_id)),
+    }
+}
+
+/// Buys tokens of a property, paid with an ICRC-2 allowance the caller has
+/// granted this canister for `investment_amount` in ledger units plus the
+/// ledger fee. The tokens are first reserved on the property canister, which
+/// checks the amount matches tokens × price; the payment is then moved into
+/// the property's account and only once it has landed is the investment
+/// recorded. The reservation is released if the payment fails.
+#[update]
+async fn create_investment(req: CreateInvestmentRequest) -> Result<Investment> {
+    let caller = ic_cdk::caller();
+
+    if req.tokens_to_purchase == 0 {
+        return Err(Error::invalid_input("tokens_to_purchase", "must purchase at least one token"));
+    }
+
+    let ledger = payment_ledger()?;
+    let payment = ledger.units_for(req.investment_amount)?;
+    let property_canister = property_canister_id()?;
+
+    let reservation = ReserveTokensRequest {
+        property_id: req.property_id,
+        tokens: req.tokens_to_purchase,
+        investment_amount: req.investment_amount,
+    };
+    let (reserved,): (Result<u64>,) =
+        ic_cdk::call(property_canister, "reserve_tokens", (reservation,))
+            .await
+            .map_err(|err| Error::call_failed("reserve_tokens", err))?;
+    reserved?;
+
+    // Numbered before paying so the payment carries it as its memo
+    let investment_id = next_id(0)?;
+    let property_account = get_property_account(req.property_id);
+    let paid = icrc::transfer_from(
+        ledger.canister,
+        Account::of(caller),
+        property_account,
+        payment,
+        icrc::memo("invest", investment_id),
+        time(),
+    )
+    .await;
+    let ledger_block = match paid {
+        Ok(block) => block,
+        Err(err) => return Err(release_reservation(property_canister, &req, err).await),
+    };
+
+    match record_investment(investment_id, caller, &req, ledger_block) {
+        Ok(investment) => Ok(investment),
+        Err(err) => {
+            // Hand the payment back before releasing the tokens it reserved
+            let refund = async {
+                let fee = icrc::fee(ledger.canister).await?;
+                icrc::transfer(
+                    ledger.canister,
+                    Some(icrc::subaccount_for(req.property_id)),
+                    Account::of(caller),
+                    payment.saturating_sub(fee),
+                    icrc::memo("refund", investment_id),
+                    time(),
+                )
+                .await
+            };
+            let err = match refund.await {
+                Ok(_) => err,
+                Err(refund_err) => Error::Internal {
+                    reason: format!("{}; refunding ledger block {} failed: {}", err, ledger_block, refund_err),
+                },
+            };
+            Err(release_reservation(property_canister, &req, err).await)
+        }
+    }
+}
+
+/// Returns the tokens reserved for `req` to the property after the purchase
+/// failed with `err`, and the error to report.
+async fn release_reservation(property_canister: Principal, req: &CreateInvestmentRequest, err: Error) -> Error {
+    let release = UpdateTokensRequest {
+        property_id: req.property_id,
+        tokens_purchased: req.tokens_to_purchase,
+    };
+    let released: CallResult<(Result<u64>,)> =
+        ic_cdk::call(property_canister, "release_tokens", (release,)).await;
+    let release_err = match released {
+        Ok((Ok(_),)) => return err,
+        Ok((Err(release_err),)) => release_err,
+        Err(call_err) => Error::call_failed("release_tokens", call_err),
+    };
+    Error::Internal {
+        reason: format!("{}; releasing reserved tokens failed: {}", err, release_err),
+    }
+}
+
+fn record_investment(
+    investment_id: u64,
+    user_id: Principal,
+    req: &CreateInvestmentRequest,
+    ledger_block: u64,
+) -> Result<Investment> {
+    // Create investment record
+    let investment = Investment {
+        id: investment_id,
+        user_id,
+        property_id: req.property_id,
+        tokens_owned: req.tokens_to_purchase,
+        investment_amount: req.investment_amount,
+        current_value: req.investment_amount, // Initially same as investment
+        purchase_date: time(),
+        is_active: true,
+    };
+
+    // Store investment
+    save_investment(&investment);
+
+    // Create transaction record
+    create_transaction_record(
+        user_id,
+        req.property_id,
+        "purchase".to_string(),
+        req.investment_amount,
+        req.tokens_to_purchase,
+        Some(ledger_block),
+        time(),
+    );
+
+    Ok(investment)
+}
+
+/// Writes an investment and keeps the holding and property indexes in step
+/// with its owner and property.
+fn save_investment(investment: &Investment) {
+    let previous = INVESTMENT_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(investment.id, investment.clone())
+    });
+
+    if let Some(previous) = previous {
+        if previous.user_id != investment.user_id || previous.property_id != investment.property_id {
+            unindex_investment(&previous);
+            log_holding(previous.user_id, previous.property_id);
+        }
+    }
+    index_investment(investment);
+    log_holding(investment.user_id, investment.property_id);
+}
+
+/// Logs the current balance of `holder` in `property_id`.
+fn log_holding(holder: Principal, property_id: u64) {
+    log_holding_change(property_id, holder, get_user_tokens_for_property(holder, property_id));
+}
+
+fn log_holding_change(property_id: u64, holder: Principal, balance: u64) {
+    HOLDING_CHANGES.with(|log| {
+        let mut log = log.borrow_mut();
+        let seq = log
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .next_back()
+            .map_or(0, |((_, seq), _)| seq)
+            + 1;
+        log.insert((property_id, seq), HoldingChange {
+            seq,
+            property_id,
+            holder,
+            balance,
+        });
+    });
+}
+
+fn index_investment(investment: &Investment) {
+    HOLDING_INDEX.with(|index| {
+        index
+            .borrow_mut()
+            .insert((investment.user_id, investment.property_id, investment.id), ());
+    });
+    PROPERTY_INVESTMENT_INDEX.with(|index| {
+        index.borrow_mut().insert((investment.property_id, investment.id), ());
+    });
+}
+
+fn unindex_investment(investment: &Investment) {
+    HOLDING_INDEX.with(|index| {
+        index
+            .borrow_mut()
+            .remove(&(investment.user_id, investment.property_id, investment.id));
+    });
+    PROPERTY_INVESTMENT_INDEX.with(|index| {
+        index.borrow_mut().remove(&(investment.property_id, investment.id));
+    });
+}
+
+fn load_investments(ids: impl Iterator<Item = u64>) -> Vec<Investment> {
+    INVESTMENT_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.filter_map(|id| storage.get(&id)).collect()
+    })
+}
+
+/// All investments of `user`, ordered by property and then investment ID.
+fn user_investments(user_id: Principal) -> Vec<Investment> {
+    let ids: Vec<u64> = HOLDING_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((user_id, 0, 0)..=(user_id, u64::MAX, u64::MAX))
+            .map(|((_, _, id), _)| id)
+            .collect()
+    });
+    load_investments(ids.into_iter())
+}
+
+fn user_property_investments(user_id: Principal, property_id: u64) -> Vec<Investment> {
+    let ids: Vec<u64> = HOLDING_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((user_id, property_id, 0)..=(user_id, property_id, u64::MAX))
+            .map(|((_, _, id), _)| id)
+            .collect()
+    });
+    load_investments(ids.into_iter())
+}
+
+fn property_investments(property_id: u64) -> Vec<Investment> {
+    let ids: Vec<u64> = PROPERTY_INVESTMENT_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    load_investments(ids.into_iter())
+}
+
+/// Tokens of `property_id` held by each holder with an active investment.
+fn holder_balances(property_id: u64) -> BTreeMap<Principal, u64> {
+    let mut balances: BTreeMap<Principal, u64> = BTreeMap::new();
+    for investment in property_investments(property_id) {
+        if investment.is_active && investment.tokens_owned > 0 {
+            *balances.entry(investment.user_id).or_default() += investment.tokens_owned;
+        }
+    }
+    balances
+}
+
+fn next_id(counter_key: u8) -> Result<u64> {
+    ID_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        let current_id = counter.get(&counter_key).unwrap_or(0);
+        let new_id = current_id
+            .checked_add(1)
+            .ok_or_else(|| Error::internal("ID counter exhausted"))?;
+        counter.insert(counter_key, new_id);
+        Ok(new_id)
+    })
+}
+
+fn create_transaction_record(
+    user_id: Principal,
+    property_id: u64,
+    transaction_type: String,
+    amount: u64,
+    tokens: u64,
+    ledger_block: Option<u64>,
+    timestamp: u64,
+) -> u64 {
+    let transaction_id = ID_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        let current_id = counter.get(&1).unwrap_or(0);
+        let new_id = current_id + 1;
+        counter.insert(1, new_id);
+        new_id
+    });
+
+    let transaction = Transaction {
+        id: transaction_id,
+        user_id,
+        property_id,
+        transaction_type,
+        amount,
+        tokens,
+        timestamp,
+        ledger_block,
+    };
+
+    TRANSACTION_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(transaction_id, transaction);
+    });
+    USER_TRANSACTION_INDEX.with(|index| {
+        index.borrow_mut().insert((user_id, transaction_id), ());
+    });
+
+    transaction_id
+}
+
+#[query]
+fn get_user_investments(user_id: Principal, page: PageRequest<u64>) -> Page<Investment, u64> {
+    let mut investments = active_user_investments(user_id);
+    investments.sort_by_key(|investment| investment.id);
+    let entries = investments.into_iter().map(|investment| (investment.id, investment)).collect();
+    paging::paginate_sorted(entries, &page)
+}
+
+fn active_user_investments(user_id: Principal) -> Vec<Investment> {
+    user_investments(user_id)
+        .into_iter()
+        .filter(|investment| investment.is_active)
+        .collect()
+}
+
+#[query]
+fn get_property_investments(property_id: u64, page: PageRequest<u64>) -> Page<Investment, u64> {
+    let entries = property_investments(property_id)
+        .into_iter()
+        .filter(|investment| investment.is_active)
+        .map(|investment| (investment.id, investment))
+        .collect();
+    paging::paginate_sorted(entries, &page)
+}
+
+#[query]
+fn get_user_transactions(user_id: Principal, page: PageRequest<u64>) -> Page<Transaction, u64> {
+    let ids: Vec<u64> = USER_TRANSACTION_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((user_id, 0)..=(user_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let entries = TRANSACTION_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter()
+            .filter_map(|id| storage.get(&id).map(|transaction| (id, transaction)))
+            .collect()
+    });
+    paging::paginate_sorted(entries, &page)
+}
+
+#[query]
+fn get_user_portfolio_summary(user_id: Principal) -> PortfolioSummary {
+    let investments = active_user_investments(user_id);
+    
+    let total_value: u64 = investments.iter().map(|inv| inv.current_value).sum();
+    let total_investments = investments.iter().map(|inv| inv.investment_amount).sum();
+    let active_properties = investments.len() as u64;
+    let total_returns = total_value.saturating_sub(total_investments);
+
+    PortfolioSummary {
+        total_value,
+        total_investments,
+        active_properties,
+        total_returns,
+    }
+}
+
+#[update]
+fn update_investment_value(investment_id: u64, new_value: u64) -> Result<Investment> {
+    require_role(Role::PropertyManager)?;
+
+    let mut investment = INVESTMENT_STORAGE
+        .with(|storage| storage.borrow().get(&investment_id))
+        .ok_or_else(|| Error::not_found("investment"))?;
+
+    investment.current_value = new_value;
+    save_investment(&investment);
+    Ok(investment)
+}
+
+#[update]
+fn process_dividend(
+    user_id: Principal,
+    property_id: u64,
+    dividend_amount: u64,
+) -> Result<u64> {
+    require_role(Role::Treasury)?;
+
+    // Create dividend transaction
+    let transaction_id = create_transaction_record(
+        user_id,
+        property_id,
+        "dividend".to_string(),
+        dividend_amount,
+        0, // No tokens involved in dividend
+        None,
+        time(),
+    );
+
+    Ok(transaction_id)
+}
+
+/// Splits `total_amount` across the holders of `property_id` in proportion to
+/// their tokens and records a dividend transaction for each. Shares are
+/// rounded down; returns the amount actually distributed.
+#[update]
+fn distribute_dividend(property_id: u64, total_amount: u64) -> Result<u64> {
+    require_role(Role::Treasury)?;
+    if total_amount == 0 {
+        return Err(Error::invalid_input("total_amount", "must be greater than zero"));
+    }
+
+    let balances = holder_balances(property_id);
+    let total_tokens: u128 = balances.values().map(|tokens| *tokens as u128).sum();
+    if total_tokens == 0 {
+        return Err(Error::invalid_state("property has no holders"));
+    }
+
+    let mut distributed = 0;
+    for (holder, tokens) in balances {
+        let share = (total_amount as u128 * tokens as u128 / total_tokens) as u64;
+        if share > 0 {
+            create_transaction_record(holder, property_id, "dividend".to_string(), share, 0, None, time());
+            distributed += share;
+        }
+    }
+
+    Ok(distributed)
+}
+
+/// Records a maintenance payment of `amount` from the property to `payee`.
+#[update]
+fn fund_maintenance(property_id: u64, payee: Principal, amount: u64) -> Result<u64> {
+    require_role(Role::Treasury)?;
+    if amount == 0 {
+        return Err(Error::invalid_input("amount", "must be greater than zero"));
+    }
+
+    Ok(create_transaction_record(
+        payee,
+        property_id,
+        "maintenance".to_string(),
+        amount,
+        0,
+        None,
+        time(),
+    ))
+}
+
+/// Moves `tokens` of `property_id` from `from` to `to` for a transfer of the
+/// property's share token, under the same rules as `transfer_tokens`.
+/// Returns the ID of the transfer_out transaction.
+#[update]
+async fn transfer_holding(property_id: u64, from: Principal, to: Principal, tokens: u64) -> Result<u64> {
+    if get_property_token_canister(property_id) != Some(ic_cdk::caller()) {
+        return Err(Error::unauthorized("only the property's token canister can move share tokens"));
+    }
+    transfer_between(property_id, from, to, tokens).await
+}
+
+/// Transfers the caller's tokens of a property to another investor. Both
+/// must be KYC verified, and only tokens whose lockup has ended can move.
+#[update]
+async fn transfer_tokens(req: TransferTokensRequest) -> Result<u64> {
+    transfer_between(req.property_id, ic_cdk::caller(), req.to, req.tokens).await
+}
+
+async fn transfer_between(property_id: u64, from: Principal, to: Principal, tokens: u64) -> Result<u64> {
+    if tokens == 0 {
+        return Err(Error::invalid_input("tokens", "must transfer at least one token"));
+    }
+    if from == to {
+        return Err(Error::invalid_input("to", "must differ from the sender"));
+    }
+    ensure_verified(from).await?;
+    ensure_verified(to).await?;
+    // Balances are read after the awaits so they cannot go stale
+    record_transfer(property_id, from, to, tokens, time())
+}
+
+/// Moves the tokens with their cost basis and records a transaction for
+/// each side. Returns the ID of the sender's transaction.
+fn record_transfer(property_id: u64, from: Principal, to: Principal, tokens: u64, now: u64) -> Result<u64> {
+    let cost_basis = move_tokens(property_id, from, to, tokens, CostBasis::Carried, now)?;
+    let transfer_out = create_transaction_record(from, property_id, "transfer_out".to_string(), cost_basis, tokens, None, now);
+    create_transaction_record(to, property_id, "transfer_in".to_string(), cost_basis, tokens, None, now);
+    Ok(transfer_out)
+}
+
+/// Cost basis the receiver of moved tokens takes on.
+#[derive(Clone, Copy)]
+enum CostBasis {
+    /// The sender's cost basis and acquisition date move with the tokens.
+    Carried,
+    /// The receiver bought the tokens now for this many USD cents.
+    Purchased(u64),
+}
+
+/// Takes `tokens` out of `investment` and returns the cost basis and value
+/// that go with them, in proportion to the tokens taken.
+fn split_off(investment: &mut Investment, tokens: u64) -> (u64, u64) {
+    let share = |total: u64| (total as u128 * tokens as u128 / investment.tokens_owned as u128) as u64;
+    let (amount, value) = if tokens == investment.tokens_owned {
+        (investment.investment_amount, investment.current_value)
+    } else {
+        (share(investment.investment_amount), share(investment.current_value))
+    };
+    investment.tokens_owned -= tokens;
+    investment.investment_amount -= amount;
+    investment.current_value -= value;
+    investment.is_active = investment.tokens_owned > 0;
+    (amount, value)
+}
+
+/// Tokens taken out of one investment with their cost basis and value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct TakenLot {
+    investment_id: u64,
+    tokens: u64,
+    amount: u64,
+    value: u64,
+}
+
+/// Takes `tokens` out of `lots` in order and returns what was taken from
+/// each. The lots must hold enough tokens.
+fn take_from_lots(lots: &mut [Investment], tokens: u64) -> Vec<TakenLot> {
+    let mut remaining = tokens;
+    let mut taken_lots = Vec::new();
+    for lot in lots.iter_mut() {
+        if remaining == 0 {
+            break;
+        }
+        let taken = remaining.min(lot.tokens_owned);
+        let (amount, value) = split_off(lot, taken);
+        remaining -= taken;
+        save_investment(lot);
+        taken_lots.push(TakenLot {
+            investment_id: lot.id,
+            tokens: taken,
+            amount,
+            value,
+        });
+    }
+    taken_lots
+}
+
+/// Cost basis and value of the tokens in `taken`.
+fn taken_totals(taken: &[TakenLot]) -> (u64, u64) {
+    taken.iter().fold((0, 0), |(amount, value), lot| (amount + lot.amount, value + lot.value))
+}
+
+/// Puts tokens taken with `take_from_lots` back into the investments they
+/// came from.
+fn restore_lots(taken: &[TakenLot]) {
+    for lot in taken {
+        let Some(mut investment) = INVESTMENT_STORAGE.with(|storage| storage.borrow().get(&lot.investment_id)) else {
+            continue;
+        };
+        investment.tokens_owned += lot.tokens;
+        investment.investment_amount += lot.amount;
+        investment.current_value += lot.value;
+        investment.is_active = true;
+        save_investment(&investment);
+    }
+}
+
+/// Moves `tokens` out of `from`'s unlocked investments, oldest first, and
+/// returns the cost basis they carried. A partly moved investment is split,
+/// keeping the share of its cost basis and value that stays behind. Tokens
+/// offered for sale or redemption cannot move. The tokens merge into `to`'s latest
+/// investment in the property when both are past their lockup, or else
+/// arrive as a new investment. Carried tokens are dated from the oldest
+/// investment they came from, so the lockup already served carries over.
+fn move_tokens(
+    property_id: u64,
+    from: Principal,
+    to: Principal,
+    tokens: u64,
+    basis: CostBasis,
+    now: u64,
+) -> Result<u64> {
+    let lockup = lockup_nanos(property_id);
+    let unlocked_at = |acquired_at: u64| acquired_at.saturating_add(lockup) <= now;
+    let unlocked = |investment: &Investment| unlocked_at(investment.purchase_date);
+
+    let holdings: Vec<Investment> = user_property_investments(from, property_id)
+        .into_iter()
+        .filter(|investment| investment.is_active && investment.tokens_owned > 0)
+        .collect();
+    let held: u64 = holdings.iter().map(|investment| investment.tokens_owned).sum();
+    if held < tokens {
+        return Err(Error::InsufficientTokens {
+            requested: tokens,
+            available: held,
+        });
+    }
+    let mut lots: Vec<Investment> = holdings.into_iter().filter(|investment| unlocked(investment)).collect();
+    let unlocked_tokens: u64 = lots.iter().map(|investment| investment.tokens_owned).sum();
+    let transferable = unlocked_tokens.saturating_sub(tokens_on_offer(from, property_id));
+    if transferable < tokens {
+        return Err(Error::invalid_state(&format!(
+            "only {} of the sender's tokens are past their lockup and not offered for sale",
+            transferable
+        )));
+    }
+
+    // Purchased tokens start a new lockup
+    let acquired_at = match basis {
+        CostBasis::Carried => lots.first().map_or(now, |lot| lot.purchase_date),
+        CostBasis::Purchased(_) => now,
+    };
+    let merge_into = user_property_investments(to, property_id)
+        .into_iter()
+        .rfind(|investment| investment.is_active && unlocked(investment))
+        .filter(|_| unlocked_at(acquired_at));
+    // Allocated before any lot changes so a failure leaves nothing half moved
+    let new_id = match merge_into {
+        Some(_) => None,
+        None => Some(next_id(0)?),
+    };
+
+    let (moved_amount, moved_value) = taken_totals(&take_from_lots(&mut lots, tokens));
+    let (received_amount, received_value) = match basis {
+        CostBasis::Carried => (moved_amount, moved_value),
+        CostBasis::Purchased(price) => (price, price),
+    };
+
+    let received = match merge_into {
+        Some(mut investment) => {
+            investment.tokens_owned += tokens;
+            investment.investment_amount += received_amount;
+            investment.current_value += received_value;
+            investment
+        }
+        None => Investment {
+            id: new_id.expect("allocated when there is nothing to merge into"),
+            user_id: to,
+            property_id,
+            tokens_owned: tokens,
+            investment_amount: received_amount,
+            current_value: received_value,
+            purchase_date: acquired_at,
+            is_active: true,
+        },
+    };
+    save_investment(&received);
+    Ok(moved_amount)
+}
+
+/// Account of this canister that holds the payments escrowed by buy orders.
+fn market_escrow_account() -> Account {
+    get_property_account(MARKET_ESCROW_ID)
+}
+
+/// Tokens of `property_id` that `user` has offered in open sell orders or
+/// asked to redeem.
+fn tokens_on_offer(user: Principal, property_id: u64) -> u64 {
+    let in_orders: u64 = user_orders(user)
+        .iter()
+        .filter(|order| order.property_id == property_id && order.side == OrderSide::Sell && order.is_open())
+        .map(|order| order.remaining)
+        .sum();
+    // Paying requests have already taken their tokens
+    let in_redemptions: u64 = user_redemptions(user)
+        .iter()
+        .filter(|request| request.property_id == property_id && request.status == RedemptionStatus::Pending)
+        .map(|request| request.tokens)
+        .sum();
+    in_orders + in_redemptions
+}
+
+/// Tokens of `property_id` that `user` could transfer or offer now: those
+/// past their lockup that are not already on offer.
+fn transferable_tokens(user: Principal, property_id: u64, now: u64) -> u64 {
+    let lockup = lockup_nanos(property_id);
+    let unlocked: u64 = user_property_investments(user, property_id)
+        .iter()
+        .filter(|investment| investment.is_active && investment.purchase_date.saturating_add(lockup) <= now)
+        .map(|investment| investment.tokens_owned)
+        .sum();
+    unlocked.saturating_sub(tokens_on_offer(user, property_id))
+}
+
+fn user_orders(user: Principal) -> Vec<Order> {
+    let ids: Vec<u64> = USER_ORDER_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((user, 0)..=(user, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    ORDERS.with(|orders| {
+        let orders = orders.borrow();
+        ids.into_iter().filter_map(|id| orders.get(&id)).collect()
+    })
+}
+
+/// Writes an order and keeps it in the book exactly while it is open.
+fn save_order(order: &Order) {
+    ORDERS.with(|orders| orders.borrow_mut().insert(order.id, order.clone()));
+    USER_ORDER_INDEX.with(|index| index.borrow_mut().insert((order.owner, order.id), ()));
+    ORDER_BOOK.with(|book| {
+        let mut book = book.borrow_mut();
+        if order.is_open() {
+            book.insert(order.book_key(), ());
+        } else {
+            book.remove(&order.book_key());
+        }
+    });
+}
+
+fn credit_market_balance(user: Principal, units: u64) {
+    if units == 0 {
+        return;
+    }
+    MARKET_BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let balance = balances.get(&user).unwrap_or(0);
+        balances.insert(user, balance + units);
+    });
+}
+
+/// Best resting order of another owner that `order` trades with.
+fn best_counter_order(order: &Order) -> Option<Order> {
+    let side = order.side.opposite().code();
+    let ids: Vec<u64> = ORDER_BOOK.with(|book| {
+        book.borrow()
+            .range((order.property_id, side, (0, 0))..=(order.property_id, side, (u64::MAX, u64::MAX)))
+            .map(|((_, _, (_, id)), _)| id)
+            .collect()
+    });
+    ORDERS.with(|orders| {
+        let orders = orders.borrow();
+        ids.into_iter()
+            .filter_map(|id| orders.get(&id))
+            .take_while(|resting| order.crosses(resting.price))
+            .find(|resting| resting.owner != order.owner)
+    })
+}
+
+/// Places a limit order on a property's tokens. A buy order escrows its
+/// full value through an ICRC-2 allowance the caller has granted this
+/// canister for price × tokens in ledger units plus the ledger fee; a sell
+/// order needs as many tokens past their lockup and not already on offer.
+/// The order then trades with the best resting orders, price first and then
+/// time, and whatever remains rests on the book.
+#[update]
+async fn place_order(req: PlaceOrderRequest) -> Result<Order> {
+    let caller = ic_cdk::caller();
+    let value = req.validate()?;
+    let ledger = payment_ledger()?;
+    let escrow = ledger.units_for(value)?;
+    ensure_verified(caller).await?;
+
+    let order_id = next_id(ORDER_COUNTER_KEY)?;
+    match req.side {
+        OrderSide::Buy => {
+            icrc::transfer_from(
+                ledger.canister,
+                Account::of(caller),
+                market_escrow_account(),
+                escrow,
+                icrc::memo("order", order_id),
+                time(),
+            )
+            .await?;
+        }
+        OrderSide::Sell => {
+            // Read after the awaits so the holdings cannot go stale
+            let available = transferable_tokens(caller, req.property_id, time());
+            if available < req.tokens {
+                return Err(Error::InsufficientTokens {
+                    requested: req.tokens,
+                    available,
+                });
+            }
+        }
+    }
+
+    let order = Order {
+        id: order_id,
+        property_id: req.property_id,
+        owner: caller,
+        side: req.side,
+        price: req.price,
+        tokens: req.tokens,
+        remaining: req.tokens,
+        created_at: time(),
+        status: OrderStatus::Open,
+    };
+    save_order(&order);
+    Ok(match_order(order, &ledger, time()))
+}
+
+/// Trades `order` against the book until it is filled, nothing crosses or
+/// it reaches the fill limit. A sell order whose tokens can no longer move
+/// is cancelled and matching goes on; any other failure stops matching and
+/// leaves the rest of `order` on the book. Failures are recorded.
+fn match_order(mut order: Order, ledger: &PaymentLedger, now: u64) -> Order {
+    let mut fills = 0;
+    while order.is_open() && fills < market::MAX_FILLS_PER_ORDER {
+        let Some(mut resting) = best_counter_order(&order) else {
+            break;
+        };
+        let price = resting.price;
+        let tokens = order.remaining.min(resting.remaining);
+        let (buy, sell) = match order.side {
+            OrderSide::Buy => (&mut order, &mut resting),
+            OrderSide::Sell => (&mut resting, &mut order),
+        };
+        match settle(buy, sell, price, tokens, ledger, now) {
+            Ok(_) => {}
+            Err(SettleError::Seller(err)) => {
+                sell.status = OrderStatus::Cancelled;
+                save_order(sell);
+                record_settlement_failure(buy, sell, err, now);
+            }
+            Err(SettleError::Other(err)) => {
+                record_settlement_failure(buy, sell, err, now);
+                break;
+            }
+        }
+        fills += 1;
+    }
+    order
+}
+
+/// Why a trade could not be settled.
+#[derive(Debug)]
+enum SettleError {
+    /// The seller's tokens can no longer move, so the sell order cannot fill.
+    Seller(Error),
+    Other(Error),
+}
+
+fn record_settlement_failure(buy: &Order, sell: &Order, error: Error, now: u64) {
+    // Nothing to record under if the counter is exhausted
+    let Ok(id) = next_id(SETTLEMENT_FAILURE_COUNTER_KEY) else {
+        return;
+    };
+    let failure = SettlementFailure {
+        id,
+        property_id: sell.property_id,
+        buy_order_id: buy.id,
+        sell_order_id: sell.id,
+        error,
+        sell_cancelled: sell.status == OrderStatus::Cancelled,
+        failed_at: now,
+    };
+    SETTLEMENT_FAILURES.with(|failures| failures.borrow_mut().insert((failure.property_id, id), failure));
+}
+
+/// Executes a trade of `tokens` at `price` between two crossing orders. The
+/// tokens move to the buyer at the trade price as their cost basis, the
+/// seller is credited the payment and the buyer gets back what they
+/// escrowed above the trade price. Both sides record a sale transaction.
+/// On failure both orders are left as they were.
+fn settle(
+    buy: &mut Order,
+    sell: &mut Order,
+    price: u64,
+    tokens: u64,
+    ledger: &PaymentLedger,
+    now: u64,
+) -> std::result::Result<u64, SettleError> {
+    // Both fit: the order values were checked when the orders were placed
+    let cost = price * tokens;
+    let paid = ledger.units_for(cost).map_err(SettleError::Other)?;
+    let escrowed = ledger.units_for(buy.price * tokens).map_err(SettleError::Other)?;
+    let trade_id = next_id(TRADE_COUNTER_KEY).map_err(SettleError::Other)?;
+
+    // The tokens being sold must not count as on offer while they move
+    sell.fill(tokens);
+    save_order(sell);
+    if let Err(err) = move_tokens(sell.property_id, sell.owner, buy.owner, tokens, CostBasis::Purchased(cost), now) {
+        sell.remaining += tokens;
+        sell.status = OrderStatus::Open;
+        save_order(sell);
+        return Err(SettleError::Seller(err));
+    }
+    buy.fill(tokens);
+    save_order(buy);
+
+    credit_market_balance(sell.owner, paid);
+    credit_market_balance(buy.owner, escrowed - paid);
+
+    let trade = Trade {
+        id: trade_id,
+        property_id: sell.property_id,
+        buy_order_id: buy.id,
+        sell_order_id: sell.id,
+        buyer: buy.owner,
+        seller: sell.owner,
+        price,
+        tokens,
+        executed_at: now,
+    };
+    TRADES.with(|trades| trades.borrow_mut().insert(trade.id, trade.clone()));
+    PROPERTY_TRADE_INDEX.with(|index| index.borrow_mut().insert((trade.property_id, trade.id), ()));
+
+    create_transaction_record(sell.owner, trade.property_id, "sale".to_string(), cost, tokens, None, now);
+    create_transaction_record(buy.owner, trade.property_id, "sale".to_string(), cost, tokens, None, now);
+    Ok(trade.id)
+}
+
+/// Cancels the caller's open order. The unspent escrow of a buy order is
+/// credited to the caller's market balance.
+#[update]
+fn cancel_order(order_id: u64) -> Result<Order> {
+    let mut order = ORDERS
+        .with(|orders| orders.borrow().get(&order_id))
+        .ok_or_else(|| Error::not_found("Order"))?;
+    if order.owner != ic_cdk::caller() {
+        return Err(Error::unauthorized("only the owner can cancel an order"));
+    }
+    if !order.is_open() {
+        return Err(Error::invalid_state("order is no longer open"));
+    }
+
+    if order.side == OrderSide::Buy {
+        let refund = payment_ledger()?.units_for(order.price * order.remaining)?;
+        credit_market_balance(order.owner, refund);
+    }
+    order.status = OrderStatus::Cancelled;
+    save_order(&order);
+    Ok(order)
+}
+
+/// Pays the caller's market balance out of escrow to their ledger account,
+/// less the ledger fee. Returns the ledger block of the payout. A payout
+/// whose outcome is unknown stays pending and is resent unchanged by the
+/// caller's next withdrawal, so the ledger can recognise it as a duplicate.
+#[update]
+async fn withdraw_market_balance() -> Result<u64> {
+    let caller = ic_cdk::caller();
+    let ledger = payment_ledger()?;
+    let fee = icrc::fee(ledger.canister).await?;
+
+    let withdrawal = match get_pending_withdrawal(caller) {
+        Some(withdrawal) => withdrawal,
+        None => {
+            let balance = get_market_balance(caller);
+            if balance <= fee {
+                return Err(Error::invalid_state("market balance does not cover the ledger fee"));
+            }
+            let withdrawal = Withdrawal {
+                id: next_id(WITHDRAWAL_COUNTER_KEY)?,
+                balance,
+                transfer: LedgerTransfer {
+                    amount: balance - fee,
+                    created_at_time: time(),
+                },
+            };
+            // Taken before the transfer so a concurrent withdrawal cannot pay it twice
+            MARKET_BALANCES.with(|balances| balances.borrow_mut().remove(&caller));
+            PENDING_WITHDRAWALS.with(|pending| pending.borrow_mut().insert(caller, withdrawal.clone()));
+            withdrawal
+        }
+    };
+    let paid = icrc::transfer(
+        ledger.canister,
+        Some(icrc::subaccount_for(MARKET_ESCROW_ID)),
+        Account::of(caller),
+        withdrawal.transfer.amount,
+        icrc::memo("withdraw", withdrawal.id),
+        withdrawal.transfer.created_at_time,
+    )
+    .await;
+    match &paid {
+        Err(err) if icrc::outcome_unknown(err) => {}
+        result => {
+            // Only the first response settles a withdrawal resent concurrently
+            let settled = PENDING_WITHDRAWALS.with(|pending| {
+                let mut pending = pending.borrow_mut();
+                match pending.get(&caller) {
+                    Some(pending_withdrawal) if pending_withdrawal.id == withdrawal.id => pending.remove(&caller),
+                    _ => None,
+                }
+            });
+            if settled.is_some() && result.is_err() {
+                credit_market_balance(caller, withdrawal.balance);
+            }
+        }
+    }
+    paid
+}
+
+/// Withdrawal of `user` whose payout may have reached the ledger.
+#[query]
+fn get_pending_withdrawal(user: Principal) -> Option<Withdrawal> {
+    PENDING_WITHDRAWALS.with(|pending| pending.borrow().get(&user))
+}
+
+/// Ledger units the market holds for `user` from sales, price improvements
+/// and cancelled buy orders.
+#[query]
+fn get_market_balance(user: Principal) -> u64 {
+    MARKET_BALANCES.with(|balances| balances.borrow().get(&user).unwrap_or(0))
+}
+
+#[query]
+fn get_order(order_id: u64) -> Option<Order> {
+    ORDERS.with(|orders| orders.borrow().get(&order_id))
+}
+
+#[query]
+fn get_user_orders(user: Principal, page: PageRequest<u64>) -> Page<Order, u64> {
+    let entries = user_orders(user).into_iter().map(|order| (order.id, order)).collect();
+    paging::paginate_sorted(entries, &page)
+}
+
+/// Open orders of a property summed per price, `depth` levels per side.
+#[query]
+fn get_order_book(property_id: u64, depth: Option<u32>) -> OrderBook {
+    let depth = depth.unwrap_or(market::DEFAULT_BOOK_DEPTH).clamp(1, market::MAX_BOOK_DEPTH) as usize;
+    let side_levels = |side: OrderSide| {
+        let side = side.code();
+        let ids: Vec<u64> = ORDER_BOOK.with(|book| {
+            book.borrow()
+                .range((property_id, side, (0, 0))..=(property_id, side, (u64::MAX, u64::MAX)))
+                .map(|((_, _, (_, id)), _)| id)
+                .collect()
+        });
+        let orders: Vec<Order> = ORDERS.with(|orders| {
+            let orders = orders.borrow();
+            ids.into_iter().filter_map(|id| orders.get(&id)).collect()
+        });
+        market::price_levels(orders.iter(), depth)
+    };
+    OrderBook {
+        property_id,
+        bids: side_levels(OrderSide::Buy),
+        asks: side_levels(OrderSide::Sell),
+    }
+}
+
+/// Trades of a property that could not be settled while matching orders.
+#[query]
+fn get_settlement_failures(property_id: u64, page: PageRequest<u64>) -> Page<SettlementFailure, u64> {
+    let entries = SETTLEMENT_FAILURES.with(|failures| {
+        failures
+            .borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|((_, id), failure)| (id, failure))
+            .collect()
+    });
+    paging::paginate_sorted(entries, &page)
+}
+
+#[query]
+fn get_property_trades(property_id: u64, page: PageRequest<u64>) -> Page<Trade, u64> {
+    let ids: Vec<u64> = PROPERTY_TRADE_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let entries = TRADES.with(|trades| {
+        let trades = trades.borrow();
+        ids.into_iter()
+            .filter_map(|id| trades.get(&id).map(|trade| (id, trade)))
+            .collect()
+    });
+    paging::paginate_sorted(entries, &page)
+}
+
+/// Opens a window in which investors can ask to sell tokens of a property
+/// back to it, optionally limited to `max_tokens` in total. The property
+/// must be active, and the window must not overlap another window of the
+/// property that is not yet settled.
+#[update]
+async fn open_redemption_window(req: OpenRedemptionWindowRequest) -> Result<RedemptionWindow> {
+    require_role(Role::PropertyManager)?;
+    req.validate(time())?;
+
+    let (property,): (Option<PropertyStatus>,) =
+        ic_cdk::call(property_canister_id()?, "get_property", (req.property_id,))
+            .await
+            .map_err(|err| Error::call_failed("get_property", err))?;
+    match property {
+        Some(property) if property.is_active => {}
+        Some(_) => return Err(Error::invalid_state("property is not active")),
+        None => return Err(Error::not_found("property")),
+    }
+    // Checked after the await so a window opened meanwhile is seen
+    ensure_no_overlapping_window(&req, time())?;
+
+    let window = RedemptionWindow {
+        id: next_id(REDEMPTION_WINDOW_COUNTER_KEY)?,
+        property_id: req.property_id,
+        opens_at: req.opens_at,
+        closes_at: req.closes_at,
+        max_tokens: req.max_tokens,
+        nav_per_token: None,
+        redeemed_tokens: 0,
+        unreturned_tokens: 0,
+    };
+    save_redemption_window(&window);
+    Ok(window)
+}
+
+fn ensure_no_overlapping_window(req: &OpenRedemptionWindowRequest, now: u64) -> Result<()> {
+    let windows: Vec<RedemptionWindow> = REDEMPTION_WINDOWS.with(|windows| {
+        windows
+            .borrow()
+            .iter()
+            .map(|(_, window)| window)
+            .filter(|window| window.property_id == req.property_id && window.overlaps(req.opens_at, req.closes_at))
+            .collect()
+    });
+    for window in windows {
+        let has_outstanding_requests = load_redemption_requests(window_request_ids(window.id).into_iter())
+            .iter()
+            .any(RedemptionRequest::is_outstanding);
+        if !window.is_settled(now, has_outstanding_requests) {
+            return Err(Error::invalid_state(&format!(
+                "overlaps redemption window {}, which is not settled",
+                window.id
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn save_redemption_window(window: &RedemptionWindow) {
+    REDEMPTION_WINDOWS.with(|windows| windows.borrow_mut().insert(window.id, window.clone()));
+}
+
+fn redemption_window(window_id: u64) -> Result<RedemptionWindow> {
+    get_redemption_window(window_id).ok_or_else(|| Error::not_found("Redemption window"))
+}
+
+fn save_redemption_request(request: &RedemptionRequest) {
+    REDEMPTION_REQUESTS.with(|requests| requests.borrow_mut().insert(request.id, request.clone()));
+    WINDOW_REQUEST_INDEX.with(|index| index.borrow_mut().insert((request.window_id, request.id), ()));
+    USER_REDEMPTION_INDEX.with(|index| index.borrow_mut().insert((request.investor, request.id), ()));
+}
+
+fn load_redemption_requests(ids: impl Iterator<Item = u64>) -> Vec<RedemptionRequest> {
+    REDEMPTION_REQUESTS.with(|requests| {
+        let requests = requests.borrow();
+        ids.filter_map(|id| requests.get(&id)).collect()
+    })
+}
+
+fn window_request_ids(window_id: u64) -> Vec<u64> {
+    WINDOW_REQUEST_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((window_id, 0)..=(window_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    })
+}
+
+fn user_redemptions(user: Principal) -> Vec<RedemptionRequest> {
+    let ids: Vec<u64> = USER_REDEMPTION_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((user, 0)..=(user, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    load_redemption_requests(ids.into_iter())
+}
+
+/// Queues a request to sell `tokens` back to the property during an open
+/// window. The tokens must be past their lockup and stay held for the
+/// request until it is settled or cancelled.
+#[update]
+fn request_redemption(window_id: u64, tokens: u64) -> Result<RedemptionRequest> {
+    let caller = ic_cdk::caller();
+    let now = time();
+    if tokens == 0 {
+        return Err(Error::invalid_input("tokens", "must redeem at least one token"));
+    }
+    let window = redemption_window(window_id)?;
+    if !window.is_open(now) {
+        return Err(Error::invalid_state("redemption window is not open"));
+    }
+    let available = transferable_tokens(caller, window.property_id, now);
+    if available < tokens {
+        return Err(Error::InsufficientTokens {
+            requested: tokens,
+            available,
+        });
+    }
+
+    let request = RedemptionRequest {
+        id: next_id(REDEMPTION_REQUEST_COUNTER_KEY)?,
+        window_id,
+        property_id: window.property_id,
+        investor: caller,
+        tokens,
+        tokens_redeemed: 0,
+        payout: 0,
+        ledger_block: None,
+        status: RedemptionStatus::Pending,
+        requested_at: now,
+        settled_at: None,
+        unconfirmed_payout: None,
+    };
+    save_redemption_request(&request);
+    Ok(request)
+}
+
+/// Withdraws the caller's pending request while its window is still open.
+#[update]
+fn cancel_redemption(request_id: u64) -> Result<RedemptionRequest> {
+    let mut request = REDEMPTION_REQUESTS
+        .with(|requests| requests.borrow().get(&request_id))
+        .ok_or_else(|| Error::not_found("Redemption request"))?;
+    if request.investor != ic_cdk::caller() {
+        return Err(Error::unauthorized("only the investor can cancel a redemption request"));
+    }
+    if request.status != RedemptionStatus::Pending || request.unconfirmed_payout.is_some() {
+        return Err(Error::invalid_state("redemption request is no longer pending"));
+    }
+    if time() >= redemption_window(request.window_id)?.closes_at {
+        return Err(Error::invalid_state("redemption window has closed"));
+    }
+
+    request.status = RedemptionStatus::Cancelled;
+    save_redemption_request(&request);
+    Ok(request)
+}
+
+/// Fills the requests of a closed window in request order at the property's
+/// net asset value per token, which is fixed by the first settlement call.
+/// Each investor's tokens are taken before they are paid from the
+/// property's account, less the ledger fee; the redeemed tokens then go back
+/// to the property's available tokens. A failed payout puts the tokens back
+/// and stops settlement with the request still pending, so calling again
+/// resumes where it stopped.
+#[update]
+async fn settle_redemption_window(window_id: u64) -> Result<RedemptionWindow> {
+    require_role(Role::Treasury)?;
+    let window = redemption_window(window_id)?;
+    if time() < window.closes_at {
+        return Err(Error::invalid_state("redemption window is still open"));
+    }
+    let ledger = payment_ledger()?;
+    let property_canister = property_canister_id()?;
+
+    let nav = match window.nav_per_token {
+        Some(nav) => nav,
+        None => {
+            let (property,): (Option<PropertyValuation>,) =
+                ic_cdk::call(property_canister, "get_property", (window.property_id,))
+                    .await
+                    .map_err(|err| Error::call_failed("get_property", err))?;
+            let property = property.ok_or_else(|| Error::not_found("property"))?;
+            let nav = redemption::nav_per_token(property.total_value, property.total_tokens)?;
+            // A concurrent settlement may have fixed the price meanwhile
+            let mut window = redemption_window(window_id)?;
+            let nav = *window.nav_per_token.get_or_insert(nav);
+            save_redemption_window(&window);
+            nav
+        }
+    };
+    let fee = icrc::fee(ledger.canister).await?;
+
+    let mut failure = None;
+    for request_id in window_request_ids(window_id) {
+        // Read one at a time because earlier payouts awaited
+        let mut request = match REDEMPTION_REQUESTS.with(|requests| requests.borrow().get(&request_id)) {
+            Some(request) if request.status == RedemptionStatus::Pending => request,
+            _ => continue,
+        };
+        let mut window = redemption_window(window_id)?;
+        let tokens = window.fillable(request.tokens);
+        let resending = request.unconfirmed_payout.is_some();
+        let payout = nav
+            .checked_mul(tokens)
+            .ok_or_else(|| Error::internal("redemption payout overflows"))
+            .and_then(|payout| Ok((payout, ledger.units_for(payout)?)));
+        let (payout, units) = match payout {
+            Ok((payout, units)) if tokens > 0 && (units > fee || resending) => (payout, units),
+            Ok(_) => {
+                request.status = RedemptionStatus::Unfilled;
+                request.settled_at = Some(time());
+                save_redemption_request(&request);
+                continue;
+            }
+            Err(err) => {
+                failure = Some(err);
+                break;
+            }
+        };
+
+        // Taken before paying so the tokens cannot stay with a paid investor
+        let taken = match take_redeemed_tokens(request.investor, request.property_id, tokens) {
+            Ok(taken) => taken,
+            Err(err) => {
+                request.status = RedemptionStatus::Unfilled;
+                request.settled_at = Some(time());
+                save_redemption_request(&request);
+                failure.get_or_insert(err);
+                continue;
+            }
+        };
+        // A payout whose outcome is unknown is resent unchanged so the ledger
+        // can recognise it
+        let transfer = request.unconfirmed_payout.clone().unwrap_or(LedgerTransfer {
+            amount: units - fee,
+            created_at_time: time(),
+        });
+        request.status = RedemptionStatus::Paying;
+        request.tokens_redeemed = tokens;
+        request.payout = payout;
+        request.unconfirmed_payout = Some(transfer.clone());
+        save_redemption_request(&request);
+        window.redeemed_tokens += tokens;
+        save_redemption_window(&window);
+
+        let paid = icrc::transfer(
+            ledger.canister,
+            Some(icrc::subaccount_for(window.property_id)),
+            Account::of(request.investor),
+            transfer.amount,
+            icrc::memo("redeem", request.id),
+            transfer.created_at_time,
+        )
+        .await;
+        let mut window = redemption_window(window_id)?;
+        match paid {
+            Ok(block) => {
+                request.status = RedemptionStatus::Redeemed;
+                request.ledger_block = Some(block);
+                request.unconfirmed_payout = None;
+                request.settled_at = Some(time());
+                save_redemption_request(&request);
+                window.unreturned_tokens += tokens;
+                save_redemption_window(&window);
+                create_transaction_record(request.investor, request.property_id, "sale".to_string(), payout, tokens, Some(block), time());
+            }
+            Err(err) => {
+                restore_lots(&taken);
+                request.status = RedemptionStatus::Pending;
+                request.tokens_redeemed = 0;
+                request.payout = 0;
+                if !icrc::outcome_unknown(&err) {
+                    request.unconfirmed_payout = None;
+                }
+                save_redemption_request(&request);
+                window.redeemed_tokens -= tokens;
+                save_redemption_window(&window);
+                failure = Some(err);
+                break;
+            }
+        }
+    }
+
+    if let Err(err) = return_redeemed_tokens(property_canister, window_id).await {
+        failure.get_or_insert(err);
+    }
+    match failure {
+        Some(err) => Err(err),
+        None => redemption_window(window_id),
+    }
+}
+
+/// Takes redeemed tokens out of `investor`'s investments, oldest first. The
+/// lockup is not checked again: the redemption request has held the tokens
+/// since it was made, when they were past their lockup.
+fn take_redeemed_tokens(investor: Principal, property_id: u64, tokens: u64) -> Result<Vec<TakenLot>> {
+    let mut lots: Vec<Investment> = user_property_investments(investor, property_id)
+        .into_iter()
+        .filter(|investment| investment.is_active && investment.tokens_owned > 0)
+        .collect();
+    let held: u64 = lots.iter().map(|investment| investment.tokens_owned).sum();
+    if held < tokens {
+        return Err(Error::InsufficientTokens {
+            requested: tokens,
+            available: held,
+        });
+    }
+    Ok(take_from_lots(&mut lots, tokens))
+}
+
+/// Hands the window's redeemed tokens back to the property's available
+/// tokens.
+async fn return_redeemed_tokens(property_canister: Principal, window_id: u64) -> Result<()> {
+    let mut window = redemption_window(window_id)?;
+    let tokens = window.unreturned_tokens;
+    if tokens == 0 {
+        return Ok(());
+    }
+    // Taken before the call so a concurrent settlement cannot return them twice
+    window.unreturned_tokens = 0;
+    save_redemption_window(&window);
+
+    let release = UpdateTokensRequest {
+        property_id: window.property_id,
+        tokens_purchased: tokens,
+    };
+    let released: CallResult<(Result<u64>,)> =
+        ic_cdk::call(property_canister, "release_tokens", (release,)).await;
+    let err = match released {
+        Ok((Ok(_),)) => return Ok(()),
+        Ok((Err(err),)) => err,
+        Err(call_err) => Error::call_failed("release_tokens", call_err),
+    };
+    let mut window = redemption_window(window_id)?;
+    window.unreturned_tokens += tokens;
+    save_redemption_window(&window);
+    Err(err)
+}
+
+#[query]
+fn get_redemption_window(window_id: u64) -> Option<RedemptionWindow> {
+    REDEMPTION_WINDOWS.with(|windows| windows.borrow().get(&window_id))
+}
+
+#[query]
+fn get_property_redemption_windows(property_id: u64, page: PageRequest<u64>) -> Page<RedemptionWindow, u64> {
+    REDEMPTION_WINDOWS.with(|windows| {
+        paging::paginate_filtered(&windows.borrow(), &page, |window| window.property_id == property_id)
+    })
+}
+
+#[query]
+fn get_window_redemptions(window_id: u64, page: PageRequest<u64>) -> Page<RedemptionRequest, u64> {
+    let entries = load_redemption_requests(window_request_ids(window_id).into_iter())
+        .into_iter()
+        .map(|request| (request.id, request))
+        .collect();
+    paging::paginate_sorted(entries, &page)
+}
+
+#[query]
+fn get_user_redemptions(user: Principal, page: PageRequest<u64>) -> Page<RedemptionRequest, u64> {
+    let entries = user_redemptions(user)
+        .into_iter()
+        .map(|request| (request.id, request))
+        .collect();
+    paging::paginate_sorted(entries, &page)
+}
+
+#[query]
+fn get_total_tokens_by_property(property_id: u64) -> u64 {
+    property_investments(property_id)
+        .iter()
+        .filter(|investment| investment.is_active)
+        .map(|investment| investment.tokens_owned)
+        .sum()
+}
+
+#[query]
+fn get_user_tokens_for_property(user_id: Principal, property_id: u64) -> u64 {
+    user_property_investments(user_id, property_id)
+        .iter()
+        .filter(|investment| investment.is_active)
+        .map(|investment| investment.tokens_owned)
+        .sum()
+}
+
+/// Holding changes of a property logged after `seq`, oldest first. Pass the
+/// `seq` of the last change received to continue, or 0 to read from the
+/// start of the log.
+#[query]
+fn get_holding_changes_since(property_id: u64, seq: u64, limit: Option<u32>) -> Vec<HoldingChange> {
+    let limit = limit.unwrap_or(paging::DEFAULT_PAGE_SIZE).clamp(1, paging::MAX_PAGE_SIZE);
+    HOLDING_CHANGES.with(|log| {
+        log.borrow()
+            .range((property_id, seq.saturating_add(1))..=(property_id, u64::MAX))
+            .take(limit as usize)
+            .map(|(_, change)| change)
+            .collect()
+    })
+}
+
+/// Registers the canister serving the ICRC-1 token of `property_id`, which
+/// alone may move the property's holdings through `transfer_holding`.
+#[update]
+fn set_property_token_canister(property_id: u64, token_canister: Principal) -> Result<()> {
+    require_role(Role::Controller)?;
+    PROPERTY_TOKENS.with(|tokens| {
+        tokens.borrow_mut().insert(property_id, token_canister);
+    });
+    Ok(())
+}
+
+#[query]
+fn get_property_token_canister(property_id: u64) -> Option<Principal> {
+    PROPERTY_TOKENS.with(|tokens| tokens.borrow().get(&property_id))
+}
+
+/// Token balance of every holder of a property, ordered by principal.
+#[query]
+fn get_property_holders(property_id: u64, page: PageRequest<Principal>) -> Page<TokenBalance, Principal> {
+    let entries = holder_balances(property_id)
+        .into_iter()
+        .map(|(holder, tokens)| (holder, TokenBalance { holder, tokens }))
+        .collect();
+    paging::paginate_sorted(entries, &page)
+}
+
+// Export candid interface
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::Storable;
+
+    /// Records as written by the canister before they were versioned.
+    #[derive(CandidType)]
+    struct LegacyInvestment {
+        id: u64,
+        user_id: Principal,
+        property_id: u64,
+        tokens_owned: u64,
+        investment_amount: u64,
+        current_value: u64,
+        purchase_date: u64,
+        is_active: bool,
+    }
+
+    #[derive(CandidType)]
+    struct LegacyTransaction {
+        id: u64,
+        user_id: Principal,
+        property_id: u64,
+        transaction_type: String,
+        amount: u64,
+        tokens: u64,
+        timestamp: u64,
+    }
+
+    #[test]
+    fn loads_unversioned_investment() {
+        let bytes = candid::encode_one(&LegacyInvestment {
+            id: 4,
+            user_id: Principal::anonymous(),
+            property_id: 2,
+            tokens_owned: 10,
+            investment_amount: 10_000,
+            current_value: 11_000,
+            purchase_date: 99,
+            is_active: true,
+        })
+        .unwrap();
+
+        let investment = Investment::from_bytes(bytes.into());
+        assert_eq!(investment.id, 4);
+        assert_eq!(investment.tokens_owned, 10);
+        assert_eq!(investment.current_value, 11_000);
+        assert_eq!(investment.to_bytes()[0], Investment::VERSION);
+    }
+
+    #[test]
+    fn loads_unversioned_transaction() {
+        let bytes = candid::encode_one(&LegacyTransaction {
+            id: 8,
+            user_id: Principal::anonymous(),
+            property_id: 2,
+            transaction_type: "purchase".to_string(),
+            amount: 10_000,
+            tokens: 10,
+            timestamp: 99,
+        })
+        .unwrap();
+
+        let transaction = Transaction::from_bytes(bytes.into());
+        assert_eq!(transaction.id, 8);
+        assert_eq!(transaction.transaction_type, "purchase");
+        assert_eq!(transaction.ledger_block, None);
+        assert_eq!(transaction.to_bytes()[0], Transaction::VERSION);
+    }
+
+    #[test]
+    fn converts_cents_into_ledger_units() {
+        let ledger = PaymentLedger {
+            canister: Principal::anonymous(),
+            units_per_cent: 10_000,
+        };
+        assert_eq!(ledger.units_for(150).unwrap(), 1_500_000);
+        assert!(ledger.units_for(u64::MAX).is_err());
+        assert!(PaymentLedger { units_per_cent: 0, ..ledger }.validate().is_err());
+    }
+
+    fn investment(tokens_owned: u64, investment_amount: u64) -> Investment {
+        Investment {
+            id: 1,
+            user_id: Principal::anonymous(),
+            property_id: 1,
+            tokens_owned,
+            investment_amount,
+            current_value: investment_amount * 2,
+            purchase_date: 0,
+            is_active: true,
+        }
+    }
+
+    #[test]
+    fn splits_cost_basis_in_proportion() {
+        let mut lot = investment(3, 1_000);
+        assert_eq!(split_off(&mut lot, 1), (333, 666));
+        assert_eq!(lot.tokens_owned, 2);
+        assert_eq!(lot.investment_amount, 667);
+        assert!(lot.is_active);
+
+        // Taking the rest moves the remainder so no cost basis is lost
+        assert_eq!(split_off(&mut lot, 2), (667, 1_334));
+        assert_eq!(lot.investment_amount, 0);
+        assert!(!lot.is_active);
+    }
+    #[test]
+    fn logs_the_holder_balance_on_every_change() {
+        let holder = Principal::anonymous();
+        let mut first = investment(3, 1_000);
+        save_investment(&first);
+        save_investment(&Investment { id: 2, ..investment(2, 500) });
+        first.tokens_owned = 0;
+        first.is_active = false;
+        save_investment(&first);
+
+        let balances: Vec<(u64, u64)> = get_holding_changes_since(1, 0, None)
+            .into_iter()
+            .map(|change| (change.seq, change.balance))
+            .collect();
+        assert_eq!(balances, [(1, 3), (2, 5), (3, 2)]);
+        assert_eq!(get_holding_changes_since(1, 2, None)[0].holder, holder);
+        assert!(get_holding_changes_since(2, 0, None).is_empty());
+    }
+
+    #[test]
+    fn rejects_windows_overlapping_an_unsettled_one() {
+        let window = RedemptionWindow {
+            id: 1,
+            property_id: 1,
+            opens_at: 10,
+            closes_at: 20,
+            max_tokens: None,
+            nav_per_token: None,
+            redeemed_tokens: 0,
+            unreturned_tokens: 0,
+        };
+        save_redemption_window(&window);
+        let mut request = RedemptionRequest {
+            id: 1,
+            window_id: 1,
+            property_id: 1,
+            investor: Principal::anonymous(),
+            tokens: 5,
+            tokens_redeemed: 0,
+            payout: 0,
+            ledger_block: None,
+            status: RedemptionStatus::Pending,
+            requested_at: 12,
+            settled_at: None,
+            unconfirmed_payout: None,
+        };
+        save_redemption_request(&request);
+        let overlapping = OpenRedemptionWindowRequest {
+            property_id: 1,
+            opens_at: 15,
+            closes_at: 30,
+            max_tokens: None,
+        };
+
+        let other_property = OpenRedemptionWindowRequest { property_id: 2, ..overlapping.clone() };
+        let after_close = OpenRedemptionWindowRequest { opens_at: 20, ..overlapping.clone() };
+        assert!(ensure_no_overlapping_window(&overlapping, 25).is_err());
+        assert!(ensure_no_overlapping_window(&other_property, 25).is_ok());
+        assert!(ensure_no_overlapping_window(&after_close, 25).is_ok());
+
+        request.status = RedemptionStatus::Redeemed;
+        save_redemption_request(&request);
+        assert!(ensure_no_overlapping_window(&overlapping, 25).is_ok());
+    }
+
+    #[test]
+    fn redeems_held_tokens_after_the_lockup_is_raised() {
+        let investor = Principal::anonymous();
+        save_investment(&investment(3, 900));
+        save_investment(&Investment { id: 2, ..investment(4, 400) });
+        // Raised after the request took the tokens
+        LOCKUPS.with(|lockups| lockups.borrow_mut().insert(1, MAX_LOCKUP_SECONDS));
+
+        let taken = take_redeemed_tokens(investor, 1, 5).unwrap();
+        assert_eq!(
+            taken,
+            [
+                TakenLot { investment_id: 1, tokens: 3, amount: 900, value: 1_800 },
+                TakenLot { investment_id: 2, tokens: 2, amount: 200, value: 400 },
+            ]
+        );
+        assert_eq!(get_user_tokens_for_property(investor, 1), 2);
+        assert!(take_redeemed_tokens(investor, 1, 3).is_err());
+
+        // A failed payout puts the tokens back where they came from
+        restore_lots(&taken);
+        let lots: Vec<(u64, u64, bool)> = user_property_investments(investor, 1)
+            .iter()
+            .map(|lot| (lot.tokens_owned, lot.investment_amount, lot.is_active))
+            .collect();
+        assert_eq!(lots, [(3, 900, true), (4, 400, true)]);
+    }
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    /// Tokens, cost basis, value and purchase date of the user's active lots.
+    fn lots(user: Principal) -> Vec<(u64, u64, u64, u64)> {
+        user_property_investments(user, 1)
+            .iter()
+            .filter(|lot| lot.is_active)
+            .map(|lot| (lot.tokens_owned, lot.investment_amount, lot.current_value, lot.purchase_date))
+            .collect()
+    }
+
+    /// Saves investments 1 to `held.len()`, owned by `user` and bought at the
+    /// given times with the given tokens, at 300 cents per token.
+    fn hold(user: Principal, held: &[(u64, u64)]) {
+        for (id, (tokens, purchase_date)) in (1..).zip(held) {
+            save_investment(&Investment {
+                id,
+                user_id: user,
+                purchase_date: *purchase_date,
+                ..investment(*tokens, tokens * 300)
+            });
+        }
+        ID_COUNTER.with(|counter| counter.borrow_mut().insert(0, held.len() as u64));
+    }
+
+    #[test]
+    fn transfers_merge_into_the_receivers_unlocked_lot() {
+        let (sender, receiver) = (principal(1), principal(2));
+        hold(sender, &[(3, 0)]);
+        save_investment(&Investment { id: 2, user_id: receiver, ..investment(2, 500) });
+        ID_COUNTER.with(|counter| counter.borrow_mut().insert(0, 2));
+
+        let transfer_out = record_transfer(1, sender, receiver, 1, 100).unwrap();
+        // The cost basis moves in proportion to the tokens
+        assert_eq!(lots(sender), [(2, 600, 1_200, 0)]);
+        assert_eq!(lots(receiver), [(3, 800, 1_600, 0)]);
+
+        let records: Vec<(Principal, String, u64, u64, u64)> = TRANSACTION_STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .range(transfer_out..)
+                .map(|(_, record)| (record.user_id, record.transaction_type, record.amount, record.tokens, record.timestamp))
+                .collect()
+        });
+        assert_eq!(
+            records,
+            [
+                (sender, "transfer_out".to_string(), 300, 1, 100),
+                (receiver, "transfer_in".to_string(), 300, 1, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn transfers_carry_the_lockup_served_over() {
+        let (sender, receiver) = (principal(1), principal(2));
+        let lockup = 10_000_000_000;
+        LOCKUPS.with(|lockups| lockups.borrow_mut().insert(1, 10));
+        hold(sender, &[(2, 0), (3, lockup)]);
+        let now = lockup + 1;
+
+        // The second lot is still locked up
+        assert!(matches!(
+            move_tokens(1, sender, receiver, 3, CostBasis::Carried, now),
+            Err(Error::InvalidState { .. })
+        ));
+
+        // Tokens from the first lot arrive as a new lot dated like it
+        assert_eq!(move_tokens(1, sender, receiver, 1, CostBasis::Carried, now), Ok(300));
+        assert_eq!(lots(receiver), [(1, 300, 600, 0)]);
+        assert_eq!(lots(sender), [(1, 300, 600, 0), (3, 900, 1_800, lockup)]);
+
+        // Bought tokens start a new lockup, so they are not merged either
+        assert_eq!(move_tokens(1, sender, receiver, 1, CostBasis::Purchased(450), now), Ok(300));
+        assert_eq!(lots(receiver), [(1, 300, 600, 0), (1, 450, 450, now)]);
+    }
+
+    #[test]
+    fn tokens_on_offer_cannot_be_transferred() {
+        let (sender, receiver) = (principal(1), principal(2));
+        hold(sender, &[(5, 0)]);
+        save_order(&Order {
+            id: 1,
+            property_id: 1,
+            owner: sender,
+            side: OrderSide::Sell,
+            price: 400,
+            tokens: 3,
+            remaining: 2,
+            created_at: 0,
+            status: OrderStatus::Open,
+        });
+        save_redemption_request(&RedemptionRequest {
+            id: 1,
+            window_id: 1,
+            property_id: 1,
+            investor: sender,
+            tokens: 2,
+            tokens_redeemed: 0,
+            payout: 0,
+            ledger_block: None,
+            status: RedemptionStatus::Pending,
+            requested_at: 0,
+            settled_at: None,
+            unconfirmed_payout: None,
+        });
+
+        assert!(matches!(
+            move_tokens(1, sender, receiver, 6, CostBasis::Carried, 1),
+            Err(Error::InsufficientTokens { requested: 6, available: 5 })
+        ));
+        assert!(matches!(
+            move_tokens(1, sender, receiver, 2, CostBasis::Carried, 1),
+            Err(Error::InvalidState { .. })
+        ));
+        assert_eq!(move_tokens(1, sender, receiver, 1, CostBasis::Carried, 1), Ok(300));
+        assert_eq!(get_user_tokens_for_property(sender, 1), 4);
+    }
+
+    fn order(id: u64, owner: Principal, side: OrderSide, price: u64, tokens: u64) -> Order {
+        let order = Order {
+            id,
+            property_id: 1,
+            owner,
+            side,
+            price,
+            tokens,
+            remaining: tokens,
+            created_at: 0,
+            status: OrderStatus::Open,
+        };
+        save_order(&order);
+        order
+    }
+
+    fn stored_order(id: u64) -> Order {
+        ORDERS.with(|orders| orders.borrow().get(&id)).unwrap()
+    }
+
+    const LEDGER: PaymentLedger = PaymentLedger {
+        canister: Principal::anonymous(),
+        units_per_cent: 10,
+    };
+
+    #[test]
+    fn settles_a_partial_fill_at_the_resting_price() {
+        let (seller, buyer) = (principal(1), principal(2));
+        hold(seller, &[(5, 0)]);
+        let mut sell = order(1, seller, OrderSide::Sell, 400, 5);
+        let mut buy = order(2, buyer, OrderSide::Buy, 500, 3);
+
+        let trade_id = settle(&mut buy, &mut sell, 400, 3, &LEDGER, 50).unwrap();
+        assert_eq!((stored_order(1).remaining, stored_order(1).status), (2, OrderStatus::Open));
+        assert_eq!((stored_order(2).remaining, stored_order(2).status), (0, OrderStatus::Filled));
+        let trade = TRADES.with(|trades| trades.borrow().get(&trade_id)).unwrap();
+        assert_eq!((trade.price, trade.tokens), (400, 3));
+
+        // The seller is paid the trade price and the buyer gets back the rest
+        // of what they escrowed
+        assert_eq!(get_market_balance(seller), 12_000);
+        assert_eq!(get_market_balance(buyer), 3_000);
+
+        // The buyer's cost basis is the trade price, locked up from now
+        assert_eq!(lots(seller), [(2, 600, 1_200, 0)]);
+        assert_eq!(lots(buyer), [(3, 1_200, 1_200, 50)]);
+    }
+
+    #[test]
+    fn failed_settlement_leaves_the_sell_order_as_it_was() {
+        let (seller, buyer) = (principal(1), principal(2));
+        hold(seller, &[(2, 0)]);
+        let mut sell = order(1, seller, OrderSide::Sell, 400, 5);
+        let mut buy = order(2, buyer, OrderSide::Buy, 400, 3);
+
+        assert!(matches!(
+            settle(&mut buy, &mut sell, 400, 3, &LEDGER, 50),
+            Err(SettleError::Seller(Error::InsufficientTokens { .. }))
+        ));
+        for sell in [&sell, &stored_order(1)] {
+            assert_eq!((sell.remaining, sell.status), (5, OrderStatus::Open));
+        }
+        assert_eq!(stored_order(2).remaining, 3);
+        assert_eq!(get_market_balance(seller), 0);
+        assert_eq!(lots(seller), [(2, 600, 1_200, 0)]);
+        assert!(lots(buyer).is_empty());
+    }
+
+    #[test]
+    fn cancels_only_sell_orders_whose_tokens_cannot_move() {
+        let (seller, buyer) = (principal(1), principal(2));
+        hold(seller, &[(2, 0)]);
+        order(1, seller, OrderSide::Sell, 400, 5);
+        let buy = order(2, buyer, OrderSide::Buy, 400, 3);
+
+        // The ledger amount does not fit, so nothing trades
+        let overflowing = PaymentLedger { units_per_cent: u64::MAX, ..LEDGER };
+        let buy = match_order(buy, &overflowing, 50);
+        assert!(buy.is_open() && stored_order(1).is_open());
+
+        let buy = match_order(buy, &LEDGER, 60);
+        assert!(buy.is_open());
+        assert_eq!(stored_order(1).status, OrderStatus::Cancelled);
+
+        let failures: Vec<(u64, bool, u64)> = get_settlement_failures(1, PageRequest::default())
+            .items
+            .iter()
+            .map(|failure| (failure.sell_order_id, failure.sell_cancelled, failure.failed_at))
+            .collect();
+        assert_eq!(failures, [(1, false, 50), (1, true, 60)]);
+    }
+
+    #[test]
+    fn lockup_nanos_saturate() {
+        LOCKUPS.with(|lockups| {
+            let mut lockups = lockups.borrow_mut();
+            lockups.insert(1, MAX_LOCKUP_SECONDS);
+            lockups.insert(2, u64::MAX);
+        });
+        assert_eq!(lockup_nanos(1), MAX_LOCKUP_SECONDS * 1_000_000_000);
+        assert_eq!(lockup_nanos(2), u64::MAX);
+        assert_eq!(lockup_nanos(3), 0);
+    }
+}
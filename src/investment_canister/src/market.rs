@@ -0,0 +1,290 @@
+//! Secondary market where verified investors trade a property's tokens with
+//! limit orders. Open orders rest in a book per property, ordered by price
+//! and then by age, and a trade executes at the price of the resting order.
+
+use candid::{CandidType, Deserialize, Principal};
+use realty_common::icrc::LedgerTransfer;
+use realty_common::schema::Versioned;
+use realty_common::{versioned_storable, Error, Result};
+use serde::Serialize;
+
+/// Trades a single order may execute before the rest of it is left on the
+/// book, so matching stays within one message's instruction limit.
+pub const MAX_FILLS_PER_ORDER: usize = 100;
+pub const DEFAULT_BOOK_DEPTH: u32 = 20;
+pub const MAX_BOOK_DEPTH: u32 = 100;
+
+/// Key of an open order in the book: (property_id, side, (price rank,
+/// order_id)). Ascending keys visit each side best price first and, within
+/// a price, oldest order first.
+pub type BookKey = (u64, u8, (u64, u64));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum OrderStatus {
+    Open,
+    Filled,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Order {
+    pub id: u64,
+    pub property_id: u64,
+    pub owner: Principal,
+    pub side: OrderSide,
+    pub price: u64, // in USD cents per token
+    pub tokens: u64,
+    pub remaining: u64, // Tokens not yet traded
+    pub created_at: u64,
+    pub status: OrderStatus,
+}
+
+impl Versioned for Order {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(Order);
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Trade {
+    pub id: u64,
+    pub property_id: u64,
+    pub buy_order_id: u64,
+    pub sell_order_id: u64,
+    pub buyer: Principal,
+    pub seller: Principal,
+    pub price: u64, // in USD cents per token
+    pub tokens: u64,
+    pub executed_at: u64,
+}
+
+impl Versioned for Trade {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(Trade);
+
+/// A trade between two crossing orders that could not be settled. When the
+/// seller's tokens could no longer move, the sell order was cancelled;
+/// otherwise both orders were left as they were and matching stopped.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct SettlementFailure {
+    pub id: u64,
+    pub property_id: u64,
+    pub buy_order_id: u64,
+    pub sell_order_id: u64,
+    pub error: Error,
+    pub sell_cancelled: bool,
+    pub failed_at: u64,
+}
+
+impl Versioned for SettlementFailure {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(SettlementFailure);
+
+/// Payout of a market balance whose ledger transfer may have executed. It is
+/// resent unchanged by the owner's next withdrawal.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Withdrawal {
+    pub id: u64,
+    pub balance: u64, // Market balance taken, credited back if the ledger rejects the payout
+    pub transfer: LedgerTransfer,
+}
+
+impl Versioned for Withdrawal {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(Withdrawal);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PlaceOrderRequest {
+    pub property_id: u64,
+    pub side: OrderSide,
+    pub price: u64, // in USD cents per token
+    pub tokens: u64,
+}
+
+/// Open orders at one price.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct PriceLevel {
+    pub price: u64,
+    pub tokens: u64,
+    pub orders: u64,
+}
+
+/// Open orders of a property, best price first on each side.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct OrderBook {
+    pub property_id: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+impl OrderSide {
+    pub fn code(self) -> u8 {
+        match self {
+            OrderSide::Buy => 0,
+            OrderSide::Sell => 1,
+        }
+    }
+
+    pub fn opposite(self) -> OrderSide {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+
+    /// Rank of `price` within this side of the book; the best price ranks
+    /// lowest, which is the highest bid and the lowest ask.
+    pub fn price_rank(self, price: u64) -> u64 {
+        match self {
+            OrderSide::Buy => u64::MAX - price,
+            OrderSide::Sell => price,
+        }
+    }
+}
+
+impl PlaceOrderRequest {
+    /// Order value in USD cents.
+    pub fn validate(&self) -> Result<u64> {
+        if self.price == 0 {
+            return Err(Error::invalid_input("price", "must be greater than zero"));
+        }
+        if self.tokens == 0 {
+            return Err(Error::invalid_input("tokens", "must trade at least one token"));
+        }
+        self.price
+            .checked_mul(self.tokens)
+            .ok_or_else(|| Error::invalid_input("price", "order value is too large"))
+    }
+}
+
+impl Order {
+    pub fn is_open(&self) -> bool {
+        self.status == OrderStatus::Open
+    }
+
+    pub fn book_key(&self) -> BookKey {
+        (self.property_id, self.side.code(), (self.side.price_rank(self.price), self.id))
+    }
+
+    /// Whether this order trades with a resting order at `price`.
+    pub fn crosses(&self, price: u64) -> bool {
+        match self.side {
+            OrderSide::Buy => price <= self.price,
+            OrderSide::Sell => price >= self.price,
+        }
+    }
+
+    /// Takes `tokens` off the order, marking it filled once nothing remains.
+    pub fn fill(&mut self, tokens: u64) {
+        self.remaining -= tokens;
+        if self.remaining == 0 {
+            self.status = OrderStatus::Filled;
+        }
+    }
+}
+
+/// Sums open orders, given best price first, into at most `depth` levels.
+pub fn price_levels<'a>(orders: impl Iterator<Item = &'a Order>, depth: usize) -> Vec<PriceLevel> {
+    let mut levels: Vec<PriceLevel> = Vec::new();
+    for order in orders {
+        if let Some(level) = levels.last_mut().filter(|level| level.price == order.price) {
+            level.tokens += order.remaining;
+            level.orders += 1;
+            continue;
+        }
+        if levels.len() == depth {
+            break;
+        }
+        levels.push(PriceLevel {
+            price: order.price,
+            tokens: order.remaining,
+            orders: 1,
+        });
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: u64, side: OrderSide, price: u64, remaining: u64) -> Order {
+        Order {
+            id,
+            property_id: 1,
+            owner: Principal::anonymous(),
+            side,
+            price,
+            tokens: remaining,
+            remaining,
+            created_at: 0,
+            status: OrderStatus::Open,
+        }
+    }
+
+    #[test]
+    fn book_keys_give_price_then_time_priority() {
+        let mut bids = [
+            order(1, OrderSide::Buy, 100, 1),
+            order(2, OrderSide::Buy, 120, 1),
+            order(3, OrderSide::Buy, 120, 1),
+        ];
+        bids.sort_by_key(Order::book_key);
+        assert_eq!(bids.iter().map(|o| o.id).collect::<Vec<_>>(), [2, 3, 1]);
+
+        let mut asks = [
+            order(4, OrderSide::Sell, 130, 1),
+            order(5, OrderSide::Sell, 110, 1),
+            order(6, OrderSide::Sell, 110, 1),
+        ];
+        asks.sort_by_key(Order::book_key);
+        assert_eq!(asks.iter().map(|o| o.id).collect::<Vec<_>>(), [5, 6, 4]);
+    }
+
+    #[test]
+    fn crosses_at_or_through_the_limit() {
+        let buy = order(1, OrderSide::Buy, 100, 5);
+        assert!(buy.crosses(90));
+        assert!(buy.crosses(100));
+        assert!(!buy.crosses(101));
+
+        let mut sell = order(2, OrderSide::Sell, 100, 5);
+        assert!(sell.crosses(100));
+        assert!(!sell.crosses(99));
+
+        sell.fill(2);
+        assert!(sell.is_open());
+        sell.fill(3);
+        assert_eq!(sell.status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn aggregates_price_levels() {
+        let asks = [
+            order(1, OrderSide::Sell, 110, 5),
+            order(2, OrderSide::Sell, 110, 3),
+            order(3, OrderSide::Sell, 120, 4),
+            order(4, OrderSide::Sell, 130, 1),
+        ];
+        let levels = price_levels(asks.iter(), 2);
+        assert_eq!(
+            levels,
+            [
+                PriceLevel { price: 110, tokens: 8, orders: 2 },
+                PriceLevel { price: 120, tokens: 4, orders: 1 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Redemption windows in which investors sell tokens back to a property.
+//! Requests queue while a window is open and are filled in request order
+//! at the property's net asset value per token once it closes.
+
+use candid::{CandidType, Deserialize, Principal};
+use realty_common::icrc::LedgerTransfer;
+use realty_common::schema::Versioned;
+use realty_common::{versioned_storable, Error, Result};
+use serde::Serialize;
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RedemptionWindow {
+    pub id: u64,
+    pub property_id: u64,
+    pub opens_at: u64,
+    pub closes_at: u64, // Requests are accepted until then
+    pub max_tokens: Option<u64>, // Tokens the property buys back at most
+    pub nav_per_token: Option<u64>, // in USD cents, fixed when settlement starts
+    pub redeemed_tokens: u64,
+    pub unreturned_tokens: u64, // Redeemed but not yet back in the property's available tokens
+}
+
+impl Versioned for RedemptionWindow {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(RedemptionWindow);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum RedemptionStatus {
+    Pending,
+    /// The payout is on its way to the ledger.
+    Paying,
+    Redeemed,
+    /// The window reached its limit before the request's turn, or the
+    /// investor no longer held the tokens.
+    Unfilled,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RedemptionRequest {
+    pub id: u64,
+    pub window_id: u64,
+    pub property_id: u64,
+    pub investor: Principal,
+    pub tokens: u64,
+    pub tokens_redeemed: u64, // Fewer than requested when the window limit is reached
+    pub payout: u64, // in USD cents
+    pub ledger_block: Option<u64>,
+    pub status: RedemptionStatus,
+    pub requested_at: u64,
+    pub settled_at: Option<u64>,
+    pub unconfirmed_payout: Option<LedgerTransfer>, // Payout that may have reached the ledger, resent unchanged
+}
+
+impl Versioned for RedemptionRequest {
+    const VERSION: u8 = 2;
+}
+
+versioned_storable!(RedemptionRequest);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct OpenRedemptionWindowRequest {
+    pub property_id: u64,
+    pub opens_at: u64,
+    pub closes_at: u64,
+    pub max_tokens: Option<u64>,
+}
+
+impl OpenRedemptionWindowRequest {
+    pub fn validate(&self, now: u64) -> Result<()> {
+        if self.opens_at >= self.closes_at {
+            return Err(Error::invalid_input("closes_at", "must be after opens_at"));
+        }
+        if self.closes_at <= now {
+            return Err(Error::invalid_input("closes_at", "must be in the future"));
+        }
+        if self.max_tokens == Some(0) {
+            return Err(Error::invalid_input("max_tokens", "must be greater than zero"));
+        }
+        Ok(())
+    }
+}
+
+impl RedemptionRequest {
+    /// Whether the request still holds the investor's tokens.
+    pub fn is_outstanding(&self) -> bool {
+        matches!(self.status, RedemptionStatus::Pending | RedemptionStatus::Paying)
+    }
+}
+
+impl RedemptionWindow {
+    pub fn is_open(&self, now: u64) -> bool {
+        (self.opens_at..self.closes_at).contains(&now)
+    }
+
+    /// Whether the window has closed with no request still holding tokens
+    /// and every redeemed token back with the property.
+    pub fn is_settled(&self, now: u64, has_outstanding_requests: bool) -> bool {
+        now >= self.closes_at && !has_outstanding_requests && self.unreturned_tokens == 0
+    }
+
+    /// Whether the window shares any time with `[opens_at, closes_at)`.
+    pub fn overlaps(&self, opens_at: u64, closes_at: u64) -> bool {
+        self.opens_at < closes_at && opens_at < self.closes_at
+    }
+
+    /// Tokens of a request for `tokens` that still fit within the limit.
+    pub fn fillable(&self, tokens: u64) -> u64 {
+        match self.max_tokens {
+            Some(max) => tokens.min(max.saturating_sub(self.redeemed_tokens)),
+            None => tokens,
+        }
+    }
+}
+
+/// Net asset value of one token in USD cents, rounded down.
+pub fn nav_per_token(total_value: u64, total_tokens: u64) -> Result<u64> {
+    match total_value.checked_div(total_tokens) {
+        Some(nav) if nav > 0 => Ok(nav),
+        _ => Err(Error::invalid_state("property has no net asset value per token")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(max_tokens: Option<u64>) -> RedemptionWindow {
+        RedemptionWindow {
+            id: 1,
+            property_id: 1,
+            opens_at: 10,
+            closes_at: 20,
+            max_tokens,
+            nav_per_token: None,
+            redeemed_tokens: 0,
+            unreturned_tokens: 0,
+        }
+    }
+
+    #[test]
+    fn fills_requests_up_to_the_window_limit() {
+        let mut limited = window(Some(10));
+        assert_eq!(limited.fillable(6), 6);
+        limited.redeemed_tokens = 6;
+        assert_eq!(limited.fillable(6), 4);
+        limited.redeemed_tokens = 10;
+        assert_eq!(limited.fillable(6), 0);
+        assert_eq!(window(None).fillable(6), 6);
+
+        assert!(!limited.is_open(9));
+        assert!(limited.is_open(10));
+        assert!(!limited.is_open(20));
+    }
+
+    #[test]
+    fn settles_once_closed_and_returned() {
+        let mut window = window(None);
+        assert!(!window.is_settled(15, false));
+        assert!(!window.is_settled(20, true));
+        assert!(window.is_settled(20, false));
+        window.unreturned_tokens = 5;
+        assert!(!window.is_settled(20, false));
+
+        assert!(window.overlaps(15, 25));
+        assert!(window.overlaps(0, 11));
+        assert!(!window.overlaps(20, 30));
+        assert!(!window.overlaps(0, 10));
+    }
+
+    #[test]
+    fn computes_nav_per_token() {
+        assert_eq!(nav_per_token(1_000_000, 1_000).unwrap(), 1_000);
+        assert_eq!(nav_per_token(1_000, 3).unwrap(), 333);
+        assert!(nav_per_token(1_000, 0).is_err());
+        assert!(nav_per_token(0, 1_000).is_err());
+    }
+}
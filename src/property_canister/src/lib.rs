@@ -1,15 +1,30 @@
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk::api::time;
-use ic_cdk_macros::{init, query, update};
+use ic_cdk_macros::{init, post_upgrade, query, update};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use realty_common::access::{self, Role, RoleAssignment, RoleStore};
+use realty_common::icrc::PropertyToken;
+use realty_common::paging::{self, Page, PageRequest};
+use realty_common::schema::{self, Migration, Versioned};
+use realty_common::{versioned_storable, Error, Result};
 use serde::Serialize;
 use std::cell::RefCell;
-use std::borrow::Cow;
+use token::TokenReconciliation;
+
+mod token;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
+type SchemaVersionCell = StableCell<u32, Memory>;
 type IdStore = StableBTreeMap<u8, u64, Memory>;
 type PropertyStore = StableBTreeMap<u64, Property, Memory>;
+type CanisterRefStore = StableBTreeMap<u8, Principal, Memory>;
+
+const INVESTMENT_CANISTER_KEY: u8 = 0;
+const GOVERNANCE_CANISTER_KEY: u8 = 1;
+
+/// Bump when a stored record changes shape and add the matching migration.
+const SCHEMA_VERSION: u32 = 1;
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct Property {
@@ -27,20 +42,15 @@ pub struct Property {
     pub is_active: bool,
     pub created_at: u64,
     pub owner: Principal,
+    pub sale_price: Option<u64>, // in USD cents, set once the property is sold
 }
 
-impl Storable for Property {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
-    }
-
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
-    }
-
-    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+impl Versioned for Property {
+    const VERSION: u8 = 1;
 }
 
+versioned_storable!(Property);
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct CreatePropertyRequest {
     pub title: String,
@@ -54,12 +64,35 @@ pub struct CreatePropertyRequest {
     pub image_url: String,
 }
 
+/// Listing fields that may be changed after creation; `None` keeps the
+/// current value.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PropertyMetadataUpdate {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub expected_roi: Option<String>,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct UpdateTokensRequest {
     pub property_id: u64,
     pub tokens_purchased: u64,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ReserveTokensRequest {
+    pub property_id: u64,
+    pub tokens: u64,
+    pub investment_amount: u64, // in USD cents
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub investment_canister: Principal,
+    pub roles: Vec<RoleAssignment>,
+}
+
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
         MemoryManager::init(DefaultMemoryImpl::default())
@@ -76,18 +109,132 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
         )
     );
+
+    static CANISTER_REFS: RefCell<CanisterRefStore> = RefCell::new(
+        CanisterRefStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
+        )
+    );
+
+    static ROLES: RefCell<RoleStore<Memory>> = RefCell::new(
+        RoleStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+        )
+    );
+
+    static STORED_SCHEMA_VERSION: RefCell<SchemaVersionCell> = RefCell::new(
+        SchemaVersionCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
+            0,
+        ).expect("failed to initialize schema version")
+    );
 }
 
 #[init]
-fn init() {
+fn init(args: InitArgs) {
+    set_schema_version(SCHEMA_VERSION);
+
     // Initialize the ID counter
     ID_COUNTER.with(|counter| {
         counter.borrow_mut().insert(0, 0);
     });
+
+    CANISTER_REFS.with(|refs| {
+        refs.borrow_mut().insert(INVESTMENT_CANISTER_KEY, args.investment_canister);
+    });
+
+    ROLES.with(|roles| access::assign_roles(&mut roles.borrow_mut(), args.roles));
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "re-encode properties in the versioned record format",
+    run: reencode_records,
+}];
+
+#[post_upgrade]
+fn post_upgrade() {
+    let stored = STORED_SCHEMA_VERSION.with(|v| *v.borrow().get());
+    let migrated = schema::run_migrations(stored, MIGRATIONS);
+    assert_eq!(migrated, SCHEMA_VERSION, "missing migration to schema v{}", SCHEMA_VERSION);
+    set_schema_version(migrated);
+}
+
+fn set_schema_version(version: u32) {
+    STORED_SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(version)
+            .expect("failed to store schema version");
+    });
+}
+
+/// Rewrites every record so that blobs stored before versioning existed are
+/// persisted in the current format.
+fn reencode_records() {
+    PROPERTY_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let entries: Vec<_> = storage.iter().collect();
+        for (key, record) in entries {
+            storage.insert(key, record);
+        }
+    });
 }
 
+#[query]
+fn get_schema_version() -> u32 {
+    STORED_SCHEMA_VERSION.with(|v| *v.borrow().get())
+}
+
+fn require_role(role: Role) -> Result<()> {
+    ROLES.with(|roles| access::require_role(&roles.borrow(), role))
+}
+
+fn caller_has_role(role: Role) -> bool {
+    ROLES.with(|roles| access::has_role(&roles.borrow(), &ic_cdk::caller(), role))
+}
+
+/// Token supply may only be moved by the investment canister, which records
+/// the matching Investment on its side of the ledger.
+fn ensure_investment_canister() -> Result<()> {
+    let investment_canister = CANISTER_REFS.with(|refs| {
+        refs.borrow().get(&INVESTMENT_CANISTER_KEY)
+    });
+
+    match investment_canister {
+        Some(id) if id == ic_cdk::caller() => Ok(()),
+        _ => Err(Error::unauthorized("only the investment canister can update token supply")),
+    }
+}
+
+#[update]
+fn grant_role(assignment: RoleAssignment) -> Result<()> {
+    ROLES.with(|roles| access::grant_role(&mut roles.borrow_mut(), assignment))
+}
+
+#[update]
+fn revoke_role(assignment: RoleAssignment) -> Result<()> {
+    ROLES.with(|roles| access::revoke_role(&mut roles.borrow_mut(), assignment))
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    ROLES.with(|roles| access::get_roles(&roles.borrow(), &principal))
+}
+
+/// Sets the governance canister told about new properties so that it can
+/// give them their default governance configuration.
 #[update]
-fn create_property(req: CreatePropertyRequest) -> Result<Property, String> {
+fn set_governance_canister(governance_canister: Principal) -> Result<()> {
+    require_role(Role::Controller)?;
+    CANISTER_REFS.with(|refs| {
+        refs.borrow_mut().insert(GOVERNANCE_CANISTER_KEY, governance_canister);
+    });
+    Ok(())
+}
+
+#[update]
+fn create_property(req: CreatePropertyRequest) -> Result<Property> {
+    require_role(Role::PropertyManager)?;
     let caller = ic_cdk::caller();
     
     // Generate new ID
@@ -114,23 +261,28 @@ fn create_property(req: CreatePropertyRequest) -> Result<Property, String> {
         is_active: true,
         created_at: time(),
         owner: caller,
+        sale_price: None,
     };
 
     PROPERTY_STORAGE.with(|storage| {
         storage.borrow_mut().insert(id, property.clone());
     });
 
+    // Governance falls back to its defaults if the notification is lost
+    let governance_canister = CANISTER_REFS.with(|refs| refs.borrow().get(&GOVERNANCE_CANISTER_KEY));
+    if let Some(governance_canister) = governance_canister {
+        if let Err(err) = ic_cdk::notify(governance_canister, "init_governance_config", (id,)) {
+            ic_cdk::println!("failed to notify governance of property {}: {:?}", id, err);
+        }
+    }
+
     Ok(property)
 }
 
 #[query]
-fn get_properties() -> Vec<Property> {
+fn get_properties(page: PageRequest<u64>) -> Page<Property, u64> {
     PROPERTY_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .map(|(_, property)| property)
-            .collect()
+        paging::paginate(&storage.borrow(), &page)
     })
 }
 
@@ -142,26 +294,30 @@ fn get_property(id: u64) -> Option<Property> {
 }
 
 #[query]
-fn get_active_properties() -> Vec<Property> {
+fn get_active_properties(page: PageRequest<u64>) -> Page<Property, u64> {
     PROPERTY_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, property)| property.is_active)
-            .map(|(_, property)| property)
-            .collect()
+        paging::paginate_filtered(&storage.borrow(), &page, |property| property.is_active)
     })
 }
 
+/// Manual supply adjustment, available to the investment canister and to
+/// property managers.
 #[update]
-fn update_available_tokens(req: UpdateTokensRequest) -> Result<Property, String> {
+fn update_available_tokens(req: UpdateTokensRequest) -> Result<Property> {
+    if ensure_investment_canister().is_err() {
+        require_role(Role::PropertyManager)?;
+    }
+
     PROPERTY_STORAGE.with(|storage| {
         let mut storage = storage.borrow_mut();
         
         if let Some(mut property) = storage.get(&req.property_id) {
             // Check if enough tokens are available
             if property.available_tokens < req.tokens_purchased {
-                return Err("Not enough tokens available".to_string());
+                return Err(Error::InsufficientTokens {
+                    requested: req.tokens_purchased,
+                    available: property.available_tokens,
+                });
             }
             
             // Update available tokens
@@ -170,45 +326,304 @@ fn update_available_tokens(req: UpdateTokensRequest) -> Result<Property, String>
             
             Ok(property)
         } else {
-            Err("Property not found".to_string())
+            Err(Error::not_found("property"))
+        }
+    })
+}
+
+/// Validates a purchase against the property terms and takes the tokens out of
+/// `available_tokens` in the same message. Returns the tokens still available.
+#[update]
+fn reserve_tokens(req: ReserveTokensRequest) -> Result<u64> {
+    ensure_investment_canister()?;
+
+    PROPERTY_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+
+        let mut property = match storage.get(&req.property_id) {
+            Some(p) => p,
+            None => return Err(Error::not_found("property")),
+        };
+
+        if !property.is_active {
+            return Err(Error::invalid_state("property is not active"));
+        }
+
+        if req.tokens == 0 {
+            return Err(Error::invalid_input("tokens", "must purchase at least one token"));
+        }
+
+        if property.available_tokens < req.tokens {
+            return Err(Error::InsufficientTokens {
+                requested: req.tokens,
+                available: property.available_tokens,
+            });
+        }
+
+        if req.investment_amount < property.min_investment {
+            return Err(Error::invalid_input("investment_amount", "below the property minimum investment"));
+        }
+
+        // The amount paid must match tokens * (total_value / total_tokens) exactly
+        let expected = req.tokens as u128 * property.total_value as u128;
+        let paid = req.investment_amount as u128 * property.total_tokens as u128;
+        if expected != paid {
+            return Err(Error::invalid_input("investment_amount", "does not match the token price"));
+        }
+
+        property.available_tokens -= req.tokens;
+        let remaining = property.available_tokens;
+        storage.insert(req.property_id, property);
+
+        Ok(remaining)
+    })
+}
+
+/// Returns previously reserved tokens to the property, used when the investment
+/// canister fails to record a purchase after reserving.
+#[update]
+fn release_tokens(req: UpdateTokensRequest) -> Result<u64> {
+    ensure_investment_canister()?;
+
+    PROPERTY_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+
+        if let Some(mut property) = storage.get(&req.property_id) {
+            let restored = property.available_tokens.saturating_add(req.tokens_purchased);
+            if restored > property.total_tokens {
+                return Err(Error::invalid_input("tokens_purchased", "exceeds the property token supply"));
+            }
+
+            property.available_tokens = restored;
+            storage.insert(req.property_id, property);
+
+            Ok(restored)
+        } else {
+            Err(Error::not_found("property"))
         }
     })
 }
 
 #[update]
-fn toggle_property_status(property_id: u64) -> Result<Property, String> {
+fn toggle_property_status(property_id: u64) -> Result<Property> {
     let caller = ic_cdk::caller();
     
     PROPERTY_STORAGE.with(|storage| {
         let mut storage = storage.borrow_mut();
         
         if let Some(mut property) = storage.get(&property_id) {
-            // Only owner can toggle status
-            if property.owner != caller {
-                return Err("Only property owner can toggle status".to_string());
+            // Only the owner or a property manager can toggle status
+            if property.owner != caller && !caller_has_role(Role::PropertyManager) {
+                return Err(Error::unauthorized("only the property owner or a property manager can toggle status"));
             }
             
+            if !property.is_active && property.sale_price.is_some() {
+                return Err(Error::invalid_state("a sold property cannot be reactivated"));
+            }
+
             property.is_active = !property.is_active;
             storage.insert(property_id, property.clone());
             
             Ok(property)
         } else {
-            Err("Property not found".to_string())
+            Err(Error::not_found("property"))
         }
     })
 }
 
+/// Loads a property the caller may manage: its owner or a property manager.
+fn managed_property(property_id: u64) -> Result<Property> {
+    let property = get_property(property_id).ok_or_else(|| Error::not_found("property"))?;
+    if property.owner != ic_cdk::caller() && !caller_has_role(Role::PropertyManager) {
+        return Err(Error::unauthorized("only the property owner or a property manager can manage the property"));
+    }
+    Ok(property)
+}
+
+fn save_property(property: &Property) {
+    PROPERTY_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(property.id, property.clone());
+    });
+}
+
+#[update]
+fn update_property_metadata(property_id: u64, update: PropertyMetadataUpdate) -> Result<Property> {
+    let mut property = managed_property(property_id)?;
+
+    let fields = [
+        ("title", &update.title),
+        ("description", &update.description),
+        ("image_url", &update.image_url),
+        ("expected_roi", &update.expected_roi),
+    ];
+    for (field, value) in fields {
+        if value.as_deref().is_some_and(|value| value.trim().is_empty()) {
+            return Err(Error::invalid_input(field, "must not be empty"));
+        }
+    }
+
+    if let Some(title) = update.title {
+        property.title = title;
+    }
+    if let Some(description) = update.description {
+        property.description = description;
+    }
+    if let Some(image_url) = update.image_url {
+        property.image_url = image_url;
+    }
+    if let Some(expected_roi) = update.expected_roi {
+        property.expected_roi = expected_roi;
+    }
+
+    save_property(&property);
+    Ok(property)
+}
+
+/// Closes a property to new investment. Unlike `toggle_property_status` this
+/// is idempotent, so it is safe to retry.
+#[update]
+fn deactivate_property(property_id: u64) -> Result<Property> {
+    let mut property = managed_property(property_id)?;
+    property.is_active = false;
+    save_property(&property);
+    Ok(property)
+}
+
+/// Records the sale of a property at `price` and closes it to new investment.
+#[update]
+fn record_property_sale(property_id: u64, price: u64) -> Result<Property> {
+    require_role(Role::PropertyManager)?;
+    if price == 0 {
+        return Err(Error::invalid_input("price", "must be greater than zero"));
+    }
+
+    let mut property = get_property(property_id).ok_or_else(|| Error::not_found("property"))?;
+    if property.sale_price.is_some() {
+        return Err(Error::invalid_state("property has already been sold"));
+    }
+
+    property.sale_price = Some(price);
+    property.is_active = false;
+    save_property(&property);
+    Ok(property)
+}
+
 #[query]
-fn get_properties_by_owner(owner: Principal) -> Vec<Property> {
+fn get_properties_by_owner(owner: Principal, page: PageRequest<u64>) -> Page<Property, u64> {
     PROPERTY_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, property)| property.owner == owner)
-            .map(|(_, property)| property)
-            .collect()
+        paging::paginate_filtered(&storage.borrow(), &page, |property| property.owner == owner)
+    })
+}
+
+fn investment_canister_id() -> Result<Principal> {
+    CANISTER_REFS.with(|refs| {
+        refs.borrow()
+            .get(&INVESTMENT_CANISTER_KEY)
+            .ok_or_else(|| Error::invalid_state("investment canister is not configured; a controller must call set_investment_canister"))
+    })
+}
+
+/// Sets the investment canister allowed to move token supply, for canisters
+/// installed before it was part of `InitArgs` or moved to a new canister.
+#[update]
+fn set_investment_canister(investment_canister: Principal) -> Result<()> {
+    require_role(Role::Controller)?;
+    CANISTER_REFS.with(|refs| {
+        refs.borrow_mut().insert(INVESTMENT_CANISTER_KEY, investment_canister);
+    });
+    Ok(())
+}
+
+#[query]
+fn get_investment_canister() -> Option<Principal> {
+    CANISTER_REFS.with(|refs| refs.borrow().get(&INVESTMENT_CANISTER_KEY))
+}
+
+/// The ICRC-1 token of a property's shares, to be passed to the token
+/// canister deployed for the property.
+#[query]
+fn get_property_token(property_id: u64) -> Option<PropertyToken> {
+    get_property(property_id).map(|property| token::property_token(&property))
+}
+
+/// Checks that the shares for sale here and the holdings recorded by the
+/// investment canister add up to the property's supply.
+#[query(composite = true)]
+async fn reconcile_property_token(property_id: u64) -> Result<TokenReconciliation> {
+    let property = get_property(property_id).ok_or_else(|| Error::not_found("property"))?;
+    let (held_by_investors,): (u64,) = ic_cdk::call(
+        investment_canister_id()?,
+        "get_total_tokens_by_property",
+        (property_id,),
+    )
+    .await
+    .map_err(|err| Error::call_failed("get_total_tokens_by_property", err))?;
+
+    Ok(TokenReconciliation {
+        property_id,
+        total_supply: property.total_tokens,
+        available_tokens: property.available_tokens,
+        held_by_investors,
+        balanced: property.available_tokens as u128 + held_by_investors as u128
+            == property.total_tokens as u128,
     })
 }
 
 // Export candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::Storable;
+
+    /// Property as written by the canister before records were versioned.
+    #[derive(CandidType)]
+    struct LegacyProperty {
+        id: u64,
+        title: String,
+        description: String,
+        location: String,
+        property_type: String,
+        total_value: u64,
+        total_tokens: u64,
+        available_tokens: u64,
+        expected_roi: String,
+        min_investment: u64,
+        image_url: String,
+        is_active: bool,
+        created_at: u64,
+        owner: Principal,
+    }
+
+    #[test]
+    fn loads_unversioned_property() {
+        let legacy = LegacyProperty {
+            id: 1,
+            title: "Manhattan Luxury Residences".to_string(),
+            description: "Premium apartment complex".to_string(),
+            location: "New York, NY".to_string(),
+            property_type: "Residential".to_string(),
+            total_value: 4_200_000,
+            total_tokens: 4_200,
+            available_tokens: 4_000,
+            expected_roi: "14.20".to_string(),
+            min_investment: 1_000,
+            image_url: String::new(),
+            is_active: true,
+            created_at: 42,
+            owner: Principal::anonymous(),
+        };
+        let bytes = candid::encode_one(&legacy).unwrap();
+
+        let property = Property::from_bytes(bytes.into());
+        assert_eq!(property.id, 1);
+        assert_eq!(property.available_tokens, 4_000);
+        assert_eq!(property.owner, Principal::anonymous());
+        assert_eq!(property.sale_price, None);
+
+        let reencoded = property.to_bytes();
+        assert_eq!(reencoded[0], Property::VERSION);
+        assert_eq!(Property::from_bytes(reencoded).title, "Manhattan Luxury Residences");
+    }
+}
@@ -0,0 +1,37 @@
+//! Each property's shares presented as an ICRC-1 token. The token itself is
+//! served by a token canister deployed per property, which mirrors the
+//! holdings recorded by the investment canister. The shares still for sale
+//! are the property's `available_tokens` here; no account holds them, and
+//! the token canister reports them as the balance of its minting account.
+
+use crate::Property;
+use candid::{CandidType, Deserialize};
+use realty_common::icrc::PropertyToken;
+
+/// Shares are whole tokens.
+pub const DECIMALS: u8 = 0;
+pub const FEE: u64 = 0;
+
+/// Comparison of the shares this canister has sold with the holdings the
+/// investment canister records for the property.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TokenReconciliation {
+    pub property_id: u64,
+    pub total_supply: u64,
+    pub available_tokens: u64,
+    pub held_by_investors: u64,
+    /// Whether available and held shares add up to the supply. Purchases
+    /// still awaiting payment leave a temporary gap.
+    pub balanced: bool,
+}
+
+pub fn property_token(property: &Property) -> PropertyToken {
+    PropertyToken {
+        property_id: property.id,
+        name: format!("{} Shares", property.title),
+        symbol: format!("RLTY{}", property.id),
+        decimals: DECIMALS,
+        fee: FEE,
+        total_supply: property.total_tokens,
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{Error, Result};
+use candid::{CandidType, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Privileged roles recognised by the canisters.
+///
+/// Canister controllers implicitly hold every role, and `Admin` implies every
+/// role except `Controller`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum Role {
+    Controller,
+    Admin,
+    KycOfficer,
+    PropertyManager,
+    Treasury,
+    /// May veto queued governance proposals before they execute.
+    Guardian,
+}
+
+impl Role {
+    pub const ALL: [Role; 6] = [
+        Role::Controller,
+        Role::Admin,
+        Role::KycOfficer,
+        Role::PropertyManager,
+        Role::Treasury,
+        Role::Guardian,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub role: Role,
+}
+
+/// The roles held by a single principal, stored as a bitmask.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RoleSet(u8);
+
+impl RoleSet {
+    pub fn contains(&self, role: Role) -> bool {
+        self.0 & role.bit() != 0
+    }
+
+    pub fn insert(&mut self, role: Role) {
+        self.0 |= role.bit();
+    }
+
+    pub fn remove(&mut self, role: Role) {
+        self.0 &= !role.bit();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn roles(&self) -> Vec<Role> {
+        Role::ALL.into_iter().filter(|role| self.contains(*role)).collect()
+    }
+}
+
+impl Storable for RoleSet {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(vec![self.0])
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        RoleSet(bytes[0])
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1,
+        is_fixed_size: true,
+    };
+}
+
+pub type RoleStore<M> = StableBTreeMap<Principal, RoleSet, M>;
+
+pub fn has_role<M: Memory>(store: &RoleStore<M>, principal: &Principal, role: Role) -> bool {
+    if ic_cdk::api::is_controller(principal) {
+        return true;
+    }
+
+    let roles = store.get(principal).unwrap_or_default();
+    roles.contains(role) || (role != Role::Controller && roles.contains(Role::Admin))
+}
+
+/// Fails with `Unauthorized` unless the caller holds one of `roles`.
+pub fn require_any_role<M: Memory>(store: &RoleStore<M>, roles: &[Role]) -> Result<()> {
+    let caller = ic_cdk::caller();
+    if roles.iter().any(|role| has_role(store, &caller, *role)) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            reason: format!("caller requires one of the roles {:?}", roles),
+        })
+    }
+}
+
+pub fn require_role<M: Memory>(store: &RoleStore<M>, role: Role) -> Result<()> {
+    require_any_role(store, &[role])
+}
+
+/// Seeds the store from init arguments. No authorization check is made, as
+/// only the installer can run `init`.
+pub fn assign_roles<M: Memory>(store: &mut RoleStore<M>, assignments: Vec<RoleAssignment>) {
+    for assignment in assignments {
+        let mut roles = store.get(&assignment.principal).unwrap_or_default();
+        roles.insert(assignment.role);
+        store.insert(assignment.principal, roles);
+    }
+}
+
+/// Admins may manage operational roles; only controllers may hand out or take
+/// away `Controller` and `Admin`.
+fn authorize_role_change<M: Memory>(store: &RoleStore<M>, role: Role) -> Result<()> {
+    match role {
+        Role::Controller | Role::Admin => require_role(store, Role::Controller),
+        _ => require_role(store, Role::Admin),
+    }
+}
+
+pub fn grant_role<M: Memory>(store: &mut RoleStore<M>, assignment: RoleAssignment) -> Result<()> {
+    authorize_role_change(store, assignment.role)?;
+    assign_roles(store, vec![assignment]);
+    Ok(())
+}
+
+pub fn revoke_role<M: Memory>(store: &mut RoleStore<M>, assignment: RoleAssignment) -> Result<()> {
+    authorize_role_change(store, assignment.role)?;
+
+    let mut roles = store.get(&assignment.principal).unwrap_or_default();
+    roles.remove(assignment.role);
+    if roles.is_empty() {
+        store.remove(&assignment.principal);
+    } else {
+        store.insert(assignment.principal, roles);
+    }
+    Ok(())
+}
+
+pub fn get_roles<M: Memory>(store: &RoleStore<M>, principal: &Principal) -> Vec<Role> {
+    store.get(principal).unwrap_or_default().roles()
+}
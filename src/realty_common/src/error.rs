@@ -0,0 +1,90 @@
+use candid::CandidType;
+use ic_cdk::api::call::RejectionCode;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Error returned by every canister endpoint. Clients should branch on the
+/// variant; the string payloads are for display only.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum Error {
+    NotFound { entity: String },
+    AlreadyExists { entity: String },
+    Unauthorized { reason: String },
+    InsufficientTokens { requested: u64, available: u64 },
+    InvalidInput { field: String, reason: String },
+    InvalidState { reason: String },
+    DeadlinePassed,
+    AlreadyVoted,
+    CanisterCallFailed { method: String, reason: String },
+    PaymentFailed { reason: String },
+    Internal { reason: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn not_found(entity: &str) -> Self {
+        Error::NotFound { entity: entity.to_string() }
+    }
+
+    pub fn already_exists(entity: &str) -> Self {
+        Error::AlreadyExists { entity: entity.to_string() }
+    }
+
+    pub fn unauthorized(reason: &str) -> Self {
+        Error::Unauthorized { reason: reason.to_string() }
+    }
+
+    pub fn invalid_input(field: &str, reason: &str) -> Self {
+        Error::InvalidInput {
+            field: field.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn invalid_state(reason: &str) -> Self {
+        Error::InvalidState { reason: reason.to_string() }
+    }
+
+    pub fn payment_failed(reason: &str) -> Self {
+        Error::PaymentFailed { reason: reason.to_string() }
+    }
+
+    pub fn internal(reason: &str) -> Self {
+        Error::Internal { reason: reason.to_string() }
+    }
+
+    /// Wraps the rejection of an inter-canister call to `method`.
+    pub fn call_failed(method: &str, (code, msg): (RejectionCode, String)) -> Self {
+        Error::CanisterCallFailed {
+            method: method.to_string(),
+            reason: format!("{:?}: {}", code, msg),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound { entity } => write!(f, "{} not found", entity),
+            Error::AlreadyExists { entity } => write!(f, "{} already exists", entity),
+            Error::Unauthorized { reason } => write!(f, "unauthorized: {}", reason),
+            Error::InsufficientTokens { requested, available } => write!(
+                f,
+                "insufficient tokens: requested {}, available {}",
+                requested, available
+            ),
+            Error::InvalidInput { field, reason } => write!(f, "invalid {}: {}", field, reason),
+            Error::InvalidState { reason } => write!(f, "invalid state: {}", reason),
+            Error::DeadlinePassed => write!(f, "deadline has passed"),
+            Error::AlreadyVoted => write!(f, "already voted"),
+            Error::CanisterCallFailed { method, reason } => {
+                write!(f, "call to {} failed: {}", method, reason)
+            }
+            Error::PaymentFailed { reason } => write!(f, "payment failed: {}", reason),
+            Error::Internal { reason } => write!(f, "internal error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
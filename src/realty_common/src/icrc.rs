@@ -0,0 +1,235 @@
+//! Minimal ICRC-1/ICRC-2 ledger interface used to move funds between
+//! accounts, with ledger errors mapped onto `Error`.
+
+use crate::{Error, Result};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use serde::Serialize;
+
+pub type Subaccount = Vec<u8>;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+impl Account {
+    pub fn of(owner: Principal) -> Self {
+        Account { owner, subaccount: None }
+    }
+}
+
+/// Subaccount a canister keeps the funds of record `id` in, with the ID
+/// big-endian in the last eight of its 32 bytes.
+pub fn subaccount_for(id: u64) -> Subaccount {
+    let mut subaccount = vec![0; 32];
+    subaccount[24..].copy_from_slice(&id.to_be_bytes());
+    subaccount
+}
+
+/// Memo tying a transfer to the record it pays for: `tag` followed by the
+/// record ID big-endian. The ledger only deduplicates transfers with the
+/// same memo and `created_at_time`, so a transfer that is retried must keep
+/// the `created_at_time` of its first attempt.
+pub fn memo(tag: &str, id: u64) -> Vec<u8> {
+    let mut memo = tag.as_bytes().to_vec();
+    memo.extend_from_slice(&id.to_be_bytes());
+    memo
+}
+
+/// Ledger amount and creation time of a transfer, kept while its outcome is
+/// unknown so that a retry sends the same transfer again.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct LedgerTransfer {
+    pub amount: u64,
+    pub created_at_time: u64,
+}
+
+/// Whether a failed transfer may still have executed: the call to the
+/// ledger failed rather than the ledger rejecting the transfer. Retry such a
+/// transfer with its original `created_at_time`.
+pub fn outcome_unknown(err: &Error) -> bool {
+    matches!(err, Error::CanisterCallFailed { .. })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum MetadataValue {
+    Nat(Nat),
+    Int(candid::Int),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}
+
+/// ICRC-1 token of a property's shares, as described by the property
+/// canister and served by the property's token canister.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct PropertyToken {
+    pub property_id: u64,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub fee: u64,
+    pub total_supply: u64,
+}
+
+impl PropertyToken {
+    pub fn metadata(&self) -> Vec<(String, MetadataValue)> {
+        vec![
+            ("icrc1:name".to_string(), MetadataValue::Text(self.name.clone())),
+            ("icrc1:symbol".to_string(), MetadataValue::Text(self.symbol.clone())),
+            ("icrc1:decimals".to_string(), MetadataValue::Nat(Nat::from(self.decimals))),
+            ("icrc1:fee".to_string(), MetadataValue::Nat(Nat::from(self.fee))),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+fn block_index(index: Nat) -> Result<u64> {
+    u64::try_from(&index.0).map_err(|_| Error::internal("ledger block index does not fit in u64"))
+}
+
+/// Transfers `amount` out of `from_subaccount` of the calling canister.
+/// Returns the ledger block index, also when the ledger reports the transfer
+/// as a duplicate of one it already executed.
+pub async fn transfer(
+    ledger: Principal,
+    from_subaccount: Option<Subaccount>,
+    to: Account,
+    amount: u64,
+    memo: Vec<u8>,
+    created_at_time: u64,
+) -> Result<u64> {
+    let arg = TransferArg {
+        from_subaccount,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: Some(memo),
+        created_at_time: Some(created_at_time),
+    };
+    let (result,): (std::result::Result<Nat, TransferError>,) =
+        ic_cdk::call(ledger, "icrc1_transfer", (arg,))
+            .await
+            .map_err(|err| Error::call_failed("icrc1_transfer", err))?;
+    match result {
+        // A retry the ledger has already executed
+        Ok(block) | Err(TransferError::Duplicate { duplicate_of: block }) => block_index(block),
+        Err(err) => Err(Error::payment_failed(&format!("{:?}", err))),
+    }
+}
+
+/// Moves `amount` from `from` to `to` under an ICRC-2 allowance `from` has
+/// granted the calling canister. Returns the ledger block index.
+pub async fn transfer_from(
+    ledger: Principal,
+    from: Account,
+    to: Account,
+    amount: u64,
+    memo: Vec<u8>,
+    created_at_time: u64,
+) -> Result<u64> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: Some(memo),
+        created_at_time: Some(created_at_time),
+    };
+    let (result,): (std::result::Result<Nat, TransferFromError>,) =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+            .await
+            .map_err(|err| Error::call_failed("icrc2_transfer_from", err))?;
+    result
+        .map_err(|err| Error::payment_failed(&format!("{:?}", err)))
+        .and_then(block_index)
+}
+
+/// The fee the ledger charges per transfer.
+pub async fn fee(ledger: Principal) -> Result<u64> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|err| Error::call_failed("icrc1_fee", err))?;
+    u64::try_from(&fee.0).map_err(|_| Error::internal("ledger fee does not fit in u64"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subaccounts_are_distinct_per_id() {
+        assert_eq!(subaccount_for(1).len(), 32);
+        assert_eq!(subaccount_for(1)[31], 1);
+        assert_ne!(subaccount_for(1), subaccount_for(256));
+        assert_ne!(subaccount_for(0), subaccount_for(1));
+    }
+
+    #[test]
+    fn memos_identify_the_record() {
+        assert_eq!(memo("invest", 1), b"invest\0\0\0\0\0\0\0\x01".to_vec());
+        assert_ne!(memo("invest", 1), memo("refund", 1));
+        assert!(memo("withdraw", u64::MAX).len() <= 32);
+    }
+
+    #[test]
+    fn only_failed_calls_leave_the_outcome_unknown() {
+        assert!(outcome_unknown(&Error::CanisterCallFailed {
+            method: "icrc1_transfer".to_string(),
+            reason: "canister is stopping".to_string(),
+        }));
+        assert!(!outcome_unknown(&Error::payment_failed("InsufficientFunds")));
+    }
+}
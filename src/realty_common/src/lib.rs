@@ -0,0 +1,9 @@
+//! Types shared by the RealtyChain canisters.
+
+pub mod access;
+pub mod error;
+pub mod icrc;
+pub mod paging;
+pub mod schema;
+
+pub use error::{Error, Result};
@@ -0,0 +1,250 @@
+use candid::CandidType;
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use serde::Deserialize;
+use std::ops::Bound;
+
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Request for one page of a list query. Pass the `next_cursor` of the
+/// previous page as `start_after` to continue; `limit` is capped at
+/// `MAX_PAGE_SIZE`.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PageRequest<K> {
+    pub start_after: Option<K>,
+    pub limit: Option<u32>,
+    pub order: Option<SortOrder>,
+}
+
+impl<K> PageRequest<K> {
+    pub fn page_size(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize
+    }
+
+    pub fn order(&self) -> SortOrder {
+        self.order.unwrap_or_default()
+    }
+}
+
+impl<K> Default for PageRequest<K> {
+    fn default() -> Self {
+        PageRequest {
+            start_after: None,
+            limit: None,
+            order: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Page<T, K> {
+    pub items: Vec<T>,
+    /// Cursor for the following page, or `None` when this is the last one.
+    pub next_cursor: Option<K>,
+    /// Number of items matching the query across all pages.
+    pub total: u64,
+}
+
+impl<T, K> Page<T, K> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U, K> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+            total: self.total,
+        }
+    }
+}
+
+/// Takes one page from `entries`, which must already be positioned after the
+/// cursor and ordered as requested.
+pub fn collect_page<K: Clone, T>(
+    entries: impl Iterator<Item = (K, T)>,
+    page_size: usize,
+    total: u64,
+) -> Page<T, K> {
+    let mut entries = entries.peekable();
+    let mut items = Vec::with_capacity(page_size);
+    let mut last_key = None;
+
+    while items.len() < page_size {
+        match entries.next() {
+            Some((key, item)) => {
+                last_key = Some(key);
+                items.push(item);
+            }
+            None => break,
+        }
+    }
+
+    let next_cursor = if entries.peek().is_some() { last_key } else { None };
+
+    Page {
+        items,
+        next_cursor,
+        total,
+    }
+}
+
+fn entries_after<'a, K, V, M>(
+    map: &'a StableBTreeMap<K, V, M>,
+    req: &PageRequest<K>,
+) -> Box<dyn Iterator<Item = (K, V)> + 'a>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    match (req.order(), req.start_after.clone()) {
+        (SortOrder::Ascending, None) => Box::new(map.iter()),
+        (SortOrder::Ascending, Some(key)) => {
+            Box::new(map.range((Bound::Excluded(key), Bound::Unbounded)))
+        }
+        (SortOrder::Descending, None) => Box::new(map.iter().rev()),
+        (SortOrder::Descending, Some(key)) => Box::new(map.range(..key).rev()),
+    }
+}
+
+/// Returns one page of every entry in `map`, ordered by key.
+pub fn paginate<K, V, M>(map: &StableBTreeMap<K, V, M>, req: &PageRequest<K>) -> Page<V, K>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    collect_page(entries_after(map, req), req.page_size(), map.len())
+}
+
+/// Returns one page of the entries in `map` whose value satisfies `filter`.
+/// Counting `total` scans the whole map.
+pub fn paginate_filtered<K, V, M>(
+    map: &StableBTreeMap<K, V, M>,
+    req: &PageRequest<K>,
+    filter: impl Fn(&V) -> bool,
+) -> Page<V, K>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    let total = map.iter().filter(|(_, value)| filter(value)).count() as u64;
+    let entries = entries_after(map, req).filter(|(_, value)| filter(value));
+    collect_page(entries, req.page_size(), total)
+}
+
+/// Returns one page of `entries`, which must be sorted ascending by unique
+/// key. Used for lists assembled from a secondary index.
+pub fn paginate_sorted<K: Ord + Clone, T>(entries: Vec<(K, T)>, req: &PageRequest<K>) -> Page<T, K> {
+    let total = entries.len() as u64;
+    let cursor = req.start_after.clone();
+
+    match req.order() {
+        SortOrder::Ascending => {
+            let entries = entries
+                .into_iter()
+                .filter(|(key, _)| cursor.as_ref().is_none_or(|c| key > c));
+            collect_page(entries, req.page_size(), total)
+        }
+        SortOrder::Descending => {
+            let entries = entries
+                .into_iter()
+                .rev()
+                .filter(|(key, _)| cursor.as_ref().is_none_or(|c| key < c));
+            collect_page(entries, req.page_size(), total)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    fn map_with(keys: impl IntoIterator<Item = u64>) -> StableBTreeMap<u64, u64, DefaultMemoryImpl> {
+        let mut map = StableBTreeMap::new(DefaultMemoryImpl::default());
+        for key in keys {
+            map.insert(key, key * 10);
+        }
+        map
+    }
+
+    fn request(start_after: Option<u64>, limit: u32, order: SortOrder) -> PageRequest<u64> {
+        PageRequest {
+            start_after,
+            limit: Some(limit),
+            order: Some(order),
+        }
+    }
+
+    #[test]
+    fn walks_pages_with_cursor() {
+        let map = map_with(1..=5);
+
+        let first = paginate(&map, &request(None, 2, SortOrder::Ascending));
+        assert_eq!(first.items, vec![10, 20]);
+        assert_eq!(first.next_cursor, Some(2));
+        assert_eq!(first.total, 5);
+
+        let second = paginate(&map, &request(first.next_cursor, 2, SortOrder::Ascending));
+        assert_eq!(second.items, vec![30, 40]);
+
+        let last = paginate(&map, &request(second.next_cursor, 2, SortOrder::Ascending));
+        assert_eq!(last.items, vec![50]);
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[test]
+    fn walks_descending() {
+        let map = map_with(1..=4);
+
+        let first = paginate(&map, &request(None, 3, SortOrder::Descending));
+        assert_eq!(first.items, vec![40, 30, 20]);
+
+        let last = paginate(&map, &request(first.next_cursor, 3, SortOrder::Descending));
+        assert_eq!(last.items, vec![10]);
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[test]
+    fn filters_and_counts_matches() {
+        let map = map_with(1..=10);
+
+        let page = paginate_filtered(&map, &request(None, 2, SortOrder::Ascending), |v| v % 20 == 0);
+        assert_eq!(page.items, vec![20, 40]);
+        assert_eq!(page.total, 5);
+        assert_eq!(page.next_cursor, Some(4));
+    }
+
+    #[test]
+    fn pages_sorted_entries() {
+        let entries: Vec<(u64, &str)> = vec![(2, "b"), (5, "e"), (9, "i")];
+
+        let first = paginate_sorted(entries.clone(), &request(None, 2, SortOrder::Ascending));
+        assert_eq!(first.items, vec!["b", "e"]);
+        assert_eq!(first.next_cursor, Some(5));
+        assert_eq!(first.total, 3);
+
+        let rest = paginate_sorted(entries.clone(), &request(Some(5), 2, SortOrder::Ascending));
+        assert_eq!(rest.items, vec!["i"]);
+        assert_eq!(rest.next_cursor, None);
+
+        let desc = paginate_sorted(entries, &request(Some(9), 5, SortOrder::Descending));
+        assert_eq!(desc.items, vec!["e", "b"]);
+    }
+
+    #[test]
+    fn caps_page_size() {
+        let req = PageRequest::<u64> {
+            limit: Some(10_000),
+            ..Default::default()
+        };
+        assert_eq!(req.page_size(), MAX_PAGE_SIZE as usize);
+        assert_eq!(PageRequest::<u64>::default().page_size(), DEFAULT_PAGE_SIZE as usize);
+    }
+}
@@ -0,0 +1,229 @@
+//! Versioned encoding for records kept in stable memory, and the migration
+//! runner canisters call from `post_upgrade`.
+//!
+//! Records are stored as a one-byte schema version followed by the candid
+//! payload. Blobs written before versioning existed are bare candid and are
+//! treated as version 0; they are recognised by the candid `DIDL` magic, so
+//! record versions must stay below `b'D'`.
+
+use candid::CandidType;
+use serde::de::DeserializeOwned;
+
+const CANDID_MAGIC: &[u8] = b"DIDL";
+
+/// A record type stored in a `StableBTreeMap`.
+pub trait Versioned: CandidType + DeserializeOwned {
+    /// The version written for newly encoded records.
+    const VERSION: u8;
+
+    /// Decodes a record written under an older `version`. The default relies
+    /// on candid subtyping, which covers added `Option` fields; types that
+    /// change shape in other ways decode into their previous struct here and
+    /// convert.
+    fn decode_previous(version: u8, payload: &[u8]) -> Self {
+        decode_payload(version, payload)
+    }
+}
+
+/// Decodes the candid payload of a record stored at `version` into `T`,
+/// which may be the current type or a legacy layout.
+pub fn decode_payload<T: CandidType + DeserializeOwned>(version: u8, payload: &[u8]) -> T {
+    candid::decode_one(payload).unwrap_or_else(|err| {
+        panic!(
+            "failed to decode {} v{} record: {}",
+            std::any::type_name::<T>(),
+            version,
+            err
+        )
+    })
+}
+
+pub fn encode<T: Versioned>(value: &T) -> Vec<u8> {
+    let mut bytes = vec![T::VERSION];
+    bytes.extend(candid::encode_one(value).expect("failed to encode record"));
+    bytes
+}
+
+pub fn decode<T: Versioned>(bytes: &[u8]) -> T {
+    let (version, payload) = if bytes.starts_with(CANDID_MAGIC) {
+        (0, bytes)
+    } else {
+        (bytes[0], &bytes[1..])
+    };
+
+    if version == T::VERSION {
+        decode_payload(version, payload)
+    } else if version < T::VERSION {
+        T::decode_previous(version, payload)
+    } else {
+        panic!(
+            "{} record has version {} but this build only supports up to {}",
+            std::any::type_name::<T>(),
+            version,
+            T::VERSION
+        )
+    }
+}
+
+/// Implements `Storable` for a `Versioned` type using the versioned envelope.
+#[macro_export]
+macro_rules! versioned_storable {
+    ($ty:ty) => {
+        impl ic_stable_structures::Storable for $ty {
+            fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                std::borrow::Cow::Owned($crate::schema::encode(self))
+            }
+
+            fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                $crate::schema::decode(&bytes)
+            }
+
+            const BOUND: ic_stable_structures::storable::Bound =
+                ic_stable_structures::storable::Bound::Unbounded;
+        }
+    };
+}
+
+/// A step that brings stable memory up to schema `version`.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub run: fn(),
+}
+
+/// Runs, in order, every migration newer than `current` and returns the
+/// schema version the canister ends up at.
+pub fn run_migrations(current: u32, migrations: &[Migration]) -> u32 {
+    let mut version = current;
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        assert!(
+            migration.version > version,
+            "migrations must be listed in increasing version order"
+        );
+        ic_cdk::println!(
+            "Running migration to schema v{}: {}",
+            migration.version,
+            migration.description
+        );
+        (migration.run)();
+        version = migration.version;
+    }
+    version
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Deserialize;
+
+    #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+    struct RecordV0 {
+        id: u64,
+        name: String,
+    }
+
+    #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+    struct RecordV1 {
+        id: u64,
+        name: String,
+        note: Option<String>,
+    }
+
+    impl Versioned for RecordV1 {
+        const VERSION: u8 = 1;
+    }
+
+    #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+    struct RecordV2 {
+        id: u64,
+        display_name: String,
+        note: Option<String>,
+    }
+
+    impl Versioned for RecordV2 {
+        const VERSION: u8 = 2;
+
+        fn decode_previous(version: u8, payload: &[u8]) -> Self {
+            let old: RecordV1 = decode_payload(version, payload);
+            RecordV2 {
+                id: old.id,
+                display_name: old.name,
+                note: old.note,
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_current_version() {
+        let record = RecordV1 {
+            id: 7,
+            name: "Loft".to_string(),
+            note: Some("renovated".to_string()),
+        };
+        let bytes = encode(&record);
+        assert_eq!(bytes[0], 1);
+        assert_eq!(decode::<RecordV1>(&bytes), record);
+    }
+
+    #[test]
+    fn loads_unversioned_legacy_blob() {
+        let legacy = candid::encode_one(RecordV0 {
+            id: 3,
+            name: "Warehouse".to_string(),
+        })
+        .unwrap();
+
+        let record: RecordV1 = decode(&legacy);
+        assert_eq!(
+            record,
+            RecordV1 {
+                id: 3,
+                name: "Warehouse".to_string(),
+                note: None,
+            }
+        );
+    }
+
+    #[test]
+    fn upgrades_older_version_through_decode_previous() {
+        let bytes = encode(&RecordV1 {
+            id: 9,
+            name: "Duplex".to_string(),
+            note: None,
+        });
+
+        let record: RecordV2 = decode(&bytes);
+        assert_eq!(record.display_name, "Duplex");
+        assert_eq!(record.id, 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "only supports up to 1")]
+    fn rejects_newer_version() {
+        let bytes = encode(&RecordV2 {
+            id: 1,
+            display_name: "Tower".to_string(),
+            note: None,
+        });
+        let _: RecordV1 = decode(&bytes);
+    }
+
+    #[test]
+    fn runs_only_pending_migrations_in_order() {
+        use std::cell::RefCell;
+
+        thread_local! {
+            static RAN: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+        }
+
+        let migrations = [
+            Migration { version: 1, description: "one", run: || RAN.with(|r| r.borrow_mut().push(1)) },
+            Migration { version: 2, description: "two", run: || RAN.with(|r| r.borrow_mut().push(2)) },
+            Migration { version: 3, description: "three", run: || RAN.with(|r| r.borrow_mut().push(3)) },
+        ];
+
+        assert_eq!(run_migrations(1, &migrations), 3);
+        assert_eq!(RAN.with(|r| r.borrow().clone()), vec![2, 3]);
+        assert_eq!(run_migrations(3, &migrations), 3);
+    }
+}
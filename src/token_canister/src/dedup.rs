@@ -0,0 +1,109 @@
+//! ICRC-1 transaction deduplication. Transfers that set `created_at_time`
+//! are remembered for the transaction window, and a transfer repeating one
+//! with the same arguments is answered with the block of the first.
+
+use candid::{CandidType, Deserialize};
+use realty_common::icrc::{Account, Subaccount, TransferError};
+use realty_common::schema::Versioned;
+use realty_common::versioned_storable;
+use serde::Serialize;
+
+/// How long a transfer is remembered after its `created_at_time`.
+pub const TX_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// How far clocks of clients and this canister may drift apart.
+pub const PERMITTED_DRIFT_NANOS: u64 = 2 * 60 * 1_000_000_000;
+pub const MAX_MEMO_BYTES: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct RecentTransfer {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub amount: u64,
+    pub fee: Option<u64>,
+    pub memo: Option<Vec<u8>>,
+    pub block: Option<u64>, // None while the transfer is in flight
+}
+
+/// Transfers one caller made with the same `created_at_time`.
+#[derive(Clone, Debug, Default, CandidType, Deserialize, Serialize)]
+pub struct RecentTransfers(pub Vec<RecentTransfer>);
+
+impl Versioned for RecentTransfers {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(RecentTransfers);
+
+impl RecentTransfer {
+    /// Whether both transfers were made with the same arguments.
+    pub fn same_arguments(&self, other: &RecentTransfer) -> bool {
+        RecentTransfer {
+            block: other.block,
+            ..self.clone()
+        } == *other
+    }
+}
+
+/// Rejects a `created_at_time` outside the window the canister remembers
+/// transfers for.
+pub fn check_created_at(created_at: u64, now: u64) -> Result<(), TransferError> {
+    if created_at.saturating_add(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS) < now {
+        return Err(TransferError::TooOld);
+    }
+    if created_at > now.saturating_add(PERMITTED_DRIFT_NANOS) {
+        return Err(TransferError::CreatedInFuture { ledger_time: now });
+    }
+    Ok(())
+}
+
+/// Whether transfers created at `created_at` no longer need remembering.
+pub fn expired(created_at: u64, now: u64) -> bool {
+    created_at.saturating_add(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS) < now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+
+    #[test]
+    fn accepts_creation_times_within_the_window() {
+        let now = 10 * TX_WINDOW_NANOS;
+        assert!(check_created_at(now, now).is_ok());
+        assert!(check_created_at(now - TX_WINDOW_NANOS - PERMITTED_DRIFT_NANOS, now).is_ok());
+        assert!(matches!(
+            check_created_at(now - TX_WINDOW_NANOS - PERMITTED_DRIFT_NANOS - 1, now),
+            Err(TransferError::TooOld)
+        ));
+        assert!(check_created_at(now + PERMITTED_DRIFT_NANOS, now).is_ok());
+        assert!(matches!(
+            check_created_at(now + PERMITTED_DRIFT_NANOS + 1, now),
+            Err(TransferError::CreatedInFuture { ledger_time }) if ledger_time == now
+        ));
+    }
+
+    #[test]
+    fn compares_arguments_but_not_blocks() {
+        let transfer = RecentTransfer {
+            from_subaccount: None,
+            to: Account::of(Principal::anonymous()),
+            amount: 5,
+            fee: None,
+            memo: Some(b"rent".to_vec()),
+            block: None,
+        };
+        let recorded = RecentTransfer {
+            block: Some(7),
+            ..transfer.clone()
+        };
+        assert!(transfer.same_arguments(&recorded));
+        assert!(!transfer.same_arguments(&RecentTransfer {
+            amount: 6,
+            ..recorded.clone()
+        }));
+        assert!(!transfer.same_arguments(&RecentTransfer {
+            memo: None,
+            ..recorded
+        }));
+    }
+}
@@ -0,0 +1,550 @@
+//! ICRC-1 token of one property's shares. One instance is installed per
+//! property with the description the property canister gives out through
+//! `get_property_token`, and is registered with the investment canister
+//! through `set_property_token_canister`.
+//!
+//! Ownership stays with the investment canister: balances here mirror the
+//! holding changes it logs for the property, and transfers move holdings
+//! through its `transfer_holding`. The shares still for sale are not held
+//! by any account; they are reported as the balance of the minting account,
+//! which exists only here.
+
+mod dedup;
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use dedup::{RecentTransfer, RecentTransfers};
+use ic_cdk_macros::{init, post_upgrade, query, update};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use realty_common::icrc::{self, Account, MetadataValue, PropertyToken, StandardRecord, TransferArg, TransferError};
+use realty_common::schema::{self, Migration, Versioned};
+use realty_common::{versioned_storable, Error, Result};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::time::Duration;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+type SchemaVersionCell = StableCell<u32, Memory>;
+type ConfigStore = StableBTreeMap<u8, TokenConfig, Memory>;
+type BalanceStore = StableBTreeMap<Principal, u64, Memory>;
+type SyncStateStore = StableBTreeMap<u8, u64, Memory>;
+type RecentTransferStore = StableBTreeMap<(u64, Principal), RecentTransfers, Memory>;
+
+const CONFIG_KEY: u8 = 0;
+/// Last holding change applied to the balances.
+const SYNCED_SEQ_KEY: u8 = 0;
+/// Sum of the balances held by investors.
+const HELD_KEY: u8 = 1;
+/// How often holding changes made outside this canister are picked up.
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+/// Most expired transfer records dropped per transfer, to bound its cost.
+const MAX_PRUNED_PER_TRANSFER: usize = 100;
+
+/// Bump when a stored record changes shape and add the matching migration.
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct TokenConfig {
+    pub token: PropertyToken,
+    pub property_canister: Principal,
+    pub investment_canister: Principal,
+}
+
+impl Versioned for TokenConfig {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(TokenConfig);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub token: PropertyToken,
+    pub property_canister: Principal,
+    pub investment_canister: Principal,
+}
+
+/// The fields of the investment canister's `HoldingChange` the balances are
+/// mirrored from.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct HoldingChange {
+    seq: u64,
+    holder: Principal,
+    balance: u64,
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(DefaultMemoryImpl::default())
+    );
+
+    static STORED_SCHEMA_VERSION: RefCell<SchemaVersionCell> = RefCell::new(
+        SchemaVersionCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))),
+            0,
+        ).expect("failed to initialize schema version")
+    );
+
+    static CONFIG: RefCell<ConfigStore> = RefCell::new(
+        ConfigStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+        )
+    );
+
+    static BALANCES: RefCell<BalanceStore> = RefCell::new(
+        BalanceStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
+        )
+    );
+
+    static SYNC_STATE: RefCell<SyncStateStore> = RefCell::new(
+        SyncStateStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+        )
+    );
+
+    /// Transfers made with `created_at_time`, keyed by it and the caller.
+    static RECENT_TRANSFERS: RefCell<RecentTransferStore> = RefCell::new(
+        RecentTransferStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
+        )
+    );
+}
+
+#[init]
+fn init(args: InitArgs) {
+    set_schema_version(SCHEMA_VERSION);
+    CONFIG.with(|config| {
+        config.borrow_mut().insert(
+            CONFIG_KEY,
+            TokenConfig {
+                token: args.token,
+                property_canister: args.property_canister,
+                investment_canister: args.investment_canister,
+            },
+        )
+    });
+    schedule_sync();
+}
+
+const MIGRATIONS: &[Migration] = &[];
+
+#[post_upgrade]
+fn post_upgrade() {
+    let stored = STORED_SCHEMA_VERSION.with(|v| *v.borrow().get());
+    let migrated = schema::run_migrations(stored, MIGRATIONS);
+    assert_eq!(migrated, SCHEMA_VERSION, "missing migration to schema v{}", SCHEMA_VERSION);
+    set_schema_version(migrated);
+
+    // Timers do not survive an upgrade
+    schedule_sync();
+}
+
+fn set_schema_version(version: u32) {
+    STORED_SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(version)
+            .expect("failed to store schema version");
+    });
+}
+
+#[query]
+fn get_schema_version() -> u32 {
+    STORED_SCHEMA_VERSION.with(|v| *v.borrow().get())
+}
+
+fn config() -> TokenConfig {
+    CONFIG.with(|config| config.borrow().get(&CONFIG_KEY).expect("token canister is not initialized"))
+}
+
+#[query]
+fn get_token_config() -> TokenConfig {
+    config()
+}
+
+fn sync_state(key: u8) -> u64 {
+    SYNC_STATE.with(|state| state.borrow().get(&key).unwrap_or(0))
+}
+
+fn schedule_sync() {
+    ic_cdk_timers::set_timer_interval(SYNC_INTERVAL, || {
+        ic_cdk::spawn(async {
+            if let Err(err) = sync_holdings().await {
+                ic_cdk::println!("failed to sync holdings: {}", err);
+            }
+        });
+    });
+}
+
+/// Applies the holding changes the investment canister logged since the
+/// last sync. Returns the sequence number of the last change applied.
+#[update]
+async fn sync_holdings() -> Result<u64> {
+    let config = config();
+    loop {
+        let (changes,): (Vec<HoldingChange>,) = ic_cdk::call(
+            config.investment_canister,
+            "get_holding_changes_since",
+            (config.token.property_id, sync_state(SYNCED_SEQ_KEY), None::<u32>),
+        )
+        .await
+        .map_err(|err| Error::call_failed("get_holding_changes_since", err))?;
+        if changes.is_empty() {
+            return Ok(sync_state(SYNCED_SEQ_KEY));
+        }
+        for change in &changes {
+            apply_change(change);
+        }
+    }
+}
+
+/// Sets the holder's balance to the one logged in `change`. Changes at or
+/// before the synced sequence number are skipped, so a sync that read a
+/// page while another was applying it cannot roll balances back.
+fn apply_change(change: &HoldingChange) {
+    if change.seq <= sync_state(SYNCED_SEQ_KEY) {
+        return;
+    }
+    let previous = BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        if change.balance == 0 {
+            balances.remove(&change.holder)
+        } else {
+            balances.insert(change.holder, change.balance)
+        }
+    });
+    SYNC_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let held = state.get(&HELD_KEY).unwrap_or(0);
+        state.insert(HELD_KEY, held - previous.unwrap_or(0) + change.balance);
+        state.insert(SYNCED_SEQ_KEY, change.seq);
+    });
+}
+
+/// Minting account of the token. It exists only on this canister: its
+/// balance is the supply not held by investors, and the property canister
+/// owns no such ledger account.
+fn minting_account(config: &TokenConfig) -> Account {
+    Account {
+        owner: config.property_canister,
+        subaccount: Some(icrc::subaccount_for(config.token.property_id)),
+    }
+}
+
+/// Holdings are recorded per principal, so only default accounts can hold
+/// shares.
+fn is_default_account(account: &Account) -> bool {
+    account
+        .subaccount
+        .as_ref()
+        .is_none_or(|subaccount| subaccount.iter().all(|byte| *byte == 0))
+}
+
+fn generic_error(err: &Error) -> TransferError {
+    match err {
+        Error::InsufficientTokens { available, .. } => TransferError::InsufficientFunds {
+            balance: Nat::from(*available),
+        },
+        err => TransferError::GenericError {
+            error_code: Nat::from(1u8),
+            message: err.to_string(),
+        },
+    }
+}
+
+/// Records `transfer` as in flight, unless the caller already made it with
+/// the same `created_at_time`: then it fails with the block of the earlier
+/// transfer, or as temporarily unavailable while that one is in flight.
+fn begin_transfer(caller: Principal, created_at: u64, transfer: &RecentTransfer) -> std::result::Result<(), TransferError> {
+    RECENT_TRANSFERS.with(|store| {
+        let mut store = store.borrow_mut();
+        let mut recent = store.get(&(created_at, caller)).unwrap_or_default();
+        if let Some(earlier) = recent.0.iter().find(|earlier| earlier.same_arguments(transfer)) {
+            return Err(match earlier.block {
+                Some(block) => TransferError::Duplicate {
+                    duplicate_of: Nat::from(block),
+                },
+                None => TransferError::TemporarilyUnavailable,
+            });
+        }
+        recent.0.push(transfer.clone());
+        store.insert((created_at, caller), recent);
+        Ok(())
+    })
+}
+
+/// Records the block of a transfer started with `begin_transfer`, or
+/// forgets the transfer if it failed so that it can be retried.
+fn finish_transfer(caller: Principal, created_at: u64, transfer: &RecentTransfer, block: Option<u64>) {
+    RECENT_TRANSFERS.with(|store| {
+        let mut store = store.borrow_mut();
+        let Some(mut recent) = store.get(&(created_at, caller)) else {
+            return;
+        };
+        let Some(index) = recent.0.iter().position(|earlier| earlier.same_arguments(transfer)) else {
+            return;
+        };
+        match block {
+            Some(block) => recent.0[index].block = Some(block),
+            None => {
+                recent.0.remove(index);
+            }
+        }
+        if recent.0.is_empty() {
+            store.remove(&(created_at, caller));
+        } else {
+            store.insert((created_at, caller), recent);
+        }
+    });
+}
+
+/// Drops transfers created too long ago to be deduplicated any more.
+fn prune_recent_transfers(now: u64) {
+    RECENT_TRANSFERS.with(|store| {
+        let mut store = store.borrow_mut();
+        let expired: Vec<(u64, Principal)> = store
+            .iter()
+            .map(|(key, _)| key)
+            .take_while(|(created_at, _)| dedup::expired(*created_at, now))
+            .take(MAX_PRUNED_PER_TRANSFER)
+            .collect();
+        for key in expired {
+            store.remove(&key);
+        }
+    });
+}
+
+#[query]
+fn icrc1_name() -> String {
+    config().token.name
+}
+
+#[query]
+fn icrc1_symbol() -> String {
+    config().token.symbol
+}
+
+#[query]
+fn icrc1_decimals() -> u8 {
+    config().token.decimals
+}
+
+#[query]
+fn icrc1_fee() -> Nat {
+    Nat::from(config().token.fee)
+}
+
+#[query]
+fn icrc1_metadata() -> Vec<(String, MetadataValue)> {
+    config().token.metadata()
+}
+
+#[query]
+fn icrc1_total_supply() -> Nat {
+    Nat::from(config().token.total_supply)
+}
+
+#[query]
+fn icrc1_minting_account() -> Option<Account> {
+    Some(minting_account(&config()))
+}
+
+/// Balance of `account` as of the last sync with the investment canister.
+#[query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    let config = config();
+    if account == minting_account(&config) {
+        return Nat::from(config.token.total_supply.saturating_sub(sync_state(HELD_KEY)));
+    }
+    if !is_default_account(&account) {
+        return Nat::from(0u8);
+    }
+    Nat::from(BALANCES.with(|balances| balances.borrow().get(&account.owner).unwrap_or(0)))
+}
+
+#[query]
+fn icrc1_supported_standards() -> Vec<StandardRecord> {
+    vec![StandardRecord {
+        name: "ICRC-1".to_string(),
+        url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1".to_string(),
+    }]
+}
+
+/// Moves shares from the caller's default account to `arg.to` by moving the
+/// holding on the investment canister, which applies its KYC and lockup
+/// rules. Returns the ID of the investment canister's transfer transaction
+/// as the block index. Transfers that set `created_at_time` are
+/// deduplicated for the ICRC-1 transaction window.
+#[update]
+async fn icrc1_transfer(arg: TransferArg) -> std::result::Result<Nat, TransferError> {
+    let caller = ic_cdk::caller();
+    let config = config();
+    if arg.fee.as_ref().is_some_and(|fee| u64::try_from(&fee.0).ok() != Some(config.token.fee)) {
+        return Err(TransferError::BadFee {
+            expected_fee: Nat::from(config.token.fee),
+        });
+    }
+    let from = Account {
+        owner: caller,
+        subaccount: arg.from_subaccount,
+    };
+    if !is_default_account(&from) || !is_default_account(&arg.to) {
+        return Err(generic_error(&Error::invalid_input(
+            "subaccount",
+            "shares can only be held by default accounts",
+        )));
+    }
+    let amount = u64::try_from(&arg.amount.0)
+        .map_err(|_| generic_error(&Error::invalid_input("amount", "is too large")))?;
+    if arg.memo.as_ref().is_some_and(|memo| memo.len() > dedup::MAX_MEMO_BYTES) {
+        return Err(generic_error(&Error::invalid_input(
+            "memo",
+            &format!("must be at most {} bytes", dedup::MAX_MEMO_BYTES),
+        )));
+    }
+
+    let dedup = match arg.created_at_time {
+        Some(created_at) => {
+            let now = ic_cdk::api::time();
+            dedup::check_created_at(created_at, now)?;
+            prune_recent_transfers(now);
+            let transfer = RecentTransfer {
+                from_subaccount: from.subaccount,
+                to: arg.to.clone(),
+                amount,
+                fee: arg.fee.as_ref().map(|_| config.token.fee),
+                memo: arg.memo,
+                block: None,
+            };
+            begin_transfer(caller, created_at, &transfer)?;
+            Some((created_at, transfer))
+        }
+        None => None,
+    };
+
+    let moved = ic_cdk::call::<_, (Result<u64>,)>(
+        config.investment_canister,
+        "transfer_holding",
+        (config.token.property_id, caller, arg.to.owner, amount),
+    )
+    .await
+    .map_err(|err| Error::call_failed("transfer_holding", err))
+    .and_then(|(moved,)| moved);
+    if let Some((created_at, transfer)) = &dedup {
+        finish_transfer(caller, *created_at, transfer, moved.as_ref().ok().copied());
+    }
+    let block = moved.map_err(|err| generic_error(&err))?;
+
+    // The timer catches up if this sync fails
+    if let Err(err) = sync_holdings().await {
+        ic_cdk::println!("failed to sync holdings after transfer {}: {}", block, err);
+    }
+    Ok(Nat::from(block))
+}
+
+// Export candid interface
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn configure(total_supply: u64) -> TokenConfig {
+        let config = TokenConfig {
+            token: PropertyToken {
+                property_id: 1,
+                name: "Harbor View Shares".to_string(),
+                symbol: "RLTY1".to_string(),
+                decimals: 0,
+                fee: 0,
+                total_supply,
+            },
+            property_canister: principal(100),
+            investment_canister: principal(101),
+        };
+        CONFIG.with(|store| store.borrow_mut().insert(CONFIG_KEY, config.clone()));
+        config
+    }
+
+    fn change(seq: u64, holder: u8, balance: u64) -> HoldingChange {
+        HoldingChange {
+            seq,
+            holder: principal(holder),
+            balance,
+        }
+    }
+
+    #[test]
+    fn zero_subaccount_is_the_default_account() {
+        let owner = Principal::anonymous();
+        assert!(is_default_account(&Account::of(owner)));
+        assert!(is_default_account(&Account {
+            owner,
+            subaccount: Some(vec![0; 32]),
+        }));
+        assert!(!is_default_account(&Account {
+            owner,
+            subaccount: Some(icrc::subaccount_for(1)),
+        }));
+    }
+
+    #[test]
+    fn balances_mirror_logged_holdings() {
+        let config = configure(1_000);
+        let treasury = minting_account(&config);
+
+        apply_change(&change(1, 1, 300));
+        apply_change(&change(2, 2, 200));
+        apply_change(&change(3, 1, 250));
+        assert_eq!(icrc1_balance_of(Account::of(principal(1))), Nat::from(250u64));
+        assert_eq!(icrc1_balance_of(Account::of(principal(2))), Nat::from(200u64));
+        assert_eq!(icrc1_balance_of(treasury.clone()), Nat::from(550u64));
+
+        // A stale page replayed after later changes leaves them in place
+        apply_change(&change(1, 1, 300));
+        assert_eq!(icrc1_balance_of(Account::of(principal(1))), Nat::from(250u64));
+
+        apply_change(&change(4, 2, 0));
+        assert_eq!(icrc1_balance_of(Account::of(principal(2))), Nat::from(0u8));
+        assert_eq!(icrc1_balance_of(treasury), Nat::from(750u64));
+        assert_eq!(sync_state(SYNCED_SEQ_KEY), 4);
+    }
+
+    #[test]
+    fn retried_transfers_are_answered_with_the_first_block() {
+        let created_at = 1_000;
+        let transfer = RecentTransfer {
+            from_subaccount: None,
+            to: Account::of(principal(2)),
+            amount: 10,
+            fee: None,
+            memo: None,
+            block: None,
+        };
+        begin_transfer(principal(1), created_at, &transfer).unwrap();
+        assert!(matches!(
+            begin_transfer(principal(1), created_at, &transfer),
+            Err(TransferError::TemporarilyUnavailable)
+        ));
+
+        finish_transfer(principal(1), created_at, &transfer, Some(7));
+        assert!(matches!(
+            begin_transfer(principal(1), created_at, &transfer),
+            Err(TransferError::Duplicate { duplicate_of }) if duplicate_of == 7u8
+        ));
+        // Other callers and other arguments are separate transfers
+        begin_transfer(principal(3), created_at, &transfer).unwrap();
+        let other = RecentTransfer { amount: 11, ..transfer.clone() };
+        begin_transfer(principal(1), created_at, &other).unwrap();
+
+        // A failed transfer can be retried
+        finish_transfer(principal(1), created_at, &other, None);
+        begin_transfer(principal(1), created_at, &other).unwrap();
+
+        prune_recent_transfers(created_at + dedup::TX_WINDOW_NANOS + dedup::PERMITTED_DRIFT_NANOS + 1);
+        begin_transfer(principal(1), created_at, &transfer).unwrap();
+    }
+}
@@ -1,13 +1,28 @@
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk::api::time;
-use ic_cdk_macros::{init, query, update};
+use ic_cdk_macros::{init, post_upgrade, query, update};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use realty_common::access::{self, Role, RoleAssignment, RoleStore};
+use realty_common::paging::{self, Page, PageRequest};
+use realty_common::schema::{self, Migration, Versioned};
+use realty_common::{versioned_storable, Error, Result};
 use serde::Serialize;
 use std::cell::RefCell;
+use std::thread::LocalKey;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
+type SchemaVersionCell = StableCell<u32, Memory>;
 type UserStore = StableBTreeMap<Principal, User, Memory>;
+type LookupIndex = StableBTreeMap<String, Principal, Memory>;
+type LookupIndexKey = &'static LocalKey<RefCell<LookupIndex>>;
+
+/// Bump when a stored record changes shape and add the matching migration.
+const SCHEMA_VERSION: u32 = 2;
+
+/// The KYC statuses a user can have. Other canisters only let "verified"
+/// users hold and move tokens.
+const KYC_STATUSES: [&str; 3] = ["pending", "verified", "rejected"];
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct User {
@@ -22,6 +37,12 @@ pub struct User {
     pub portfolio_value: u64, // in USD cents
 }
 
+impl Versioned for User {
+    const VERSION: u8 = 1;
+}
+
+versioned_storable!(User);
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct CreateUserRequest {
     pub email: String,
@@ -42,6 +63,11 @@ pub struct UpdatePortfolioRequest {
     pub portfolio_value: u64,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub roles: Vec<RoleAssignment>,
+}
+
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
         MemoryManager::init(DefaultMemoryImpl::default())
@@ -52,15 +78,177 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0)))
         )
     );
+
+    static ROLES: RefCell<RoleStore<Memory>> = RefCell::new(
+        RoleStore::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+        )
+    );
+
+    static STORED_SCHEMA_VERSION: RefCell<SchemaVersionCell> = RefCell::new(
+        SchemaVersionCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))),
+            0,
+        ).expect("failed to initialize schema version")
+    );
+
+    static EMAIL_INDEX: RefCell<LookupIndex> = RefCell::new(
+        LookupIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+        )
+    );
+
+    static WALLET_INDEX: RefCell<LookupIndex> = RefCell::new(
+        LookupIndex::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
+        )
+    );
 }
 
 #[init]
-fn init() {
-    // Initialize user storage
+fn init(args: InitArgs) {
+    set_schema_version(SCHEMA_VERSION);
+
+    ROLES.with(|roles| access::assign_roles(&mut roles.borrow_mut(), args.roles));
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "re-encode users in the versioned record format",
+        run: reencode_records,
+    },
+    Migration {
+        version: 2,
+        description: "build email and wallet indexes",
+        run: rebuild_indexes,
+    },
+];
+
+#[post_upgrade]
+fn post_upgrade() {
+    let stored = STORED_SCHEMA_VERSION.with(|v| *v.borrow().get());
+    let migrated = schema::run_migrations(stored, MIGRATIONS);
+    assert_eq!(migrated, SCHEMA_VERSION, "missing migration to schema v{}", SCHEMA_VERSION);
+    set_schema_version(migrated);
+}
+
+fn set_schema_version(version: u32) {
+    STORED_SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(version)
+            .expect("failed to store schema version");
+    });
+}
+
+/// Rewrites every record so that blobs stored before versioning existed are
+/// persisted in the current format.
+fn reencode_records() {
+    USER_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let entries: Vec<_> = storage.iter().collect();
+        for (key, record) in entries {
+            storage.insert(key, record);
+        }
+    });
+}
+
+/// Indexes every stored user. Where existing data has duplicates, the first
+/// user by principal keeps the entry.
+fn rebuild_indexes() {
+    USER_STORAGE.with(|storage| {
+        for (principal, user) in storage.borrow().iter() {
+            if let Some(key) = email_key(&user.email) {
+                EMAIL_INDEX.with(|index| {
+                    let mut index = index.borrow_mut();
+                    if !index.contains_key(&key) {
+                        index.insert(key, principal);
+                    }
+                });
+            }
+            if let Some(key) = wallet_key(&user.wallet_address) {
+                WALLET_INDEX.with(|index| {
+                    let mut index = index.borrow_mut();
+                    if !index.contains_key(&key) {
+                        index.insert(key, principal);
+                    }
+                });
+            }
+        }
+    });
+}
+
+#[query]
+fn get_schema_version() -> u32 {
+    STORED_SCHEMA_VERSION.with(|v| *v.borrow().get())
+}
+
+fn require_role(role: Role) -> Result<()> {
+    ROLES.with(|roles| access::require_role(&roles.borrow(), role))
 }
 
 #[update]
-fn create_user(req: CreateUserRequest) -> Result<User, String> {
+fn grant_role(assignment: RoleAssignment) -> Result<()> {
+    ROLES.with(|roles| access::grant_role(&mut roles.borrow_mut(), assignment))
+}
+
+#[update]
+fn revoke_role(assignment: RoleAssignment) -> Result<()> {
+    ROLES.with(|roles| access::revoke_role(&mut roles.borrow_mut(), assignment))
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    ROLES.with(|roles| access::get_roles(&roles.borrow(), &principal))
+}
+
+/// Emails are matched case-insensitively; empty values are not indexed.
+fn email_key(email: &str) -> Option<String> {
+    let key = email.trim().to_lowercase();
+    (!key.is_empty()).then_some(key)
+}
+
+fn wallet_key(wallet_address: &str) -> Option<String> {
+    let key = wallet_address.trim();
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+/// Fails if `key` is already indexed to a principal other than `owner`.
+fn ensure_unclaimed(
+    index: LookupIndexKey,
+    key: &Option<String>,
+    owner: Principal,
+    entity: &str,
+) -> Result<()> {
+    let claimed_by = key.as_ref().and_then(|key| index.with(|index| index.borrow().get(key)));
+    match claimed_by {
+        Some(principal) if principal != owner => Err(Error::already_exists(entity)),
+        _ => Ok(()),
+    }
+}
+
+/// Moves `owner`'s entry in `index` from `old` to `new`.
+fn reindex(index: LookupIndexKey, old: Option<String>, new: Option<String>, owner: Principal) {
+    index.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(old) = old {
+            if index.get(&old) == Some(owner) {
+                index.remove(&old);
+            }
+        }
+        if let Some(new) = new {
+            index.insert(new, owner);
+        }
+    });
+}
+
+fn lookup(index: LookupIndexKey, key: Option<String>) -> Option<User> {
+    let principal = index.with(|index| index.borrow().get(&key?))?;
+    USER_STORAGE.with(|storage| storage.borrow().get(&principal))
+}
+
+#[update]
+fn create_user(req: CreateUserRequest) -> Result<User> {
     let caller = ic_cdk::caller();
     
     // Check if user already exists
@@ -69,9 +257,17 @@ fn create_user(req: CreateUserRequest) -> Result<User, String> {
     });
 
     if existing_user.is_some() {
-        return Err("User already exists".to_string());
+        return Err(Error::already_exists("user"));
     }
 
+    let email = email_key(&req.email);
+    if email.is_none() {
+        return Err(Error::invalid_input("email", "must not be empty"));
+    }
+    let wallet = wallet_key(&req.wallet_address);
+    ensure_unclaimed(&EMAIL_INDEX, &email, caller, "email")?;
+    ensure_unclaimed(&WALLET_INDEX, &wallet, caller, "wallet_address")?;
+
     let user = User {
         principal: caller,
         email: req.email,
@@ -87,6 +283,8 @@ fn create_user(req: CreateUserRequest) -> Result<User, String> {
     USER_STORAGE.with(|storage| {
         storage.borrow_mut().insert(caller, user.clone());
     });
+    reindex(&EMAIL_INDEX, None, email, caller);
+    reindex(&WALLET_INDEX, None, wallet, caller);
 
     Ok(user)
 }
@@ -105,30 +303,26 @@ fn get_current_user() -> Option<User> {
 }
 
 #[query]
-fn get_all_users() -> Vec<User> {
+fn get_all_users(page: PageRequest<Principal>) -> Page<User, Principal> {
     USER_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .map(|(_, user)| user)
-            .collect()
+        paging::paginate(&storage.borrow(), &page)
     })
 }
 
 #[query]
-fn get_verified_users() -> Vec<User> {
+fn get_verified_users(page: PageRequest<Principal>) -> Page<User, Principal> {
     USER_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .filter(|(_, user)| user.kyc_status == "verified" && user.is_active)
-            .map(|(_, user)| user)
-            .collect()
+        paging::paginate_filtered(&storage.borrow(), &page, |user| {
+            user.kyc_status == "verified" && user.is_active
+        })
     })
 }
 
 #[update]
-fn update_kyc_status(req: UpdateKycStatusRequest) -> Result<User, String> {
+fn update_kyc_status(req: UpdateKycStatusRequest) -> Result<User> {
+    require_role(Role::KycOfficer)?;
+    validate_kyc_status(&req.new_status)?;
+
     USER_STORAGE.with(|storage| {
         let mut storage = storage.borrow_mut();
         
@@ -137,31 +331,51 @@ fn update_kyc_status(req: UpdateKycStatusRequest) -> Result<User, String> {
             storage.insert(req.user_principal, user.clone());
             Ok(user)
         } else {
-            Err("User not found".to_string())
+            Err(Error::not_found("user"))
         }
     })
 }
 
+fn validate_kyc_status(status: &str) -> Result<()> {
+    if KYC_STATUSES.contains(&status) {
+        Ok(())
+    } else {
+        Err(Error::invalid_input("kyc_status", &format!("must be one of {}", KYC_STATUSES.join(", "))))
+    }
+}
+
 #[update]
-fn update_user_profile(name: String, email: String) -> Result<User, String> {
+fn update_user_profile(name: String, email: String) -> Result<User> {
     let caller = ic_cdk::caller();
-    
-    USER_STORAGE.with(|storage| {
+
+    let new_key = email_key(&email);
+    if new_key.is_none() {
+        return Err(Error::invalid_input("email", "must not be empty"));
+    }
+    ensure_unclaimed(&EMAIL_INDEX, &new_key, caller, "email")?;
+
+    let (user, old_key) = USER_STORAGE.with(|storage| {
         let mut storage = storage.borrow_mut();
         
         if let Some(mut user) = storage.get(&caller) {
+            let old_key = email_key(&user.email);
             user.name = name;
             user.email = email;
             storage.insert(caller, user.clone());
-            Ok(user)
+            Ok((user, old_key))
         } else {
-            Err("User not found".to_string())
+            Err(Error::not_found("user"))
         }
-    })
+    })?;
+    reindex(&EMAIL_INDEX, old_key, new_key, caller);
+
+    Ok(user)
 }
 
 #[update]
-fn update_portfolio_value(req: UpdatePortfolioRequest) -> Result<User, String> {
+fn update_portfolio_value(req: UpdatePortfolioRequest) -> Result<User> {
+    require_role(Role::Treasury)?;
+
     USER_STORAGE.with(|storage| {
         let mut storage = storage.borrow_mut();
         
@@ -171,13 +385,15 @@ fn update_portfolio_value(req: UpdatePortfolioRequest) -> Result<User, String> {
             storage.insert(req.user_principal, user.clone());
             Ok(user)
         } else {
-            Err("User not found".to_string())
+            Err(Error::not_found("user"))
         }
     })
 }
 
 #[update]
-fn deactivate_user(user_principal: Principal) -> Result<User, String> {
+fn deactivate_user(user_principal: Principal) -> Result<User> {
+    require_role(Role::Admin)?;
+
     USER_STORAGE.with(|storage| {
         let mut storage = storage.borrow_mut();
         
@@ -186,31 +402,19 @@ fn deactivate_user(user_principal: Principal) -> Result<User, String> {
             storage.insert(user_principal, user.clone());
             Ok(user)
         } else {
-            Err("User not found".to_string())
+            Err(Error::not_found("user"))
         }
     })
 }
 
 #[query]
 fn get_user_by_email(email: String) -> Option<User> {
-    USER_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .find(|(_, user)| user.email == email)
-            .map(|(_, user)| user)
-    })
+    lookup(&EMAIL_INDEX, email_key(&email))
 }
 
 #[query]
 fn get_user_by_wallet(wallet_address: String) -> Option<User> {
-    USER_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .find(|(_, user)| user.wallet_address == wallet_address)
-            .map(|(_, user)| user)
-    })
+    lookup(&WALLET_INDEX, wallet_key(&wallet_address))
 }
 
 #[query]
@@ -231,4 +435,61 @@ fn get_user_stats() -> (u64, u64, u64) {
 }
 
 // Export candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::Storable;
+
+    /// User as written by the canister before records were versioned.
+    #[derive(CandidType)]
+    struct LegacyUser {
+        principal: Principal,
+        email: String,
+        name: String,
+        kyc_status: String,
+        wallet_address: String,
+        registration_date: u64,
+        is_active: bool,
+        total_investments: u64,
+        portfolio_value: u64,
+    }
+
+    #[test]
+    fn loads_unversioned_user() {
+        let legacy = LegacyUser {
+            principal: Principal::anonymous(),
+            email: "investor@example.com".to_string(),
+            name: "Investor".to_string(),
+            kyc_status: "verified".to_string(),
+            wallet_address: "wallet-1".to_string(),
+            registration_date: 10,
+            is_active: true,
+            total_investments: 5_000,
+            portfolio_value: 5_500,
+        };
+        let bytes = candid::encode_one(&legacy).unwrap();
+
+        let user = User::from_bytes(bytes.into());
+        assert_eq!(user.email, "investor@example.com");
+        assert_eq!(user.kyc_status, "verified");
+        assert_eq!(user.portfolio_value, 5_500);
+
+        let reencoded = user.to_bytes();
+        assert_eq!(reencoded[0], User::VERSION);
+        assert_eq!(User::from_bytes(reencoded).wallet_address, "wallet-1");
+    }
+
+    #[test]
+    fn accepts_only_known_kyc_statuses() {
+        for status in KYC_STATUSES {
+            assert!(validate_kyc_status(status).is_ok());
+        }
+        assert!(matches!(
+            validate_kyc_status("Verified"),
+            Err(Error::InvalidInput { field, .. }) if field == "kyc_status"
+        ));
+        assert!(validate_kyc_status("").is_err());
+    }
+}